@@ -62,7 +62,7 @@ fn main() {
 
     let bad_payload: &[u8; 9] = &[0x87, 0xA3, 0x69, 0x6E, 0x74, 0x01, 0xA5, 0x66, 0x6C];
     working_tether_agent
-        .publish(&output, Some(bad_payload))
+        .publish(&output, bad_payload)
         .expect("This will produce an error when DECODING, but not checked by library");
 
     let bad_topic_input = PlugOptionsBuilder::create_input("something").topic(Some("*/#/house+"));
@@ -106,7 +106,7 @@ fn main() {
             Ok(a) => {
                 count_messages_sent += 1;
                 if let Some(plug) = &output_plug {
-                    a.publish(plug, Some(&[0])).expect("Failed to publish");
+                    a.publish(plug, &[0]).expect("Failed to publish");
                     println!(">>>>>>>> MAIN THREAD LOOP: sent {count_messages_sent} messages");
                 }
             }
@@ -25,8 +25,8 @@ fn main() {
         .password(Some("connected.space"))
         .build()
         .expect("Failed to initialise and connect");
-    let (role, id, _) = tether_agent.description();
-    info!("Created agent OK: {}, {}", role, id);
+    let (identity, _) = tether_agent.description();
+    info!("Created agent OK: {}", identity);
 
     let empty_message_output = PlugOptionsBuilder::create_output("nothing")
         .build(&tether_agent)
@@ -40,12 +40,12 @@ fn main() {
 
     for i in 1..=10 {
         info!("#{i}: Sending empty message...");
-        tether_agent.publish(&empty_message_output, None).unwrap();
+        tether_agent.publish_empty(&empty_message_output).unwrap();
 
         let bool = i % 2 == 0;
         info!("#{i}: Sending boolean message...");
         tether_agent
-            .publish(&boolean_message_output, Some(&[bool.into()]))
+            .publish(&boolean_message_output, &[bool.into()])
             .unwrap();
 
         let custom_message = CustomStruct {
@@ -0,0 +1,50 @@
+use env_logger::{Builder, Env};
+use log::info;
+use tether_agent::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+/// Connects from within an async application, without blocking the runtime.
+/// Run with `cargo run --example connect_async --features async`
+#[tokio::main]
+async fn main() {
+    println!("Rust Tether Agent async connect example");
+
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    builder.init();
+
+    let mut tether_agent = TetherAgentOptionsBuilder::new("RustDemo")
+        .auto_connect(false)
+        .build()
+        .expect("failed to create Tether Agent");
+
+    // Input Plugs can be built before connecting; they subscribe once connected
+    let input = PlugOptionsBuilder::create_input("greetings")
+        .build(&mut tether_agent)
+        .expect("failed to create input");
+    let output = PlugOptionsBuilder::create_output("greetings")
+        .build(&mut tether_agent)
+        .expect("failed to create output");
+
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        tether_agent.connect_async(),
+    )
+    .await
+    .expect("timed out connecting")
+    .expect("failed to connect");
+    info!("Connected: {}", tether_agent.is_connected());
+
+    tether_agent
+        .encode_and_publish(&output, "hello from async")
+        .expect("failed to publish");
+
+    loop {
+        if let Some((topic, payload)) = tether_agent.check_messages() {
+            if input.matches(&topic) {
+                let greeting: String = rmp_serde::from_slice(&payload).expect("failed to decode");
+                info!("Received \"{}\"", greeting);
+                break;
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+}
@@ -30,9 +30,9 @@ fn main() {
         .build(&mut tether_agent);
 
     println!("Agent looks like this: {:?}", tether_agent.description());
-    let (role, id, _) = tether_agent.description();
-    assert_eq!(role, "example");
-    assert_eq!(id, "any"); // because we set None
+    let (identity, _) = tether_agent.description();
+    assert_eq!(identity.role(), "example");
+    assert_eq!(identity.id(), "any"); // because we set None
 
     if let PlugDefinition::OutputPlug(p) = &output_plug {
         println!("output plug: {:?}", p);
@@ -45,7 +45,7 @@ fn main() {
     let payload =
         rmp_serde::to_vec::<String>(&String::from("boo")).expect("failed to serialise payload");
     tether_agent
-        .publish(&output_plug, Some(&payload))
+        .publish(&output_plug, &payload)
         .expect("failed to publish");
 
     std::thread::sleep(Duration::from_millis(4000));
@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tether_agent::{
+    decode_batch,
+    three_part_topic::{TetherOrCustomTopic, ThreePartTopic},
+    Message,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SensorReading {
+    index: usize,
+    values: Vec<f32>,
+    label: String,
+}
+
+const BATCH_SIZE: usize = 1000;
+const ROUNDS: usize = 100;
+
+/// Compares `decode_batch` with decoding each message individually via a dynamic
+/// value and JSON string, which is how the `tether receive` utility does it.
+/// No broker is needed; the messages are generated locally.
+fn main() {
+    println!("Rust Tether Agent batch decode benchmark");
+
+    let messages: Vec<Message> = (0..BATCH_SIZE)
+        .map(|index| {
+            let reading = SensorReading {
+                index,
+                values: vec![0.1, 0.2, 0.3, 0.4],
+                label: "hello".into(),
+            };
+            (
+                TetherOrCustomTopic::Tether(
+                    ThreePartTopic::new("sensor", "any", "readings").expect("valid topic"),
+                ),
+                rmp_serde::to_vec_named(&reading).expect("failed to encode"),
+            )
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut per_message_count = 0;
+    for _ in 0..ROUNDS {
+        for (_topic, payload) in &messages {
+            let value: serde_json::Value =
+                rmp_serde::from_slice(payload).expect("failed to decode");
+            let json = serde_json::to_string(&value).expect("failed to stringify JSON");
+            let reading: SensorReading = serde_json::from_str(&json).expect("failed to parse");
+            per_message_count += reading.values.len();
+        }
+    }
+    let per_message = start.elapsed();
+
+    let start = Instant::now();
+    let mut batch_count = 0;
+    for _ in 0..ROUNDS {
+        for reading in decode_batch::<SensorReading>(&messages)
+            .into_iter()
+            .flatten()
+        {
+            batch_count += reading.values.len();
+        }
+    }
+    let batch = start.elapsed();
+
+    assert_eq!(per_message_count, batch_count);
+
+    println!(
+        "{} rounds of {} messages: per-message (via JSON) {:?}, decode_batch {:?} ({:.1}x)",
+        ROUNDS,
+        BATCH_SIZE,
+        per_message,
+        batch,
+        per_message.as_secs_f64() / batch.as_secs_f64()
+    );
+}
@@ -22,8 +22,8 @@ fn main() {
     let mut tether_agent = TetherAgentOptionsBuilder::new("RustDemo")
         .build()
         .expect("failed to connect Tether");
-    let (role, id, _) = tether_agent.description();
-    info!("Created agent OK: {}, {}", role, id);
+    let (identity, _) = tether_agent.description();
+    info!("Created agent OK: {}", identity);
 
     let empty_message_output = PlugOptionsBuilder::create_output("nothing")
         .build(&mut tether_agent)
@@ -46,12 +46,12 @@ fn main() {
 
     for i in 1..=10 {
         info!("#{i}: Sending empty message...");
-        tether_agent.publish(&empty_message_output, None).unwrap();
+        tether_agent.publish_empty(&empty_message_output).unwrap();
 
         let bool = i % 2 == 0;
         info!("#{i}: Sending boolean message...");
         tether_agent
-            .publish(&boolean_message_output, Some(&[bool.into()]))
+            .publish(&boolean_message_output, &[bool.into()])
             .unwrap();
 
         info!("#{i}: Sending custom struct message...");
@@ -64,8 +64,8 @@ fn main() {
             .unwrap();
 
         info!("#{i}: Sending grouped messages...");
-        tether_agent.publish(&grouped_output_1, None).unwrap();
-        tether_agent.publish(&grouped_output_2, None).unwrap();
+        tether_agent.publish_empty(&grouped_output_1).unwrap();
+        tether_agent.publish_empty(&grouped_output_2).unwrap();
 
         thread::sleep(Duration::from_millis(1000))
     }
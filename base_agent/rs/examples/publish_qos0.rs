@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+use env_logger::{Builder, Env};
+use log::info;
+use tether_agent::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+const MESSAGE_COUNT: u32 = 100_000;
+
+/// Measures the average time spent in each `publish` call for a high-rate
+/// stream of QoS 0 telemetry, compared with the same stream at QoS 1.
+fn main() {
+    println!("Rust Tether Agent QoS 0 publish latency example");
+
+    let mut builder = Builder::from_env(Env::default().default_filter_or("info"));
+    builder.init();
+
+    let mut tether_agent = TetherAgentOptionsBuilder::new("RustDemo")
+        .build()
+        .expect("failed to connect Tether");
+
+    for qos in [0, 1] {
+        let output = PlugOptionsBuilder::create_output("telemetry")
+            .qos(Some(qos))
+            .build(&mut tether_agent)
+            .expect("failed to create output");
+
+        let mut slowest = Duration::ZERO;
+        let mut failed = 0;
+        let start = Instant::now();
+        for i in 0..MESSAGE_COUNT {
+            let before = Instant::now();
+            if tether_agent.publish(&output, &i.to_le_bytes()).is_err() {
+                failed += 1;
+            }
+            slowest = slowest.max(before.elapsed());
+        }
+        let elapsed = start.elapsed();
+
+        info!(
+            "QoS {}: {} messages, average {:?} per publish, slowest {:?}, {} not sent",
+            qos,
+            MESSAGE_COUNT,
+            elapsed / MESSAGE_COUNT,
+            slowest,
+            failed
+        );
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+/// Wakes anything waiting for a message to arrive, on any of the queues of an Agent (its
+/// own, and those of any additional brokers, which share it), without holding any of the
+/// queues meanwhile
+#[derive(Default)]
+pub(crate) struct Arrivals {
+    count: Mutex<u64>,
+    arrived: Condvar,
+}
+
+impl Arrivals {
+    /// How many messages have arrived so far; read this before checking the queues, then
+    /// pass it to `wait_after` if they were empty
+    pub(crate) fn count(&self) -> u64 {
+        *self.count.lock().expect("failed to lock mutex")
+    }
+
+    /// Called once a message has been queued
+    pub(crate) fn notify(&self) {
+        *self.count.lock().expect("failed to lock mutex") += 1;
+        self.arrived.notify_all();
+    }
+
+    /// Wait until more than `seen` messages have arrived, or until the deadline; returns
+    /// false if the deadline passed first
+    pub(crate) fn wait_after(&self, seen: u64, deadline: Instant) -> bool {
+        let mut count = self.count.lock().expect("failed to lock mutex");
+        while *count == seen {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = self
+                .arrived
+                .wait_timeout(count, deadline - now)
+                .expect("failed to lock mutex")
+                .0;
+        }
+        true
+    }
+}
@@ -0,0 +1,72 @@
+use rumqttc::{ConnectReturnCode, ConnectionError};
+use serde::{Deserialize, Serialize};
+
+/// Whether to try connecting again in the other mode (with or without a username and
+/// password) if the broker refuses the connection because of the credentials, e.g. for
+/// brokers which accept anonymous connections but grant more permissions to clients which
+/// log in. Only a refusal with the return code "bad username or password" or "not
+/// authorized" triggers the fallback, and only once; after that, reconnecting uses
+/// whichever mode was tried last.
+///
+/// The default is to only ever connect with the credentials given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthFallback {
+    #[default]
+    None,
+    /// Connect with the username and password first, then anonymously if refused
+    ToAnonymous,
+    /// Connect anonymously first, then with the username and password if refused
+    ToAuthenticated,
+}
+
+impl AuthFallback {
+    /// Whether the first attempt is made without any username and password
+    pub fn starts_anonymous(&self) -> bool {
+        *self == AuthFallback::ToAuthenticated
+    }
+}
+
+/// Whether the broker refused the connection because of the credentials (or lack of them)
+pub(crate) fn is_auth_refusal(e: &ConnectionError) -> bool {
+    matches!(
+        e,
+        ConnectionError::ConnectionRefused(
+            ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized
+        )
+    )
+}
+
+/// How the connection was made, for log lines
+pub(crate) fn auth_mode(anonymous: bool) -> &'static str {
+    if anonymous {
+        "anonymously"
+    } else {
+        "with username and password"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{ConnectReturnCode, ConnectionError};
+
+    use super::{is_auth_refusal, AuthFallback};
+
+    #[test]
+    fn auth_refusals() {
+        for code in [
+            ConnectReturnCode::BadUserNamePassword,
+            ConnectReturnCode::NotAuthorized,
+        ] {
+            assert!(is_auth_refusal(&ConnectionError::ConnectionRefused(code)));
+        }
+        assert!(!is_auth_refusal(&ConnectionError::ConnectionRefused(
+            ConnectReturnCode::ServiceUnavailable
+        )));
+        assert!(!is_auth_refusal(&ConnectionError::NetworkTimeout));
+
+        assert!(AuthFallback::ToAuthenticated.starts_anonymous());
+        assert!(!AuthFallback::ToAnonymous.starts_anonymous());
+        assert!(!AuthFallback::default().starts_anonymous());
+    }
+}
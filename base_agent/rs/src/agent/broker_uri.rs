@@ -0,0 +1,129 @@
+use super::TetherError;
+
+/// Check the host given for the broker, returning it in the form the MQTT client expects
+/// (a bare hostname or IP address). A scheme included by mistake (e.g. `mqtt://localhost`)
+/// is removed as long as it agrees with the protocol, as are trailing slashes; anything
+/// else that does not belong in a host, such as a port or path, is rejected.
+pub fn validate_host(host: &str, protocol: &str) -> Result<String, TetherError> {
+    let invalid = |reason: &str| TetherError::InvalidBrokerUri {
+        host: String::from(host),
+        reason: String::from(reason),
+    };
+
+    let mut remaining = host.trim();
+    if let Some((scheme, rest)) = remaining.split_once("://") {
+        let scheme_protocol = match scheme.to_ascii_lowercase().as_str() {
+            "mqtt" | "tcp" => "mqtt",
+            "mqtts" | "ssl" | "tls" => "mqtts",
+            "ws" => "ws",
+            "wss" => "wss",
+            _ => return Err(invalid(&format!("unknown scheme \"{}\"", scheme))),
+        };
+        if scheme_protocol != protocol {
+            return Err(invalid(&format!(
+                "scheme \"{}\" does not match protocol \"{}\"; set the protocol separately",
+                scheme, protocol
+            )));
+        }
+        remaining = rest;
+    }
+    let remaining = remaining.trim_end_matches('/');
+
+    if remaining.is_empty() {
+        return Err(invalid("host is empty"));
+    }
+    if remaining.contains('/') {
+        return Err(invalid(
+            "host must not include a path; use the base path option instead",
+        ));
+    }
+    if let Some(c) = remaining
+        .chars()
+        .find(|c| c.is_whitespace() || matches!(c, '@' | '?' | '#' | '\\'))
+    {
+        return Err(invalid(&format!("host contains invalid character {:?}", c)));
+    }
+
+    let has_port = if let Some(bracketed) = remaining.strip_prefix('[') {
+        // IPv6 address, e.g. [::1], possibly followed by a port
+        match bracketed.split_once(']') {
+            Some((_, after)) => !after.is_empty(),
+            None => return Err(invalid("unclosed bracket in IPv6 address")),
+        }
+    } else {
+        // More than one colon means a bare IPv6 address
+        remaining.matches(':').count() == 1
+    };
+    if has_port {
+        return Err(invalid(
+            "host must not include a port; use the port option instead",
+        ));
+    }
+
+    Ok(String::from(remaining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_host;
+    use crate::TetherError;
+
+    #[test]
+    fn plain_hosts() {
+        assert_eq!(validate_host("localhost", "mqtt").unwrap(), "localhost");
+        assert_eq!(validate_host("10.0.0.1", "mqtt").unwrap(), "10.0.0.1");
+        assert_eq!(
+            validate_host(" broker.local ", "mqtts").unwrap(),
+            "broker.local"
+        );
+        assert_eq!(validate_host("::1", "mqtt").unwrap(), "::1");
+        assert_eq!(validate_host("[::1]", "mqtt").unwrap(), "[::1]");
+    }
+
+    #[test]
+    fn embedded_schemes() {
+        assert_eq!(
+            validate_host("mqtt://localhost", "mqtt").unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            validate_host("tcp://localhost", "mqtt").unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            validate_host("wss://example.com", "wss").unwrap(),
+            "example.com"
+        );
+        assert!(validate_host("wss://example.com", "mqtt").is_err());
+        assert!(validate_host("http://example.com", "mqtt").is_err());
+        assert!(matches!(
+            validate_host("tcp://tcp://localhost", "mqtt"),
+            Err(TetherError::InvalidBrokerUri { .. })
+        ));
+    }
+
+    #[test]
+    fn trailing_slashes_and_paths() {
+        assert_eq!(validate_host("localhost/", "mqtt").unwrap(), "localhost");
+        assert_eq!(
+            validate_host("mqtt://localhost//", "mqtt").unwrap(),
+            "localhost"
+        );
+        assert!(validate_host("localhost/mqtt", "ws").is_err());
+        assert!(validate_host("/", "mqtt").is_err());
+        assert!(validate_host("", "mqtt").is_err());
+    }
+
+    #[test]
+    fn port_in_host() {
+        assert!(validate_host("localhost:1883", "mqtt").is_err());
+        assert!(validate_host("mqtt://localhost:1883/", "mqtt").is_err());
+        assert!(validate_host("[::1]:1883", "mqtt").is_err());
+    }
+
+    #[test]
+    fn invalid_characters() {
+        assert!(validate_host("user@localhost", "mqtt").is_err());
+        assert!(validate_host("local host", "mqtt").is_err());
+    }
+}
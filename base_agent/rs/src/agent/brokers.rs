@@ -0,0 +1,261 @@
+use anyhow::anyhow;
+
+use super::TetherAgentOptionsBuilder;
+
+/// The tag of the broker an Agent connects to with its own options (host, port, etc.), as
+/// opposed to any `AdditionalBroker`
+pub const PRIMARY_BROKER_TAG: &str = "primary";
+
+/// Another broker for an Agent to be connected to at the same time as its own, e.g. a
+/// cloud broker as well as the local one; see
+/// `TetherAgentOptionsBuilder::additional_brokers`.
+///
+/// Each additional broker is identified by its tag, so that Output Plugs can publish to
+/// only some of the brokers (see `PlugOptionsBuilder::brokers`). Any connection option
+/// not given here (protocol, port, username, password) has its default value, not the
+/// one given for the primary broker, and neither are the proxy, TLS server name, network
+/// interface or presence (see `options`); every other option (identity, TLS certificates,
+/// reconnecting, etc.) is the same for all brokers.
+#[derive(Clone, PartialEq)]
+pub struct AdditionalBroker {
+    tag: String,
+    protocol: Option<String>,
+    host: String,
+    port: Option<u16>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl std::fmt::Debug for AdditionalBroker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdditionalBroker")
+            .field("tag", &self.tag)
+            .field("protocol", &self.protocol)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("has_password", &self.password.is_some())
+            .finish()
+    }
+}
+
+impl AdditionalBroker {
+    pub fn new(tag: &str, host: &str) -> AdditionalBroker {
+        AdditionalBroker {
+            tag: String::from(tag),
+            protocol: None,
+            host: String::from(host),
+            port: None,
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Provide Some(value) to override or None to use default
+    pub fn protocol(mut self, protocol: Option<&str>) -> Self {
+        self.protocol = protocol.map(|x| x.into());
+        self
+    }
+
+    pub fn port(mut self, port: Option<u16>) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Provide Some(value) to override or None to use default
+    pub fn username(mut self, username: Option<&str>) -> Self {
+        self.username = username.map(|x| x.into());
+        self
+    }
+
+    /// Provide Some(value) to override or None to use default
+    pub fn password(mut self, password: Option<&str>) -> Self {
+        self.password = password.map(|x| x.into());
+        self
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The options for connecting to this broker: the primary broker's, apart from the
+    /// connection itself. Any MQTT Client ID given gets the tag appended, since the same
+    /// ID cannot be used for two connections to one broker. The manifest and Plug
+    /// descriptions are published by the primary Agent, on every broker. The broker's log
+    /// records are labelled with its tag (after the Agent's own label, if any).
+    ///
+    /// Options which only make sense for the primary broker's network are not carried
+    /// over: the proxy, TLS server name and network interface, and presence (so no last
+    /// will either), which is only announced on the primary broker.
+    pub(crate) fn options(&self, primary: &TetherAgentOptionsBuilder) -> TetherAgentOptionsBuilder {
+        let mut options = primary.clone();
+        options.protocol = self.protocol.clone();
+        options.host = Some(self.host.clone());
+        options.port = self.port;
+        options.username = self.username.clone();
+        options.password = self.password.clone();
+        options.mqtt_client_id = primary
+            .mqtt_client_id
+            .as_ref()
+            .filter(|id| !id.is_empty())
+            .map(|id| format!("{}-{}", id, self.tag));
+        options.label = Some(match &primary.label {
+            Some(label) => format!("{}-{}", label, self.tag),
+            None => self.tag.clone(),
+        });
+        options.proxy = None;
+        options.server_name = None;
+        options.bind_device = None;
+        options.announce_presence = false;
+        options.additional_brokers = None;
+        options.announce_manifest = false;
+        options
+    }
+}
+
+/// Whether one message was published on each of the brokers it was meant for, by tag, so
+/// that a failure on one broker neither hides nor undoes the message reaching the others
+pub(crate) struct BrokerResults(Vec<(String, anyhow::Result<()>)>);
+
+impl BrokerResults {
+    pub(crate) fn new() -> BrokerResults {
+        BrokerResults(Vec::new())
+    }
+
+    pub(crate) fn push(&mut self, tag: &str, result: anyhow::Result<()>) {
+        self.0.push((String::from(tag), result));
+    }
+
+    /// Whether the message reached at least one broker
+    pub(crate) fn any_published(&self) -> bool {
+        self.0.iter().any(|(_, result)| result.is_ok())
+    }
+
+    /// The tags of the brokers the message reached
+    pub(crate) fn published_tags(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(tag, _)| tag.clone())
+            .collect()
+    }
+
+    /// Ok if the message reached every broker it was meant for; otherwise the first error,
+    /// naming every broker which failed if the message was meant for more than one (the
+    /// error can still be downcast, e.g. to `TetherError::NotConnected`)
+    pub(crate) fn into_result(self) -> anyhow::Result<()> {
+        let count = self.0.len();
+        let failed: Vec<String> = self
+            .0
+            .iter()
+            .filter(|(_, result)| result.is_err())
+            .map(|(tag, _)| tag.clone())
+            .collect();
+        let Some(error) = self.0.into_iter().find_map(|(_, result)| result.err()) else {
+            return Ok(());
+        };
+        if count == 1 {
+            return Err(error);
+        }
+        Err(error.context(format!(
+            "Failed to publish on broker(s) {}",
+            failed.join(", ")
+        )))
+    }
+}
+
+/// Whether the broker with this tag is one of those selected; None selects every broker
+pub(crate) fn is_selected(brokers: Option<&[String]>, tag: &str) -> bool {
+    brokers.is_none_or(|brokers| brokers.iter().any(|b| b == tag))
+}
+
+/// Every broker needs its own tag, which cannot be the primary broker's
+pub(crate) fn validate_broker_tags(brokers: &[AdditionalBroker]) -> anyhow::Result<()> {
+    for (i, broker) in brokers.iter().enumerate() {
+        if broker.tag.is_empty() || broker.tag == PRIMARY_BROKER_TAG {
+            return Err(anyhow!(
+                "Invalid tag \"{}\" for additional broker {}",
+                broker.tag,
+                broker.host
+            ));
+        }
+        if brokers[..i].iter().any(|b| b.tag == broker.tag) {
+            return Err(anyhow!(
+                "More than one additional broker has the tag \"{}\"",
+                broker.tag
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use crate::{TetherAgentOptionsBuilder, TetherError};
+
+    use super::{
+        is_selected, validate_broker_tags, AdditionalBroker, BrokerResults, PRIMARY_BROKER_TAG,
+    };
+
+    #[test]
+    fn broker_tags() {
+        let cloud = AdditionalBroker::new("cloud", "broker.example.com")
+            .port(Some(8883))
+            .password(Some("secret"));
+        assert!(!format!("{:?}", cloud).contains("secret"));
+
+        assert!(validate_broker_tags(std::slice::from_ref(&cloud)).is_ok());
+        assert!(validate_broker_tags(&[cloud.clone(), cloud.clone()]).is_err());
+        assert!(validate_broker_tags(&[AdditionalBroker::new(PRIMARY_BROKER_TAG, "x")]).is_err());
+        assert!(validate_broker_tags(&[AdditionalBroker::new("", "x")]).is_err());
+
+        assert!(is_selected(None, "cloud"));
+        let selected = [String::from("cloud")];
+        assert!(is_selected(Some(&selected), "cloud"));
+        assert!(!is_selected(Some(&selected), PRIMARY_BROKER_TAG));
+    }
+
+    #[test]
+    fn network_options_not_inherited() {
+        let primary = TetherAgentOptionsBuilder::new("tester")
+            .proxy(Some("http://proxy.local:3128"))
+            .server_name(Some("broker.local"))
+            .bind_device(Some("eth1"))
+            .announce_presence(true);
+        let options = AdditionalBroker::new("cloud", "broker.example.com").options(&primary);
+        assert_eq!(options.proxy, None);
+        assert_eq!(options.server_name, None);
+        assert_eq!(options.bind_device, None);
+        assert!(!options.announce_presence);
+    }
+
+    #[test]
+    fn results_per_broker() {
+        let mut results = BrokerResults::new();
+        results.push(PRIMARY_BROKER_TAG, Ok(()));
+        results.push("cloud", Err(TetherError::NotConnected.into()));
+        results.push("backup", Err(anyhow!("refused")));
+        assert!(results.any_published());
+        assert_eq!(results.published_tags(), vec![PRIMARY_BROKER_TAG]);
+        let error = results.into_result().unwrap_err();
+        assert!(error.to_string().contains("cloud, backup"));
+        assert!(matches!(
+            error.downcast_ref::<TetherError>(),
+            Some(TetherError::NotConnected)
+        ));
+
+        let mut results = BrokerResults::new();
+        results.push(PRIMARY_BROKER_TAG, Err(TetherError::NotConnected.into()));
+        assert!(!results.any_published());
+        assert_eq!(
+            results.into_result().unwrap_err().to_string(),
+            TetherError::NotConnected.to_string()
+        );
+    }
+}
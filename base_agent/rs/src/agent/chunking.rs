@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use super::{HEADER_KIND_CHUNK, HEADER_MARKER};
+
+const HEADER_LENGTH: usize = 18;
+
+/// The largest complete payload a `ChunkReassembler` accepts, unless set otherwise
+pub const DEFAULT_MAX_TRANSFER_SIZE: u32 = 64 * 1024 * 1024;
+
+/// The header of one chunk of a payload split up by `split_into_chunks` (or published with
+/// `TetherAgent::publish_chunked`). On the wire, it is the marker and kind bytes, then
+/// each of these as a big-endian u32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    /// Random, the same for every chunk of one payload
+    pub transfer_id: u32,
+    /// Size of the complete payload, in bytes
+    pub total_size: u32,
+    /// Counting from zero
+    pub index: u32,
+    pub count: u32,
+}
+
+/// Why a chunk could not be added to a `ChunkReassembler`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkError {
+    /// The payload does not start with a chunk header
+    NotAChunk,
+    /// The chunk does not fit with the others of the same transfer (or the chunks add up
+    /// to a different size than the one given, or there are more chunks than bytes), so
+    /// the transfer was dropped
+    Inconsistent { transfer_id: u32 },
+    /// The header gives a total size above the reassembler's maximum transfer size, so
+    /// the chunk was ignored
+    TooLarge { transfer_id: u32, total_size: u32 },
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAChunk => write!(f, "payload has no chunk header"),
+            Self::Inconsistent { transfer_id } => {
+                write!(
+                    f,
+                    "inconsistent chunks for transfer {}; dropped",
+                    transfer_id
+                )
+            }
+            Self::TooLarge {
+                transfer_id,
+                total_size,
+            } => write!(
+                f,
+                "transfer {} of {} bytes is larger than the maximum; ignored",
+                transfer_id, total_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+/// A transfer which did not receive all of its chunks in time; see `ChunkReassembler::expire`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompleteTransfer {
+    pub transfer_id: u32,
+    /// The indexes of the chunks which never arrived
+    pub missing: Vec<u32>,
+}
+
+/// Split the data into payloads of (at most) `chunk_size` bytes of data each, plus a
+/// chunk header. Empty data is sent as a single, empty chunk.
+pub fn split_into_chunks(data: &[u8], chunk_size: usize, transfer_id: u32) -> Vec<Vec<u8>> {
+    let chunk_size = chunk_size.max(1);
+    let count = data.len().div_ceil(chunk_size).max(1);
+    (0..count)
+        .map(|index| {
+            let start = index * chunk_size;
+            let chunk = &data[start..(start + chunk_size).min(data.len())];
+            let mut payload = Vec::with_capacity(HEADER_LENGTH + chunk.len());
+            payload.push(HEADER_MARKER);
+            payload.push(HEADER_KIND_CHUNK);
+            for value in [transfer_id, data.len() as u32, index as u32, count as u32] {
+                payload.extend_from_slice(&value.to_be_bytes());
+            }
+            payload.extend_from_slice(chunk);
+            payload
+        })
+        .collect()
+}
+
+/// The header and the data of a chunk, or None if the payload is not a chunk
+pub fn parse_chunk(payload: &[u8]) -> Option<(ChunkHeader, &[u8])> {
+    let [HEADER_MARKER, HEADER_KIND_CHUNK, rest @ ..] = payload else {
+        return None;
+    };
+    if payload.len() < HEADER_LENGTH {
+        return None;
+    }
+    let field = |i: usize| u32::from_be_bytes(rest[i * 4..i * 4 + 4].try_into().unwrap());
+    let header = ChunkHeader {
+        transfer_id: field(0),
+        total_size: field(1),
+        index: field(2),
+        count: field(3),
+    };
+    (header.index < header.count).then_some((header, &payload[HEADER_LENGTH..]))
+}
+
+struct Transfer {
+    total_size: u32,
+    count: u32,
+    /// Only the chunks received so far, so that memory use follows the data which has
+    /// actually arrived, not what the header claims
+    chunks: BTreeMap<u32, Vec<u8>>,
+    received_bytes: usize,
+    last_chunk: Instant,
+}
+
+/// Puts chunked payloads (see `TetherAgent::publish_chunked`) back together: feed it every
+/// chunk as it arrives, in any order, and it returns each complete payload as soon as its
+/// last missing chunk arrives. Duplicate chunks (e.g. redelivered with QoS 1) are ignored.
+///
+/// Transfers which stop receiving chunks for longer than the timeout are dropped by
+/// `expire`, which should be called every so often. Since chunk headers come from the
+/// network, transfers larger than the maximum transfer size (by default
+/// `DEFAULT_MAX_TRANSFER_SIZE`) are refused.
+pub struct ChunkReassembler {
+    timeout: Duration,
+    max_transfer_size: u32,
+    transfers: HashMap<u32, Transfer>,
+}
+
+impl ChunkReassembler {
+    pub fn new(timeout: Duration) -> ChunkReassembler {
+        ChunkReassembler {
+            timeout,
+            max_transfer_size: DEFAULT_MAX_TRANSFER_SIZE,
+            transfers: HashMap::new(),
+        }
+    }
+
+    /// Refuse transfers with a total size above this many bytes
+    pub fn with_max_transfer_size(mut self, max_transfer_size: u32) -> ChunkReassembler {
+        self.max_transfer_size = max_transfer_size;
+        self
+    }
+
+    /// Add one chunk; returns the complete payload if this was the last one missing
+    pub fn add(&mut self, payload: &[u8]) -> Result<Option<Vec<u8>>, ChunkError> {
+        let (header, data) = parse_chunk(payload).ok_or(ChunkError::NotAChunk)?;
+        let inconsistent = ChunkError::Inconsistent {
+            transfer_id: header.transfer_id,
+        };
+        if header.total_size > self.max_transfer_size {
+            return Err(ChunkError::TooLarge {
+                transfer_id: header.transfer_id,
+                total_size: header.total_size,
+            });
+        }
+        // Every chunk carries at least one byte, except the single chunk of empty data
+        if header.count > header.total_size.max(1) {
+            self.transfers.remove(&header.transfer_id);
+            return Err(inconsistent);
+        }
+        let transfer = self
+            .transfers
+            .entry(header.transfer_id)
+            .or_insert_with(|| Transfer {
+                total_size: header.total_size,
+                count: header.count,
+                chunks: BTreeMap::new(),
+                received_bytes: 0,
+                last_chunk: Instant::now(),
+            });
+        if transfer.total_size != header.total_size || transfer.count != header.count {
+            self.transfers.remove(&header.transfer_id);
+            return Err(inconsistent);
+        }
+        transfer.last_chunk = Instant::now();
+        if !transfer.chunks.contains_key(&header.index) {
+            transfer.received_bytes += data.len();
+            if transfer.received_bytes > transfer.total_size as usize {
+                self.transfers.remove(&header.transfer_id);
+                return Err(inconsistent);
+            }
+            transfer.chunks.insert(header.index, data.to_vec());
+        }
+        if transfer.chunks.len() < header.count as usize {
+            return Ok(None);
+        }
+
+        let transfer = self
+            .transfers
+            .remove(&header.transfer_id)
+            .expect("the transfer should exist");
+        // Within the maximum transfer size, and no more than what was actually received
+        let mut complete = Vec::with_capacity(transfer.received_bytes);
+        for chunk in transfer.chunks.into_values() {
+            complete.extend_from_slice(&chunk);
+        }
+        if complete.len() != transfer.total_size as usize {
+            return Err(inconsistent);
+        }
+        Ok(Some(complete))
+    }
+
+    /// Drop every transfer which has not received a chunk within the timeout, returning
+    /// which chunks each was missing
+    pub fn expire(&mut self) -> Vec<IncompleteTransfer> {
+        let mut expired = Vec::new();
+        self.transfers.retain(|transfer_id, transfer| {
+            if transfer.last_chunk.elapsed() <= self.timeout {
+                return true;
+            }
+            expired.push(IncompleteTransfer {
+                transfer_id: *transfer_id,
+                missing: (0..transfer.count)
+                    .filter(|i| !transfer.chunks.contains_key(i))
+                    .collect(),
+            });
+            false
+        });
+        expired
+    }
+
+    /// How many transfers are still waiting for chunks
+    pub fn pending(&self) -> usize {
+        self.transfers.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        parse_chunk, split_into_chunks, ChunkError, ChunkReassembler, DEFAULT_MAX_TRANSFER_SIZE,
+        HEADER_KIND_CHUNK, HEADER_MARKER,
+    };
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let mut chunks = split_into_chunks(&data, 64, 7);
+        assert_eq!(chunks.len(), 16);
+        let (header, chunk) = parse_chunk(&chunks[15]).unwrap();
+        assert_eq!((header.transfer_id, header.total_size), (7, 1000));
+        assert_eq!((header.index, header.count), (15, 16));
+        assert_eq!(chunk.len(), 1000 - 15 * 64);
+
+        chunks.reverse();
+        chunks.swap(3, 9);
+        let duplicate = chunks[0].clone();
+        let mut reassembler = ChunkReassembler::new(Duration::from_secs(5));
+        let last = chunks.pop().unwrap();
+        for chunk in &chunks {
+            assert_eq!(reassembler.add(chunk), Ok(None));
+        }
+        assert_eq!(reassembler.add(&duplicate), Ok(None));
+        assert_eq!(reassembler.add(&last), Ok(Some(data)));
+        assert_eq!(reassembler.pending(), 0);
+
+        let empty = split_into_chunks(&[], 64, 8);
+        assert_eq!(reassembler.add(&empty[0]), Ok(Some(Vec::new())));
+        assert_eq!(reassembler.add(&[1, 2, 3]), Err(ChunkError::NotAChunk));
+    }
+
+    #[test]
+    fn missing_chunk_timeout() {
+        let chunks = split_into_chunks(&[0; 100], 10, 1);
+        let mut reassembler = ChunkReassembler::new(Duration::from_millis(20));
+        for chunk in chunks
+            .iter()
+            .filter(|c| parse_chunk(c).unwrap().0.index != 4)
+        {
+            assert_eq!(reassembler.add(chunk), Ok(None));
+        }
+        assert!(reassembler.expire().is_empty());
+        std::thread::sleep(Duration::from_millis(50));
+        let expired = reassembler.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].transfer_id, 1);
+        assert_eq!(expired[0].missing, vec![4]);
+        assert_eq!(reassembler.pending(), 0);
+
+        // A chunk which does not fit the transfer drops it
+        let other = split_into_chunks(&[0; 50], 10, 1);
+        assert_eq!(reassembler.add(&chunks[0]), Ok(None));
+        assert_eq!(
+            reassembler.add(&other[1]),
+            Err(ChunkError::Inconsistent { transfer_id: 1 })
+        );
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    /// A chunk header as it might be crafted by anyone able to publish on the topic
+    fn crafted_chunk(total_size: u32, count: u32) -> Vec<u8> {
+        let mut payload = vec![HEADER_MARKER, HEADER_KIND_CHUNK];
+        for value in [9, total_size, 0, count] {
+            payload.extend_from_slice(&u32::to_be_bytes(value));
+        }
+        payload.push(0);
+        payload
+    }
+
+    #[test]
+    fn untrusted_headers_bounded() {
+        let mut reassembler =
+            ChunkReassembler::new(Duration::from_secs(5)).with_max_transfer_size(1000);
+        assert_eq!(
+            reassembler.add(&crafted_chunk(u32::MAX, u32::MAX)),
+            Err(ChunkError::TooLarge {
+                transfer_id: 9,
+                total_size: u32::MAX
+            })
+        );
+        assert_eq!(
+            reassembler.add(&crafted_chunk(1000, 1001)),
+            Err(ChunkError::Inconsistent { transfer_id: 9 })
+        );
+        assert_eq!(reassembler.pending(), 0);
+
+        // More data than the header's total size drops the transfer
+        let mut reassembler = ChunkReassembler::new(Duration::from_secs(5));
+        assert_eq!(reassembler.add(&crafted_chunk(2, 2)), Ok(None));
+        let mut oversized = crafted_chunk(2, 2);
+        oversized[13] = 1;
+        oversized.extend_from_slice(&[0; 10]);
+        assert_eq!(
+            reassembler.add(&oversized),
+            Err(ChunkError::Inconsistent { transfer_id: 9 })
+        );
+        assert_eq!(reassembler.pending(), 0);
+
+        // Within the default maximum, nothing is allocated for chunks not yet received
+        assert_eq!(
+            reassembler.add(&crafted_chunk(DEFAULT_MAX_TRANSFER_SIZE, 1 << 26)),
+            Ok(None)
+        );
+        assert_eq!(reassembler.pending(), 1);
+    }
+}
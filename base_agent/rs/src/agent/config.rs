@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    AuthFallback, DuplicateClientIdPolicy, ErrorPolicy, ReconnectPolicy, TetherAgent,
+    TetherAgentOptionsBuilder,
+};
+
+/// The plain-data part of an Agent's options, which can be saved to and loaded from a
+/// file (JSON, TOML, YAML, ...) with Serde, so that a whole Agent configuration is
+/// reproducible; see `TetherAgentOptionsBuilder::from_config` and `to_config`.
+///
+/// Only the Role is required; anything left out has the same default as on the builder.
+/// Options which are not plain data (e.g. `on_disconnect` callbacks, topic rewrites or
+/// additional brokers) are not included, and can be set on the builder afterwards.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AgentConfig {
+    pub role: String,
+    pub id: Option<String>,
+    pub protocol: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub base_path: Option<String>,
+    pub mqtt_client_id: Option<String>,
+    /// True (or left out) to start a new session with the broker on every connect
+    pub clean_session: Option<bool>,
+    pub label: Option<String>,
+    /// True (or left out) to connect on `build()`
+    pub auto_connect: Option<bool>,
+    #[serde(default)]
+    pub lazy_connect: bool,
+    /// True (or left out) to receive messages; false for a publish-only Agent
+    pub consume_incoming: Option<bool>,
+    #[serde(default)]
+    pub announce_presence: bool,
+    #[serde(default)]
+    pub announce_manifest: bool,
+    #[serde(default)]
+    pub lowercase_topics: bool,
+    pub topic_schema: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub maximum_packet_size: Option<u32>,
+    pub queue_high_water_mark: Option<usize>,
+    pub alpn_protocols: Option<Vec<String>>,
+    pub server_name: Option<String>,
+    pub proxy: Option<String>,
+    pub bind_device: Option<String>,
+    pub default_subscribe_qos: Option<i32>,
+    pub default_publish_qos: Option<i32>,
+    pub auth_fallback: Option<AuthFallback>,
+    pub reconnect_policy: Option<ReconnectPolicy>,
+    pub duplicate_client_id_policy: Option<DuplicateClientIdPolicy>,
+    pub encode_error_policy: Option<ErrorPolicy>,
+}
+
+impl std::fmt::Debug for AgentConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentConfig")
+            .field("role", &self.role)
+            .field("id", &self.id)
+            .field("protocol", &self.protocol)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("has_password", &self.password.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AgentConfig {
+    /// A configuration with only the Role given, i.e. every other option at its default
+    pub fn new(role: &str) -> AgentConfig {
+        TetherAgentOptionsBuilder::new(role).to_config()
+    }
+
+    /// Build (and by default connect) the Agent; see `TetherAgentOptionsBuilder::build`
+    pub fn build(&self) -> anyhow::Result<TetherAgent> {
+        TetherAgentOptionsBuilder::from_config(self).build()
+    }
+}
+
+impl TetherAgentOptionsBuilder {
+    /// Start from a saved configuration; any other options can still be set afterwards
+    pub fn from_config(config: &AgentConfig) -> Self {
+        // Destructured, so that a new option cannot be left out by mistake
+        let AgentConfig {
+            role,
+            id,
+            protocol,
+            host,
+            port,
+            username,
+            password,
+            base_path,
+            mqtt_client_id,
+            clean_session,
+            label,
+            auto_connect,
+            lazy_connect,
+            consume_incoming,
+            announce_presence,
+            announce_manifest,
+            lowercase_topics,
+            topic_schema,
+            dry_run,
+            maximum_packet_size,
+            queue_high_water_mark,
+            alpn_protocols,
+            server_name,
+            proxy,
+            bind_device,
+            default_subscribe_qos,
+            default_publish_qos,
+            auth_fallback,
+            reconnect_policy,
+            duplicate_client_id_policy,
+            encode_error_policy,
+        } = config.clone();
+        let mut builder = TetherAgentOptionsBuilder::new(&role);
+        builder.id = id;
+        builder.protocol = protocol;
+        builder.host = host;
+        builder.port = port;
+        builder.username = username;
+        builder.password = password;
+        builder.base_path = base_path;
+        builder.mqtt_client_id = mqtt_client_id;
+        builder.clean_session = clean_session;
+        builder.label = label;
+        builder.auto_connect = auto_connect.unwrap_or(builder.auto_connect);
+        builder.lazy_connect = lazy_connect;
+        builder.consume_incoming = consume_incoming.unwrap_or(builder.consume_incoming);
+        builder.announce_presence = announce_presence;
+        builder.announce_manifest = announce_manifest;
+        builder.lowercase_topics = lowercase_topics;
+        builder.topic_schema = topic_schema;
+        builder.dry_run = dry_run;
+        builder.maximum_packet_size = maximum_packet_size;
+        builder.queue_high_water_mark = queue_high_water_mark;
+        builder.alpn_protocols = alpn_protocols;
+        builder.server_name = server_name;
+        builder.proxy = proxy;
+        builder.bind_device = bind_device;
+        builder.default_subscribe_qos = default_subscribe_qos;
+        builder.default_publish_qos = default_publish_qos;
+        builder.auth_fallback = auth_fallback;
+        builder.reconnect_policy = reconnect_policy;
+        builder.duplicate_client_id_policy = duplicate_client_id_policy;
+        builder.encode_error_policy = encode_error_policy;
+        builder
+    }
+
+    /// The plain-data options set so far, e.g. to save them; see `AgentConfig`
+    pub fn to_config(&self) -> AgentConfig {
+        // Destructured, so that a new option has to be either saved or skipped explicitly
+        let TetherAgentOptionsBuilder {
+            role,
+            id,
+            label,
+            protocol,
+            host,
+            port,
+            username,
+            password,
+            base_path,
+            auto_connect,
+            lazy_connect,
+            consume_incoming,
+            announce_presence,
+            announce_manifest,
+            lowercase_topics,
+            topic_schema,
+            topic_rewrites: _,
+            dry_run,
+            maximum_packet_size,
+            queue_high_water_mark,
+            mqtt_client_id,
+            clean_session,
+            alpn_protocols,
+            server_name,
+            proxy,
+            bind_device,
+            on_disconnect: _,
+            reconnect_policy,
+            duplicate_client_id_policy,
+            auth_fallback,
+            default_subscribe_qos,
+            default_publish_qos,
+            encode_error_policy,
+            additional_brokers: _,
+        } = self.clone();
+        AgentConfig {
+            role,
+            id,
+            protocol,
+            host,
+            port,
+            username,
+            password,
+            base_path,
+            mqtt_client_id,
+            clean_session,
+            label,
+            auto_connect: Some(auto_connect),
+            lazy_connect,
+            consume_incoming: Some(consume_incoming),
+            announce_presence,
+            announce_manifest,
+            lowercase_topics,
+            topic_schema,
+            dry_run,
+            maximum_packet_size,
+            queue_high_water_mark,
+            alpn_protocols,
+            server_name,
+            proxy,
+            bind_device,
+            default_subscribe_qos,
+            default_publish_qos,
+            auth_fallback,
+            reconnect_policy,
+            duplicate_client_id_policy,
+            encode_error_policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{DuplicateClientIdPolicy, ErrorPolicy, ReconnectPolicy, TetherAgentOptionsBuilder};
+
+    use super::AgentConfig;
+
+    #[test]
+    fn config_round_trip() {
+        let builder = TetherAgentOptionsBuilder::new("tester")
+            .id(Some("configured"))
+            .host(Some("localhost"))
+            .port(Some(1883))
+            .username(Some("tether"))
+            .password(Some("sp_ceB0ss!"))
+            .announce_presence(true)
+            .default_publish_qos(Some(2))
+            .reconnect_policy(Some(
+                ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(5))
+                    .with_jitter(0.2),
+            ))
+            .duplicate_client_id_policy(Some(
+                DuplicateClientIdPolicy::new(4, Duration::from_secs(10))
+                    .with_stop_reconnecting(true),
+            ))
+            .on_encode_error(Some(ErrorPolicy::SkipWithWarning))
+            .auto_connect(false);
+        let config = builder.to_config();
+        assert!(!format!("{:?}", config).contains("sp_ceB0ss!"));
+
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded: AgentConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, config);
+        assert_eq!(
+            TetherAgentOptionsBuilder::from_config(&loaded).to_config(),
+            config
+        );
+
+        // Only the Role is needed; unknown options are rejected
+        let minimal: AgentConfig = serde_json::from_str(r#"{ "role": "tester" }"#).unwrap();
+        assert_eq!(
+            TetherAgentOptionsBuilder::from_config(&minimal).to_config(),
+            AgentConfig::new("tester")
+        );
+        assert!(serde_json::from_str::<AgentConfig>(r#"{ "id": "x" }"#).is_err());
+        assert!(serde_json::from_str::<AgentConfig>(r#"{ "role": "x", "hots": "y" }"#).is_err());
+
+        // Policies are loaded with the same limits as when built
+        let clamped: AgentConfig = serde_json::from_str(
+            r#"{ "role": "tester", "reconnectPolicy": {
+                "initialDelay": { "secs": 1, "nanos": 0 }, "maxDelay": { "secs": 0, "nanos": 0 },
+                "multiplier": 0.5, "jitter": 3.0, "stableAfter": { "secs": 0, "nanos": 0 } } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            clamped.reconnect_policy,
+            Some(ReconnectPolicy::fixed(Duration::from_secs(1)).with_jitter(1.0))
+        );
+    }
+
+    #[test]
+    fn build_from_config() {
+        let config: AgentConfig = serde_json::from_str(
+            r#"{ "role": "tester", "id": "fromConfig", "defaultPublishQos": 2 }"#,
+        )
+        .unwrap();
+        let tether_agent = config
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        assert!(tether_agent.is_connected());
+        assert_eq!(tether_agent.id(), "fromConfig");
+        assert_eq!(tether_agent.default_publish_qos(), Some(2));
+    }
+}
@@ -0,0 +1,288 @@
+use rmpv::ValueRef;
+use serde::de::DeserializeOwned;
+
+use super::Message;
+
+/// First byte of any payload which starts with a Tether header (e.g. a schema version,
+/// or to mark the payload as encrypted). This byte (0xC1) is reserved as "never used"
+/// in the MessagePack spec, so such a payload can never be confused with a plain
+/// MessagePack one.
+pub const HEADER_MARKER: u8 = 0xc1;
+
+/// Second byte of a Tether header: the payload carries a schema version
+pub const HEADER_KIND_VERSION: u8 = 0x01;
+
+/// Second byte of a Tether header: the rest of the payload is encrypted
+pub const HEADER_KIND_ENCRYPTED: u8 = 0x02;
+
+/// Second byte of a Tether header: the payload is one chunk of a larger one; see
+/// `ChunkReassembler`
+pub const HEADER_KIND_CHUNK: u8 = 0x03;
+
+/// The error type returned when a MessagePack payload cannot be decoded
+pub type DecodeError = rmp_serde::decode::Error;
+
+/// Decode a single MessagePack payload directly into the given type
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, DecodeError> {
+    rmp_serde::from_slice(payload)
+}
+
+/// Decode a batch of messages which are all expected to have the same type, e.g.
+/// a burst of sensor readings drained from `TetherAgent::check_messages` in one frame.
+///
+/// Each payload is decoded straight into `T` (no intermediate dynamic value or JSON
+/// string), and a single output Vec is allocated up front for the whole batch. The
+/// result for each message is returned in the same order, so that one bad payload
+/// does not prevent the others from being decoded.
+pub fn decode_batch<T: DeserializeOwned>(messages: &[Message]) -> Vec<Result<T, DecodeError>> {
+    let mut results = Vec::with_capacity(messages.len());
+    results.extend(messages.iter().map(|(_topic, payload)| decode(payload)));
+    results
+}
+
+/// The type of a MessagePack value which is neither a map nor an array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarKind {
+    Nil,
+    Boolean,
+    Integer,
+    Float,
+    String,
+    Binary,
+    /// An extension type, with its type number
+    Extension(i8),
+}
+
+/// The top-level structure of a payload, as found by `probe_payload`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadShape {
+    /// A map (e.g. a struct encoded with `encode_and_publish`), with its keys in the order
+    /// they appear; any key which is not a string is given as its MessagePack text
+    Map(Vec<String>),
+    /// An array, with its length
+    Array(usize),
+    Scalar(ScalarKind),
+    /// Starts with a Tether header (see `HEADER_MARKER`), with the kind of header, e.g.
+    /// `HEADER_KIND_VERSION`; the rest can only be probed after removing the header
+    Header(u8),
+    Empty,
+    /// Not (a single, complete) MessagePack value
+    Invalid,
+}
+
+/// Find out the top-level structure of a payload without decoding it into any type, e.g.
+/// to choose the right decoder for a topic whose message shape has changed over time.
+/// Nothing is copied except the map keys.
+pub fn probe_payload(payload: &[u8]) -> PayloadShape {
+    match payload {
+        [] => return PayloadShape::Empty,
+        [HEADER_MARKER, kind, ..] => return PayloadShape::Header(*kind),
+        _ => {}
+    }
+    let mut remaining = payload;
+    let value = match rmpv::decode::read_value_ref(&mut remaining) {
+        Ok(value) if remaining.is_empty() => value,
+        _ => return PayloadShape::Invalid,
+    };
+    match value {
+        ValueRef::Map(entries) => PayloadShape::Map(
+            entries
+                .iter()
+                .map(|(k, _)| match k {
+                    ValueRef::String(s) if s.is_str() => String::from(s.as_str().unwrap()),
+                    _ => k.to_string(),
+                })
+                .collect(),
+        ),
+        ValueRef::Array(items) => PayloadShape::Array(items.len()),
+        ValueRef::Nil => PayloadShape::Scalar(ScalarKind::Nil),
+        ValueRef::Boolean(_) => PayloadShape::Scalar(ScalarKind::Boolean),
+        ValueRef::Integer(_) => PayloadShape::Scalar(ScalarKind::Integer),
+        ValueRef::F32(_) | ValueRef::F64(_) => PayloadShape::Scalar(ScalarKind::Float),
+        ValueRef::String(_) => PayloadShape::Scalar(ScalarKind::String),
+        ValueRef::Binary(_) => PayloadShape::Scalar(ScalarKind::Binary),
+        ValueRef::Ext(kind, _) => PayloadShape::Scalar(ScalarKind::Extension(kind)),
+    }
+}
+
+/// A MessagePack value as JSON text; a map with keys which JSON cannot have (e.g. nil, or
+/// another map) falls back to the value's own (JSON-like) `Display` form, since the
+/// payload may come from anyone
+pub fn value_to_json(value: &rmpv::Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// A readable preview of a payload for log lines: decoded as JSON if it is exactly one
+/// MessagePack value (otherwise shown as text), and truncated to at most `max_len`
+/// characters, noting the total size if anything was cut off.
+pub fn preview_payload(payload: &[u8], max_len: usize) -> String {
+    let mut remaining = payload;
+    let preview = match rmpv::decode::read_value(&mut remaining) {
+        Ok(value) if remaining.is_empty() => value_to_json(&value),
+        // Not only invalid MessagePack, but also plain text (which starts with a
+        // MessagePack integer) is shown as text
+        _ => format!("\"{}\"", String::from_utf8_lossy(payload)),
+    };
+    truncate_preview(preview, max_len, payload.len())
+}
+
+/// Cut a preview of a payload (e.g. one made like `preview_payload`, but with some values
+/// masked) down to at most `max_len` characters, noting the total size of the payload if
+/// anything was cut off
+pub fn truncate_preview(preview: String, max_len: usize, payload_len: usize) -> String {
+    match preview.char_indices().nth(max_len) {
+        Some((cut, _)) => format!("{}… ({} bytes total)", &preview[..cut], payload_len),
+        None => preview,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        three_part_topic::{TetherOrCustomTopic, ThreePartTopic},
+        Message,
+    };
+
+    use super::{decode_batch, preview_payload, probe_payload, PayloadShape, ScalarKind};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        index: usize,
+        value: f32,
+    }
+
+    fn message(payload: Vec<u8>) -> Message {
+        (
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked("sensor", "any", "readings")),
+            payload,
+        )
+    }
+
+    #[test]
+    fn batch_in_order_with_failures() {
+        let messages = vec![
+            message(
+                rmp_serde::to_vec_named(&Reading {
+                    index: 0,
+                    value: 0.5,
+                })
+                .unwrap(),
+            ),
+            message(vec![]),
+            message(
+                rmp_serde::to_vec_named(&Reading {
+                    index: 2,
+                    value: 1.5,
+                })
+                .unwrap(),
+            ),
+        ];
+
+        let decoded = decode_batch::<Reading>(&messages);
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(
+            decoded[0].as_ref().unwrap(),
+            &Reading {
+                index: 0,
+                value: 0.5
+            }
+        );
+        assert!(decoded[1].is_err());
+        assert_eq!(
+            decoded[2].as_ref().unwrap(),
+            &Reading {
+                index: 2,
+                value: 1.5
+            }
+        );
+    }
+
+    #[test]
+    fn probe_map_payload() {
+        let payload = rmp_serde::to_vec_named(&Reading {
+            index: 3,
+            value: 0.5,
+        })
+        .unwrap();
+        assert_eq!(
+            probe_payload(&payload),
+            PayloadShape::Map(vec!["index".into(), "value".into()])
+        );
+
+        let mut numbered = std::collections::BTreeMap::new();
+        numbered.insert(1, "one");
+        assert_eq!(
+            probe_payload(&rmp_serde::to_vec(&numbered).unwrap()),
+            PayloadShape::Map(vec!["1".into()])
+        );
+    }
+
+    #[test]
+    fn probe_array_payload() {
+        let payload = rmp_serde::to_vec(&[1.0, 2.0, 3.0]).unwrap();
+        assert_eq!(probe_payload(&payload), PayloadShape::Array(3));
+        // A struct encoded without field names is an array too
+        let payload = rmp_serde::to_vec(&Reading {
+            index: 3,
+            value: 0.5,
+        })
+        .unwrap();
+        assert_eq!(probe_payload(&payload), PayloadShape::Array(2));
+    }
+
+    #[test]
+    fn probe_scalar_payloads() {
+        let scalar = |payload: Vec<u8>| probe_payload(&payload);
+        assert_eq!(
+            scalar(rmp_serde::to_vec(&42).unwrap()),
+            PayloadShape::Scalar(ScalarKind::Integer)
+        );
+        assert_eq!(
+            scalar(rmp_serde::to_vec(&0.5).unwrap()),
+            PayloadShape::Scalar(ScalarKind::Float)
+        );
+        assert_eq!(
+            scalar(rmp_serde::to_vec("hello").unwrap()),
+            PayloadShape::Scalar(ScalarKind::String)
+        );
+        assert_eq!(
+            scalar(rmp_serde::to_vec(&true).unwrap()),
+            PayloadShape::Scalar(ScalarKind::Boolean)
+        );
+        assert_eq!(
+            scalar(rmp_serde::to_vec(&()).unwrap()),
+            PayloadShape::Scalar(ScalarKind::Nil)
+        );
+
+        assert_eq!(probe_payload(&[]), PayloadShape::Empty);
+        assert_eq!(probe_payload(&[0x92, 0x01]), PayloadShape::Invalid);
+        assert_eq!(probe_payload(&[0x01, 0x02]), PayloadShape::Invalid);
+        let versioned = crate::encode_versioned(42, 2).unwrap();
+        assert_eq!(
+            probe_payload(&versioned),
+            PayloadShape::Header(super::HEADER_KIND_VERSION)
+        );
+    }
+
+    #[test]
+    fn preview_truncated() {
+        let payload = rmp_serde::to_vec(&vec![1u32; 500]).unwrap();
+        assert_eq!(
+            preview_payload(&payload, 10),
+            format!("[1,1,1,1,1… ({} bytes total)", payload.len())
+        );
+
+        let payload = rmp_serde::to_vec(&[1, 2, 3]).unwrap();
+        assert_eq!(preview_payload(&payload, 10), "[1,2,3]");
+        assert_eq!(preview_payload(b"plain text", 200), "\"plain text\"");
+        assert_eq!(
+            preview_payload(b"\xc1not msgpack", 200),
+            "\"\u{FFFD}not msgpack\""
+        );
+        assert_eq!(preview_payload(&[], 200), "\"\"");
+        // A map with a nil key is valid MessagePack, but has no JSON equivalent
+        assert_eq!(preview_payload(&[0x81, 0xc0, 0x01], 100), "{nil: 1}");
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rumqttc::{ConnectionError, StateError};
+use serde::{Deserialize, Serialize};
+
+/// Why the connection to the broker was lost (or could not be established), as far
+/// as the Agent can tell.
+///
+/// This Agent uses MQTT 3.1.1, where brokers do not send a DISCONNECT packet with a
+/// reason code (that only exists in MQTT v5); they simply close the connection. So,
+/// for example, being kicked off because another client connected with the same
+/// MQTT Client ID ("session taken over") and the broker shutting down both appear
+/// as `ClosedByBroker`, while network problems usually appear as `Timeout` or `Network`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisconnectReason {
+    /// The broker closed the connection cleanly
+    ClosedByBroker,
+    /// The broker refused the connection, e.g. because of bad credentials; the
+    /// return code is included
+    Refused(String),
+    /// No response from the broker in time (keep-alive or network timeout)
+    Timeout,
+    /// Any other network (or TLS, websocket...) error
+    Network(String),
+}
+
+impl From<&ConnectionError> for DisconnectReason {
+    fn from(e: &ConnectionError) -> Self {
+        match e {
+            ConnectionError::Io(io_error)
+            | ConnectionError::MqttState(StateError::Io(io_error))
+                if io_error.kind() == io::ErrorKind::ConnectionAborted =>
+            {
+                DisconnectReason::ClosedByBroker
+            }
+            ConnectionError::ConnectionRefused(code) => {
+                DisconnectReason::Refused(format!("{:?}", code))
+            }
+            ConnectionError::NetworkTimeout
+            | ConnectionError::FlushTimeout
+            | ConnectionError::MqttState(StateError::AwaitPingResp) => DisconnectReason::Timeout,
+            _ => DisconnectReason::Network(e.to_string()),
+        }
+    }
+}
+
+/// How to detect, and what to do about, another client connecting with the same MQTT
+/// Client ID as this Agent. The broker disconnects whichever client was connected first,
+/// so two such clients which both reconnect automatically keep kicking each other off
+/// (a "reconnect war").
+///
+/// MQTT 3.1.1 has no reason code for "session taken over" (see `DisconnectReason`), so a
+/// duplicate Client ID is suspected when the broker closes the connection at least
+/// `max_disconnects` times within `window`. A distinct warning is logged when this
+/// happens; with `stop_reconnecting`, the Agent also gives up reconnecting, which ends the
+/// war (as if an `on_disconnect` callback had returned false).
+///
+/// The default is to warn after 3 such disconnects within 30 seconds, but keep reconnecting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", from = "DuplicateClientIdPolicyFields")]
+pub struct DuplicateClientIdPolicy {
+    max_disconnects: u32,
+    window: Duration,
+    stop_reconnecting: bool,
+}
+
+impl Default for DuplicateClientIdPolicy {
+    fn default() -> Self {
+        DuplicateClientIdPolicy::new(3, Duration::from_secs(30))
+    }
+}
+
+/// The fields of a `DuplicateClientIdPolicy` as saved, e.g. in an `AgentConfig`; loaded
+/// through the constructor, so that the same limits apply
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct DuplicateClientIdPolicyFields {
+    max_disconnects: u32,
+    window: Duration,
+    stop_reconnecting: bool,
+}
+
+impl From<DuplicateClientIdPolicyFields> for DuplicateClientIdPolicy {
+    fn from(fields: DuplicateClientIdPolicyFields) -> Self {
+        DuplicateClientIdPolicy::new(fields.max_disconnects, fields.window)
+            .with_stop_reconnecting(fields.stop_reconnecting)
+    }
+}
+
+impl DuplicateClientIdPolicy {
+    /// Suspect a duplicate Client ID after this many disconnects (at least 2) by the broker
+    /// within the window
+    pub fn new(max_disconnects: u32, window: Duration) -> DuplicateClientIdPolicy {
+        DuplicateClientIdPolicy {
+            max_disconnects: max_disconnects.max(2),
+            window,
+            stop_reconnecting: false,
+        }
+    }
+
+    /// Give up reconnecting once a duplicate Client ID is suspected
+    pub fn with_stop_reconnecting(mut self, stop_reconnecting: bool) -> DuplicateClientIdPolicy {
+        self.stop_reconnecting = stop_reconnecting;
+        self
+    }
+
+    pub fn stops_reconnecting(&self) -> bool {
+        self.stop_reconnecting
+    }
+}
+
+/// Keeps track of recent disconnects by the broker, for `DuplicateClientIdPolicy`
+pub(crate) struct DuplicateClientIdDetector {
+    policy: DuplicateClientIdPolicy,
+    closed_at: VecDeque<Instant>,
+}
+
+impl DuplicateClientIdDetector {
+    pub(crate) fn new(policy: DuplicateClientIdPolicy) -> DuplicateClientIdDetector {
+        DuplicateClientIdDetector {
+            policy,
+            closed_at: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &DuplicateClientIdPolicy {
+        &self.policy
+    }
+
+    /// Record a disconnect; returns true if a duplicate Client ID is now suspected. The
+    /// count then starts again, so that a continuing war is reported every so often,
+    /// rather than on every disconnect.
+    pub(crate) fn on_disconnect(&mut self, reason: &DisconnectReason, now: Instant) -> bool {
+        if *reason != DisconnectReason::ClosedByBroker {
+            return false;
+        }
+        self.closed_at.push_back(now);
+        while self
+            .closed_at
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > self.policy.window)
+        {
+            self.closed_at.pop_front();
+        }
+        let suspected = self.closed_at.len() >= self.policy.max_disconnects as usize;
+        if suspected {
+            self.closed_at.clear();
+        }
+        suspected
+    }
+}
+
+/// Called (from the connection thread) every time the connection is lost or fails;
+/// return `true` to keep trying to reconnect, or `false` to give up.
+pub type DisconnectCallback = Arc<dyn Fn(&DisconnectReason) -> bool + Send + Sync>;
+
+/// A change in the state of the connection to the broker, as received from
+/// `TetherAgent::connection_events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// Connected (or reconnected), i.e. the broker acknowledged the connection; `duration`
+    /// is how long that took, from starting the attempt (see also `last_connect_duration`)
+    Connected { duration: Duration },
+    /// The connection was lost, or could not be established. Not sent when the Agent
+    /// disconnects deliberately.
+    Disconnected { reason: DisconnectReason },
+    /// About to try to reconnect, after the delay given by the `ReconnectPolicy`; the
+    /// attempts are counted from 1, starting again after every connection which stayed up
+    /// long enough (see `ReconnectPolicy::with_stable_after`)
+    Reconnecting { attempt: u32 },
+}
+
+/// Everyone listening for connection events
+pub(crate) type ConnectionEventSenders = Arc<Mutex<Vec<mpsc::Sender<ConnectionEvent>>>>;
+
+/// Send the event to every listener, forgetting any which have gone away
+pub(crate) fn send_connection_event(senders: &ConnectionEventSenders, event: ConnectionEvent) {
+    senders
+        .lock()
+        .expect("failed to lock mutex")
+        .retain(|sender| sender.send(event.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        time::{Duration, Instant},
+    };
+
+    use rumqttc::{ConnectReturnCode, ConnectionError, StateError};
+
+    use super::{DisconnectReason, DuplicateClientIdDetector, DuplicateClientIdPolicy};
+
+    #[test]
+    fn classify_errors() {
+        let closed = io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "connection closed by peer",
+        );
+        assert_eq!(
+            DisconnectReason::from(&ConnectionError::MqttState(StateError::Io(closed))),
+            DisconnectReason::ClosedByBroker
+        );
+        assert_eq!(
+            DisconnectReason::from(&ConnectionError::NetworkTimeout),
+            DisconnectReason::Timeout
+        );
+        assert!(matches!(
+            DisconnectReason::from(&ConnectionError::ConnectionRefused(
+                ConnectReturnCode::BadUserNamePassword
+            )),
+            DisconnectReason::Refused(_)
+        ));
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+        assert!(matches!(
+            DisconnectReason::from(&ConnectionError::Io(refused)),
+            DisconnectReason::Network(_)
+        ));
+    }
+
+    #[test]
+    fn duplicate_client_id_suspected() {
+        let mut detector = DuplicateClientIdDetector::new(DuplicateClientIdPolicy::new(
+            3,
+            Duration::from_secs(10),
+        ));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Only disconnects by the broker count, and only within the window
+        assert!(!detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(0)));
+        assert!(!detector.on_disconnect(&DisconnectReason::Timeout, at(1)));
+        assert!(!detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(2)));
+        assert!(!detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(20)));
+        assert!(!detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(25)));
+        assert!(detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(26)));
+        assert!(!detector.on_disconnect(&DisconnectReason::ClosedByBroker, at(27)));
+    }
+}
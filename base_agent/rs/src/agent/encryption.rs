@@ -0,0 +1,133 @@
+use std::fmt;
+
+use anyhow::anyhow;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+use super::{HEADER_KIND_ENCRYPTED, HEADER_MARKER};
+
+const NONCE_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = 2 + NONCE_LENGTH;
+
+/// A 256-bit key shared by all the Agents which need to read or write encrypted
+/// payloads on a Plug. Payloads are encrypted with ChaCha20-Poly1305, so any
+/// tampering (or the use of the wrong key) is detected on decryption.
+///
+/// This is application-layer encryption of the message payloads only, which is
+/// distinct from (and can be combined with) TLS: with `mqtts` or `wss` the connection
+/// to the broker is encrypted, but the broker itself (and anyone else allowed to
+/// subscribe) can still read the payloads. Topics are never encrypted.
+#[derive(Clone)]
+pub struct EncryptionKey(Key);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> EncryptionKey {
+        EncryptionKey(bytes.into())
+    }
+
+    /// The slice must be exactly 32 bytes long
+    pub fn from_slice(bytes: &[u8]) -> anyhow::Result<EncryptionKey> {
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Encryption key must be 32 bytes, got {}", bytes.len()))?;
+        Ok(EncryptionKey::new(bytes))
+    }
+
+    /// Generate a new random key, e.g. to be saved and distributed to all Agents
+    pub fn generate() -> EncryptionKey {
+        EncryptionKey(ChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Returns true if the payload starts with the header which marks it as encrypted;
+/// consumers without the key can use this to skip such messages.
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    matches!(payload, [HEADER_MARKER, HEADER_KIND_ENCRYPTED, ..])
+}
+
+/// Encrypt the payload, preceded by a header: the marker and kind bytes, then the
+/// (random) nonce used for this payload.
+pub fn encrypt_payload(key: &EncryptionKey, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt payload"))?;
+
+    let mut payload = Vec::with_capacity(HEADER_LENGTH + ciphertext.len());
+    payload.push(HEADER_MARKER);
+    payload.push(HEADER_KIND_ENCRYPTED);
+    payload.extend_from_slice(&nonce);
+    payload.extend_from_slice(&ciphertext);
+    Ok(payload)
+}
+
+/// Decrypt a payload which was encrypted with `encrypt_payload`. Fails if the payload
+/// is not encrypted, was encrypted with a different key or has been tampered with.
+pub fn decrypt_payload(key: &EncryptionKey, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if !is_encrypted(payload) || payload.len() < HEADER_LENGTH {
+        return Err(anyhow!("Payload is not encrypted"));
+    }
+    let cipher = ChaCha20Poly1305::new(&key.0);
+    let nonce = Nonce::from_slice(&payload[2..HEADER_LENGTH]);
+    cipher
+        .decrypt(nonce, &payload[HEADER_LENGTH..])
+        .map_err(|_| anyhow!("Failed to decrypt payload; wrong key, or tampered with?"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt_payload, encrypt_payload, is_encrypted, EncryptionKey};
+
+    #[test]
+    fn round_trip() {
+        let key = EncryptionKey::generate();
+        let plaintext = rmp_serde::to_vec_named(&[1, 2, 3]).unwrap();
+
+        let encrypted = encrypt_payload(&key, &plaintext).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(&plaintext));
+        assert_ne!(encrypted, plaintext);
+
+        assert_eq!(decrypt_payload(&key, &encrypted).unwrap(), plaintext);
+
+        // Same plaintext encrypts differently every time
+        assert_ne!(encrypt_payload(&key, &plaintext).unwrap(), encrypted);
+    }
+
+    #[test]
+    fn wrong_key_fails() {
+        let key = EncryptionKey::new([7; 32]);
+        let other_key = EncryptionKey::new([8; 32]);
+        let encrypted = encrypt_payload(&key, b"secret").unwrap();
+        assert!(decrypt_payload(&other_key, &encrypted).is_err());
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_payload(&key, &tampered).is_err());
+
+        assert!(decrypt_payload(&key, b"not encrypted").is_err());
+    }
+
+    #[test]
+    fn key_from_slice() {
+        assert!(EncryptionKey::from_slice(&[0; 32]).is_ok());
+        assert!(EncryptionKey::from_slice(&[0; 16]).is_err());
+        assert_eq!(
+            format!("{:?}", EncryptionKey::new([1; 32])),
+            "EncryptionKey(..)"
+        );
+    }
+}
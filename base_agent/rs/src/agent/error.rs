@@ -0,0 +1,58 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Errors which the Agent reports in a form that can be matched on (by downcasting the
+/// `anyhow::Error`), rather than only as a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TetherError {
+    /// The broker host (or the URI built from it) is not valid
+    InvalidBrokerUri { host: String, reason: String },
+    /// Not connected to the broker (yet, or any more), so nothing can be published
+    NotConnected,
+    /// The topic to publish on contains a `+` or `#` wildcard, which MQTT only allows in
+    /// subscriptions (e.g. a subscription pattern used as a publish topic by mistake)
+    WildcardInPublishTopic { topic: String },
+    /// A payload arrived on a Plug which expects encrypted payloads, without encryption;
+    /// refused, since anyone able to publish on the topic could otherwise bypass it
+    UnencryptedPayload { plug_name: String },
+}
+
+impl fmt::Display for TetherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBrokerUri { host, reason } => {
+                write!(f, "invalid broker host \"{}\": {}", host, reason)
+            }
+            Self::NotConnected => write!(f, "not connected to the broker"),
+            Self::WildcardInPublishTopic { topic } => write!(
+                f,
+                "cannot publish on \"{}\": wildcards (+ and #) are only allowed in subscriptions",
+                topic
+            ),
+            Self::UnencryptedPayload { plug_name } => write!(
+                f,
+                "Plug \"{}\" expects encrypted payloads, but received plaintext",
+                plug_name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TetherError {}
+
+/// What to do when data cannot be encoded for publishing (e.g. `encode_and_publish` with a
+/// value whose `Serialize` implementation fails); see
+/// `TetherAgentOptionsBuilder::on_encode_error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorPolicy {
+    /// Log the error and return it, without publishing anything
+    #[default]
+    Error,
+    /// Log a warning and publish nothing, but return Ok, so that one bad value does not
+    /// break a stream of messages
+    SkipWithWarning,
+    /// Panic, for strict pipelines where a value which cannot be encoded is a bug
+    Panic,
+}
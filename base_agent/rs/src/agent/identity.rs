@@ -0,0 +1,126 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::three_part_topic::{validate_part, ThreePartTopic};
+
+/// Who an Agent is: its Role and ID (group), i.e. the first two parts of every topic it
+/// publishes on by default. Both parts are validated on construction, so an identity
+/// can always be used to build a publishable topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AgentIdentity {
+    role: String,
+    id: String,
+}
+
+impl AgentIdentity {
+    /// Fails if either part is empty or contains separators (`/`) or wildcards (`+`, `#`)
+    pub fn new(role: &str, id: &str) -> anyhow::Result<AgentIdentity> {
+        validate_part("role", role)?;
+        validate_part("id", id)?;
+        Ok(AgentIdentity {
+            role: role.into(),
+            id: id.into(),
+        })
+    }
+
+    pub fn role(&self) -> &str {
+        &self.role
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn set_role(&mut self, role: &str) -> anyhow::Result<()> {
+        validate_part("role", role)?;
+        self.role = role.into();
+        Ok(())
+    }
+
+    pub fn set_id(&mut self, id: &str) -> anyhow::Result<()> {
+        validate_part("id", id)?;
+        self.id = id.into();
+        Ok(())
+    }
+
+    /// The topic this identity would publish on, for the given Plug name
+    pub fn topic(&self, plug_name: &str) -> anyhow::Result<ThreePartTopic> {
+        ThreePartTopic::new(&self.role, &self.id, plug_name)
+    }
+}
+
+/// Formats as `role/id`, i.e. the topic prefix
+impl fmt::Display for AgentIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.role, self.id)
+    }
+}
+
+/// Parses `role/id`, as produced by `Display`
+impl FromStr for AgentIdentity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (role, id) = s.split_once('/').ok_or(anyhow!(
+            "Expected an identity of the form role/id, got \"{}\"",
+            s
+        ))?;
+        AgentIdentity::new(role, id)
+    }
+}
+
+/// The identity of whoever published on the topic; fails for wildcard (subscribe) topics
+impl TryFrom<&ThreePartTopic> for AgentIdentity {
+    type Error = anyhow::Error;
+
+    fn try_from(topic: &ThreePartTopic) -> Result<Self, Self::Error> {
+        AgentIdentity::new(topic.role(), topic.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::three_part_topic::ThreePartTopic;
+
+    use super::AgentIdentity;
+
+    #[test]
+    fn identity_validation() {
+        let identity = AgentIdentity::new("brain", "left").unwrap();
+        assert_eq!(identity.role(), "brain");
+        assert_eq!(identity.id(), "left");
+        assert_eq!(identity.to_string(), "brain/left");
+
+        assert!(AgentIdentity::new("", "left").is_err());
+        assert!(AgentIdentity::new("brain", "").is_err());
+        assert!(AgentIdentity::new("brain/extra", "left").is_err());
+        assert!(AgentIdentity::new("+", "left").is_err());
+        assert!(AgentIdentity::new("brain", "#").is_err());
+
+        let mut identity = identity;
+        assert!(identity.set_id("+").is_err());
+        assert_eq!(identity.id(), "left");
+        identity.set_id("right").unwrap();
+        assert_eq!(identity.to_string(), "brain/right");
+    }
+
+    #[test]
+    fn identity_topic_round_trip() {
+        let identity = AgentIdentity::new("brain", "left").unwrap();
+        let topic = identity.topic("decisions").unwrap();
+        assert_eq!(topic.topic(), "brain/left/decisions");
+        assert_eq!(AgentIdentity::try_from(&topic).unwrap(), identity);
+        assert!(identity.topic("+").is_err());
+
+        let parsed = ThreePartTopic::try_from("brain/left/decisions").unwrap();
+        assert_eq!(AgentIdentity::try_from(&parsed).unwrap(), identity);
+        let wildcard = ThreePartTopic::new_for_subscribe("decisions", None, None, None);
+        assert!(AgentIdentity::try_from(&wildcard).is_err());
+
+        assert_eq!("brain/left".parse::<AgentIdentity>().unwrap(), identity);
+        assert!("brain".parse::<AgentIdentity>().is_err());
+        assert!("brain/left/decisions".parse::<AgentIdentity>().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashSet,
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+/// Keeps track of the messages an Agent has published which have not yet been sent (QoS 0)
+/// or acknowledged by the broker (QoS 1 and 2), so that `flush` can wait for them without
+/// polling.
+///
+/// Only publishes made by the Agent itself are counted: the MQTT client may also be used
+/// elsewhere (see `TetherAgent::from_client`), and acknowledgements of those are ignored
+/// rather than letting the count drift.
+#[derive(Default)]
+pub(crate) struct Outstanding {
+    state: Mutex<State>,
+    delivered: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// Handed over to the client, but not yet seen going out
+    unsent: usize,
+    /// Packet IDs of those sent with QoS 1 or 2, still waiting for the broker
+    unacknowledged: HashSet<u16>,
+}
+
+impl State {
+    fn count(&self) -> usize {
+        self.unsent + self.unacknowledged.len()
+    }
+}
+
+impl Outstanding {
+    pub(crate) fn count(&self) -> usize {
+        self.state.lock().expect("failed to lock mutex").count()
+    }
+
+    /// Called just before handing a message over to the client
+    pub(crate) fn handed_over(&self) {
+        self.state.lock().expect("failed to lock mutex").unsent += 1;
+    }
+
+    /// Called if the client refused the message after all
+    pub(crate) fn refused(&self) {
+        self.update(|state| state.unsent = state.unsent.saturating_sub(1));
+    }
+
+    /// Called as the client sends a message, with its packet ID (zero for QoS 0, in which
+    /// case there is nothing more to wait for). Messages sent without having been handed
+    /// over by the Agent, and those sent again after reconnecting, are not counted.
+    pub(crate) fn sent(&self, pkid: u16) {
+        self.update(|state| {
+            if state.unsent > 0 {
+                state.unsent -= 1;
+                if pkid != 0 {
+                    state.unacknowledged.insert(pkid);
+                }
+            }
+        });
+    }
+
+    /// Called when the broker acknowledges (or completes) a message; unknown packet IDs are
+    /// ignored
+    pub(crate) fn acknowledged(&self, pkid: u16) {
+        self.update(|state| {
+            state.unacknowledged.remove(&pkid);
+        });
+    }
+
+    /// Wait until nothing is outstanding, or until the deadline; returns false if the
+    /// deadline passed first
+    pub(crate) fn wait_until_delivered(&self, deadline: Instant) -> bool {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        while state.count() > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .delivered
+                .wait_timeout(state, deadline - now)
+                .expect("failed to lock mutex")
+                .0;
+        }
+        true
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let mut state = self.state.lock().expect("failed to lock mutex");
+        change(&mut state);
+        if state.count() == 0 {
+            self.delivered.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Outstanding;
+
+    #[test]
+    fn only_own_publishes_counted() {
+        let outstanding = Outstanding::default();
+        // Sent and acknowledged by someone else sharing the client
+        outstanding.sent(7);
+        outstanding.acknowledged(7);
+        outstanding.acknowledged(8);
+        assert_eq!(outstanding.count(), 0);
+
+        outstanding.handed_over();
+        outstanding.handed_over();
+        assert_eq!(outstanding.count(), 2);
+        outstanding.sent(0);
+        outstanding.sent(1);
+        assert_eq!(outstanding.count(), 1);
+        // Not counted again when sent again, e.g. after reconnecting
+        outstanding.sent(1);
+        outstanding.acknowledged(2);
+        assert_eq!(outstanding.count(), 1);
+        assert!(!outstanding.wait_until_delivered(Instant::now() + Duration::from_millis(10)));
+
+        outstanding.acknowledged(1);
+        assert_eq!(outstanding.count(), 0);
+        outstanding.refused();
+        assert_eq!(outstanding.count(), 0);
+        assert!(outstanding.wait_until_delivered(Instant::now()));
+    }
+
+    #[test]
+    fn wakes_waiter() {
+        let outstanding = std::sync::Arc::new(Outstanding::default());
+        outstanding.handed_over();
+        let sender = std::sync::Arc::clone(&outstanding);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            sender.sent(0);
+        });
+        assert!(outstanding.wait_until_delivered(Instant::now() + Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use rumqttc::{Client, QoS};
+
+use crate::{
+    encrypt_payload,
+    outstanding::Outstanding,
+    plugs::sequence::Sequencer,
+    topic_rewrite::{rewrite_topic, TopicRewrite},
+    EncryptionKey, OutputPlugDefinition, SubscriptionRegistry,
+};
+
+/// How long to wait, after subscribing, for the broker to send any retained copies of the
+/// persisted values, before republishing those which did not arrive
+const RETAINED_PROBE_WAIT: Duration = Duration::from_millis(500);
+
+/// The latest value published on a topic by an Output Plug which persists its last value,
+/// to publish again on (re)connecting
+#[derive(Clone)]
+pub(crate) struct PersistedValue {
+    qos: QoS,
+    retain: bool,
+    /// As given to `publish`, before any sequence number or encryption, which are added
+    /// again on republishing (so a republished value gets a new sequence number)
+    payload: Vec<u8>,
+    sequencer: Option<Arc<Sequencer>>,
+    encryption_key: Option<EncryptionKey>,
+    /// Whether the value has reached the broker; until it has, any retained copy there is
+    /// an older value, so it is republished regardless
+    published: bool,
+}
+
+impl PersistedValue {
+    /// A value (e.g. the manifest) published as-is, with nothing added on republishing
+    pub(crate) fn plain(qos: QoS, retain: bool, payload: &[u8], published: bool) -> Self {
+        PersistedValue {
+            qos,
+            retain,
+            payload: payload.to_vec(),
+            sequencer: None,
+            encryption_key: None,
+            published,
+        }
+    }
+
+    /// The value given to an Output Plug, which republishes it the way the Plug would
+    pub(crate) fn for_plug(
+        plug_definition: &OutputPlugDefinition,
+        qos: QoS,
+        payload: &[u8],
+    ) -> Self {
+        PersistedValue {
+            sequencer: plug_definition.shared_sequencer(),
+            encryption_key: plug_definition.encryption_key().cloned(),
+            ..PersistedValue::plain(qos, plug_definition.retain(), payload, true)
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    /// The payload to publish again, with a new sequence number and encryption if need be
+    fn prepared_payload(&self) -> anyhow::Result<Vec<u8>> {
+        let payload = match &self.sequencer {
+            Some(sequencer) if !self.payload.is_empty() => sequencer.stamp(&self.payload)?,
+            _ => self.payload.clone(),
+        };
+        match &self.encryption_key {
+            Some(key) if !payload.is_empty() => encrypt_payload(key, &payload),
+            _ => Ok(payload),
+        }
+    }
+}
+
+/// The persisted value for each topic (as published, i.e. any topic rewrite applied)
+pub(crate) type PersistedValues = BTreeMap<String, PersistedValue>;
+
+/// The latest value published on each topic by Output Plugs which persist their last value,
+/// and the retained copies of them being checked for after (re)connecting
+#[derive(Default)]
+pub(crate) struct Persisted {
+    pub(crate) values: Mutex<PersistedValues>,
+    pub(crate) retained_probe: Mutex<RetainedProbe>,
+}
+
+/// The retained topics being checked after (re)connecting, and those on which the broker
+/// turned out to still have a retained message
+#[derive(Default)]
+pub(crate) struct RetainedProbe {
+    topics: HashSet<String>,
+    found: HashSet<String>,
+}
+
+impl RetainedProbe {
+    fn start(&mut self, topics: &[String]) {
+        self.topics = topics.iter().cloned().collect();
+        self.found.clear();
+    }
+
+    /// Whether a retained message on this topic is one being checked for (noting that it
+    /// was found). The topics stay checked for until the next probe, so that a copy which
+    /// arrives late is still recognised.
+    pub(crate) fn observe(&mut self, topic: &str) -> bool {
+        if !self.topics.contains(topic) {
+            return false;
+        }
+        self.found.insert(String::from(topic));
+        true
+    }
+
+    fn found(&self) -> HashSet<String> {
+        self.found.clone()
+    }
+}
+
+/// Publish every persisted last value again, after (re)connecting, with the Plug's own
+/// retain flag. Retained values are only republished if the broker no longer has a retained
+/// message on the topic (e.g. after a restart without persistence): to find out, the Agent
+/// briefly subscribes to each of those topics. A topic the Agent has subscribed to itself is
+/// not checked (that would replace its subscription), so its value is always republished.
+/// This is done on a thread of its own, since publishing can block until
+/// the connection thread (the one calling this) has made room in the queue.
+pub(crate) fn republish_persisted(
+    log_target: &str,
+    client: &Client,
+    persisted: &Arc<Persisted>,
+    subscriptions: &Arc<SubscriptionRegistry>,
+    subscribed_topics: &Mutex<Vec<String>>,
+    topic_rewrites: &[TopicRewrite],
+    outstanding_publishes: &Arc<Outstanding>,
+) {
+    let values = persisted
+        .values
+        .lock()
+        .expect("failed to lock mutex")
+        .clone();
+    if values.is_empty() {
+        return;
+    }
+    let subscribed: Vec<String> = subscribed_topics
+        .lock()
+        .expect("failed to lock mutex")
+        .iter()
+        .map(|t| rewrite_topic(topic_rewrites, t.clone(), TopicRewrite::to_legacy))
+        .collect();
+    let log_target = String::from(log_target);
+    let client = client.clone();
+    let persisted = Arc::clone(persisted);
+    let subscriptions = Arc::clone(subscriptions);
+    let outstanding_publishes = Arc::clone(outstanding_publishes);
+    thread::spawn(move || {
+        let probed: Vec<String> = values
+            .iter()
+            .filter(|(topic, value)| value.retain && value.published && !subscribed.contains(topic))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        if !probed.is_empty() {
+            persisted
+                .retained_probe
+                .lock()
+                .expect("failed to lock mutex")
+                .start(&probed);
+            for topic in &probed {
+                if let Err(e) =
+                    subscriptions.subscribe(&client, None, topic.clone(), QoS::AtMostOnce, false)
+                {
+                    warn!(target: &log_target, "Could not check for retained message on \"{}\": {}", topic, e);
+                }
+            }
+            thread::sleep(RETAINED_PROBE_WAIT);
+            for topic in &probed {
+                let _ = client.unsubscribe(topic);
+            }
+        }
+        let found = persisted
+            .retained_probe
+            .lock()
+            .expect("failed to lock mutex")
+            .found();
+
+        let due: Vec<(String, PersistedValue)> = values
+            .into_iter()
+            .filter(|(topic, _)| {
+                let still_there = found.contains(topic);
+                if still_there {
+                    debug!(target: &log_target, "Broker still has the last value on \"{}\"", topic);
+                }
+                !still_there
+            })
+            .collect();
+        if due.is_empty() {
+            return;
+        }
+        info!(
+            target: &log_target,
+            "Republishing the last value on {} topic(s)",
+            due.len()
+        );
+        for (topic, value) in due {
+            let payload = match value.prepared_payload() {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(target: &log_target, "Could not republish last value on \"{}\": {}", topic, e);
+                    continue;
+                }
+            };
+            outstanding_publishes.handed_over();
+            if let Err(e) = client.publish(&topic, value.qos, value.retain, payload) {
+                outstanding_publishes.refused();
+                warn!(target: &log_target, "Could not republish last value on \"{}\": {}", topic, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,25 @@
+use rmp_serde::to_vec_named;
+use serde::{Deserialize, Serialize};
+
+use crate::{three_part_topic::build_topic, AgentIdentity};
+
+/// The Plug name of the topic on which an Agent announces its presence
+pub const PRESENCE_PLUG_NAME: &str = "presence";
+
+/// The retained message by which an Agent announces that it is online, or (as its "last
+/// will", published by the broker if the Agent disappears) that it is offline.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Presence {
+    pub online: bool,
+}
+
+impl Presence {
+    pub fn payload(&self) -> Vec<u8> {
+        to_vec_named(self).expect("presence should always encode")
+    }
+}
+
+/// The topic an Agent with this identity announces its presence on, e.g. `brain/any/presence`
+pub fn presence_topic(identity: &AgentIdentity) -> String {
+    build_topic(identity.role(), identity.id(), PRESENCE_PLUG_NAME)
+}
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How long to wait before each attempt to reconnect, after the connection has been lost.
+///
+/// The delay starts at `initial_delay` and is multiplied by `multiplier` after every failed
+/// attempt, up to `max_delay`. With `jitter` (a fraction between 0 and 1), each delay is
+/// reduced by a random amount of up to that fraction, so that many Agents which lost their
+/// connection at the same moment (e.g. because the broker restarted) do not all try to
+/// reconnect at the same moment as well.
+///
+/// The delay goes back to `initial_delay` once a connection has stayed up for
+/// `stable_after` (by default straight away), so that one flaky period does not slow down
+/// every reconnect after it.
+///
+/// The default is a fixed delay of 1 second, with no jitter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", from = "ReconnectPolicyFields")]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    stable_after: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy::fixed(Duration::from_secs(1))
+    }
+}
+
+/// The fields of a `ReconnectPolicy` as saved, e.g. in an `AgentConfig`; loaded through
+/// the constructors, so that the same limits apply
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ReconnectPolicyFields {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    stable_after: Duration,
+}
+
+impl From<ReconnectPolicyFields> for ReconnectPolicy {
+    fn from(fields: ReconnectPolicyFields) -> Self {
+        ReconnectPolicy::exponential(fields.initial_delay, fields.max_delay)
+            .with_multiplier(fields.multiplier)
+            .with_jitter(fields.jitter)
+            .with_stable_after(fields.stable_after)
+    }
+}
+
+impl ReconnectPolicy {
+    /// Always wait the same amount of time
+    pub fn fixed(delay: Duration) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: delay,
+            max_delay: delay,
+            multiplier: 1.0,
+            jitter: 0.0,
+            stable_after: Duration::ZERO,
+        }
+    }
+
+    /// Double the delay after every failed attempt, up to the maximum
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            multiplier: 2.0,
+            jitter: 0.0,
+            stable_after: Duration::ZERO,
+        }
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> ReconnectPolicy {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Fraction (clamped to between 0 and 1) by which each delay may be randomly reduced
+    pub fn with_jitter(mut self, jitter: f64) -> ReconnectPolicy {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// Only go back to the initial delay once a connection has stayed up this long; a
+    /// connection lost sooner carries on from the delay already reached
+    pub fn with_stable_after(mut self, window: Duration) -> ReconnectPolicy {
+        self.stable_after = window;
+        self
+    }
+
+    pub fn stable_after(&self) -> Duration {
+        self.stable_after
+    }
+
+    /// Whether a connection which was up for this long resets the delay
+    pub fn is_stable(&self, connected_for: Duration) -> bool {
+        connected_for >= self.stable_after
+    }
+
+    /// The delay before the given attempt (counting from zero for the first attempt
+    /// after the connection was lost)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let base = base.min(self.max_delay.as_secs_f64());
+        let factor = if self.jitter > 0.0 {
+            1.0 - self.jitter * rand::thread_rng().gen::<f64>()
+        } else {
+            1.0
+        };
+        Duration::from_secs_f64(base * factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReconnectPolicy;
+
+    #[test]
+    fn exponential_backoff() {
+        let policy =
+            ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(200));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+        assert_eq!(policy.delay(10), Duration::from_secs(1));
+
+        assert_eq!(ReconnectPolicy::default().delay(5), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn stable_window() {
+        assert!(ReconnectPolicy::default().is_stable(Duration::ZERO));
+        let policy =
+            ReconnectPolicy::exponential(Duration::from_millis(100), Duration::from_secs(1))
+                .with_stable_after(Duration::from_secs(10));
+        assert!(!policy.is_stable(Duration::from_secs(9)));
+        assert!(policy.is_stable(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn jitter_spreads_delays() {
+        let delays: Vec<Duration> = (0..20)
+            .map(|_| {
+                ReconnectPolicy::exponential(Duration::from_secs(1), Duration::from_secs(30))
+                    .with_jitter(0.5)
+                    .delay(3)
+            })
+            .collect();
+        assert!(delays
+            .iter()
+            .all(|d| *d >= Duration::from_secs(4) && *d <= Duration::from_secs(8)));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        let without_jitter =
+            ReconnectPolicy::exponential(Duration::from_secs(1), Duration::from_secs(30));
+        assert_eq!(without_jitter.delay(3), without_jitter.delay(3));
+    }
+}
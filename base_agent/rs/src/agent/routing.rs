@@ -0,0 +1,35 @@
+use crate::TetherOrCustomTopic;
+
+/// What happened when an incoming message was offered to a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RouteOutcome {
+    /// The message is not for this route
+    NoMatch,
+    /// The message was handled (even if it could not be decoded)
+    Delivered,
+    /// Nobody is listening any more, so the route can be removed
+    Closed,
+}
+
+/// A handler which gets first refusal on incoming messages, in the connection thread,
+/// before they are queued for `check_messages`
+pub(crate) type MessageRoute = Box<dyn Fn(&TetherOrCustomTopic, &[u8]) -> RouteOutcome + Send>;
+
+/// Offer the message to every route, dropping any that have closed. Returns true
+/// if at least one route handled it.
+pub(crate) fn route_message(
+    routes: &mut Vec<MessageRoute>,
+    topic: &TetherOrCustomTopic,
+    payload: &[u8],
+) -> bool {
+    let mut delivered = false;
+    routes.retain(|route| match route(topic, payload) {
+        RouteOutcome::NoMatch => true,
+        RouteOutcome::Delivered => {
+            delivered = true;
+            true
+        }
+        RouteOutcome::Closed => false,
+    });
+    delivered
+}
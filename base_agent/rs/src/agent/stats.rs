@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// A snapshot of the connection history of a Tether Agent, useful as a quick
+/// health summary (e.g. for dashboards) without any external instrumentation.
+///
+/// The MQTT client reconnects automatically after an error; every time the connection
+/// is re-established after having been lost, this counts as one reconnect.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    reconnect_count: u32,
+    last_disconnect_reason: Option<String>,
+    total_downtime: Duration,
+    /// Monotonic, so that the downtime is not thrown off by clock adjustments
+    disconnected_since: Option<Instant>,
+    last_connect_duration: Option<Duration>,
+}
+
+impl ConnectionStats {
+    /// How many times the connection was re-established after having been lost
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnect_count
+    }
+
+    /// The error which caused the most recent disconnection, if any
+    pub fn last_disconnect_reason(&self) -> Option<&str> {
+        self.last_disconnect_reason.as_deref()
+    }
+
+    /// Cumulative time spent disconnected (after the initial connection), including
+    /// the current outage if the connection is down right now
+    pub fn total_downtime(&self) -> Duration {
+        match self.disconnected_since {
+            Some(t) => self.total_downtime + t.elapsed(),
+            None => self.total_downtime,
+        }
+    }
+
+    /// True if the connection has been lost and not (yet) re-established
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected_since.is_some()
+    }
+
+    /// How long the most recent (re)connection took, from starting the attempt until the
+    /// broker acknowledged it; None if never connected
+    pub fn last_connect_duration(&self) -> Option<Duration> {
+        self.last_connect_duration
+    }
+
+    pub(crate) fn on_connected(&mut self, connect_duration: Duration) {
+        self.last_connect_duration = Some(connect_duration);
+        if let Some(t) = self.disconnected_since.take() {
+            self.reconnect_count += 1;
+            self.total_downtime += t.elapsed();
+        }
+    }
+
+    pub(crate) fn on_disconnected(&mut self, reason: String) {
+        self.last_disconnect_reason = Some(reason);
+        if self.disconnected_since.is_none() {
+            self.disconnected_since = Some(Instant::now());
+        }
+    }
+}
+
+/// How many messages (and bytes of payload) went through a Plug, and how often.
+///
+/// For Output Plugs this counts what was handed to the MQTT client, per topic (or Topic
+/// Template); for Input Plugs, every incoming message matching the Plug's subscription,
+/// whether or not it was taken by `check_messages`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MessageStats {
+    message_count: u64,
+    byte_count: u64,
+    first_message: Option<SystemTime>,
+    last_message: Option<SystemTime>,
+}
+
+impl MessageStats {
+    pub fn message_count(&self) -> u64 {
+        self.message_count
+    }
+
+    /// Total size of the payloads (as sent, i.e. after any encryption)
+    pub fn byte_count(&self) -> u64 {
+        self.byte_count
+    }
+
+    pub fn last_message(&self) -> Option<SystemTime> {
+        self.last_message
+    }
+
+    /// Average messages per second, from the first message until now
+    pub fn rate(&self) -> f64 {
+        self.per_second(self.message_count)
+    }
+
+    /// Average bytes of payload per second, from the first message until now: e.g. to
+    /// find out which Output Plug uses most of a constrained link
+    pub fn byte_rate(&self) -> f64 {
+        self.per_second(self.byte_count)
+    }
+
+    fn per_second(&self, count: u64) -> f64 {
+        let elapsed = self
+            .first_message
+            .and_then(|t| t.elapsed().ok())
+            .unwrap_or_default();
+        if elapsed.is_zero() {
+            0.0
+        } else {
+            count as f64 / elapsed.as_secs_f64()
+        }
+    }
+}
+
+/// The counts behind one `MessageStats`, updated without locking; times are kept as
+/// microseconds since the Unix epoch, with zero for none
+#[derive(Debug, Default)]
+struct MessageCounter {
+    message_count: AtomicU64,
+    byte_count: AtomicU64,
+    first_message: AtomicU64,
+    last_message: AtomicU64,
+}
+
+impl MessageCounter {
+    fn record(&self, bytes: usize) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.message_count.fetch_add(1, Ordering::Relaxed);
+        self.byte_count.fetch_add(bytes as u64, Ordering::Relaxed);
+        let _ = self
+            .first_message
+            .compare_exchange(0, now, Ordering::Relaxed, Ordering::Relaxed);
+        self.last_message.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> MessageStats {
+        let time = |micros: &AtomicU64| match micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(UNIX_EPOCH + Duration::from_micros(micros)),
+        };
+        MessageStats {
+            message_count: self.message_count.load(Ordering::Relaxed),
+            byte_count: self.byte_count.load(Ordering::Relaxed),
+            first_message: time(&self.first_message),
+            last_message: time(&self.last_message),
+        }
+    }
+}
+
+/// Message counts kept by the Agent: sent per Output Plug topic (or Topic Template), and
+/// received per Input Plug subscription. Both are only ever as large as the number of
+/// Plugs, however many concrete topics a wildcard subscription matches; and recording a
+/// message only takes a shared lock, without allocating.
+#[derive(Debug, Default)]
+pub(crate) struct MessageStatsStore {
+    sent: RwLock<HashMap<String, Arc<MessageCounter>>>,
+    received: RwLock<Vec<(String, MessageCounter)>>,
+}
+
+impl MessageStatsStore {
+    pub fn record_sent(&self, plug_topic: &str, bytes: usize) {
+        let counter = self
+            .sent
+            .read()
+            .expect("failed to lock")
+            .get(plug_topic)
+            .cloned();
+        let counter = counter.unwrap_or_else(|| {
+            Arc::clone(
+                self.sent
+                    .write()
+                    .expect("failed to lock")
+                    .entry(String::from(plug_topic))
+                    .or_default(),
+            )
+        });
+        counter.record(bytes);
+    }
+
+    /// Start counting the messages received on this subscription (if not already)
+    pub fn watch_received(&self, topic_filter: &str) {
+        let mut received = self.received.write().expect("failed to lock");
+        if !received.iter().any(|(filter, _)| filter == topic_filter) {
+            received.push((String::from(topic_filter), MessageCounter::default()));
+        }
+    }
+
+    pub fn record_received(&self, topic: &str, bytes: usize) {
+        for (filter, counter) in self.received.read().expect("failed to lock").iter() {
+            if rumqttc::matches(topic, filter) {
+                counter.record(bytes);
+            }
+        }
+    }
+
+    pub fn sent(&self, plug_topic: &str) -> MessageStats {
+        self.sent
+            .read()
+            .expect("failed to lock")
+            .get(plug_topic)
+            .map(|counter| counter.snapshot())
+            .unwrap_or_default()
+    }
+
+    pub fn all_sent(&self) -> Vec<(String, MessageStats)> {
+        self.sent
+            .read()
+            .expect("failed to lock")
+            .iter()
+            .map(|(plug_topic, counter)| (plug_topic.clone(), counter.snapshot()))
+            .collect()
+    }
+
+    pub fn received(&self, topic_filter: &str) -> MessageStats {
+        self.received
+            .read()
+            .expect("failed to lock")
+            .iter()
+            .find(|(filter, _)| filter == topic_filter)
+            .map(|(_, counter)| counter.snapshot())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use log::debug;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::{InputPlugDefinition, PlugDefinitionCommon};
+
+use super::{decode, routing::RouteOutcome, DecodeError, TetherAgent};
+
+/// The Stream returned by `TetherAgent::plug_stream`
+struct PlugStream<T> {
+    receiver: mpsc::UnboundedReceiver<Result<T, DecodeError>>,
+}
+
+impl<T> Stream for PlugStream<T> {
+    type Item = Result<T, DecodeError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl TetherAgent {
+    /// A Stream of every message matching this Input Plug, already decoded (and decrypted,
+    /// if the Plug has an encryption key): the async equivalent of
+    /// `TypedInputPlug::into_channel`. Messages which cannot be decoded are yielded as
+    /// errors, rather than skipped.
+    ///
+    /// As with the channel, messages delivered this way are no longer returned by
+    /// `check_messages`, and dropping the Stream stops the routing again.
+    pub fn plug_stream<T: DeserializeOwned + Send + 'static>(
+        &self,
+        plug: &InputPlugDefinition,
+    ) -> impl Stream<Item = Result<T, DecodeError>> + Unpin {
+        let (tx, receiver) = mpsc::unbounded_channel();
+        let plug = plug.matcher();
+        let log_target = String::from(self.log_target());
+        self.add_route(Box::new(move |topic, payload| {
+            if !plug.matches(topic) {
+                return RouteOutcome::NoMatch;
+            }
+            let decoded = match plug.decrypt(payload) {
+                Ok(payload) => decode::<T>(&payload),
+                Err(e) => Err(DecodeError::Uncategorized(e.to_string())),
+            };
+            match tx.send(decoded) {
+                Ok(()) => RouteOutcome::Delivered,
+                Err(_) => {
+                    debug!(
+                        target: &log_target,
+                        "Stream for Plug \"{}\" dropped; stop routing",
+                        plug.name()
+                    );
+                    RouteOutcome::Closed
+                }
+            }
+        }));
+        PlugStream { receiver }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::poll_fn, pin::Pin, time::Duration};
+
+    use futures_core::Stream;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    use crate::{PlugDefinition, PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        level: u8,
+    }
+
+    async fn next_item<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        let next = poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx));
+        tokio::time::timeout(Duration::from_secs(5), next)
+            .await
+            .expect("timed out waiting for the Stream")
+    }
+
+    #[tokio::test]
+    async fn typed_plug_stream() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .id(Some(&Uuid::new_v4().to_string()))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let PlugDefinition::InputPlug(input) = PlugOptionsBuilder::create_input("readings")
+            .wait_for_subscribe_response(true)
+            .build(&mut tether_agent)
+            .unwrap()
+        else {
+            panic!("expected an Input Plug");
+        };
+        let output = PlugOptionsBuilder::create_output("readings")
+            .build(&mut tether_agent)
+            .unwrap();
+        let other = PlugOptionsBuilder::create_output("unrelated")
+            .build(&mut tether_agent)
+            .unwrap();
+        let mut stream = tether_agent.plug_stream::<Reading>(&input);
+
+        tether_agent
+            .encode_and_publish(&other, Reading { level: 1 })
+            .unwrap();
+        tether_agent
+            .encode_and_publish(&output, Reading { level: 2 })
+            .unwrap();
+        tether_agent
+            .encode_and_publish(&output, "not a reading")
+            .unwrap();
+
+        // Only messages for the Plug, with decode errors as errors
+        let first = next_item(&mut stream).await.unwrap();
+        assert_eq!(first.unwrap(), Reading { level: 2 });
+        let second = next_item(&mut stream).await.unwrap();
+        assert!(second.is_err());
+        assert!(tether_agent.check_messages().is_none());
+    }
+}
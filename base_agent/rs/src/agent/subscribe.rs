@@ -0,0 +1,347 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    sync::{mpsc, Mutex, MutexGuard},
+};
+
+use rumqttc::{Client, ClientError, QoS, SubAck, SubscribeReasonCode};
+
+use crate::three_part_topic::topic_filter_matches;
+
+/// Whether an Input Plug receives the retained message (if any) which the broker sends
+/// straight away on subscribing, as well as live messages.
+///
+/// These are the MQTT 5 retain-handling options. This Agent uses MQTT 3.1.1, where the
+/// broker always sends retained messages on subscribing, so `DontSend` is done by the
+/// Agent instead: retained messages delivered on subscribing are dropped on arrival.
+/// Retained messages are only dropped if every Input Plug whose topic matches them asked
+/// for `DontSend`, and only for as long as such a Plug is subscribed. 3.1.1 subscriptions
+/// carry no retain handling, so the Agent cannot tell whether a subscription is new to the
+/// broker (with `clean_session(false)` it may already exist in the session), and
+/// `SendIfNew` behaves like `Send`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetainHandling {
+    #[default]
+    Send,
+    SendIfNew,
+    DontSend,
+}
+
+/// The broker's response (SUBACK) to a subscription request, kept for diagnostics;
+/// e.g. to find out whether the broker granted a lower QoS than was requested, or
+/// refused the subscription altogether (typically because of access control rules).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscribeResponse {
+    packet_id: u16,
+    granted: Vec<Option<i32>>,
+}
+
+impl SubscribeResponse {
+    pub fn packet_id(&self) -> u16 {
+        self.packet_id
+    }
+
+    /// The QoS granted for each topic filter in the request, in the same order;
+    /// `None` means the subscription to that filter was refused
+    pub fn return_codes(&self) -> &[Option<i32>] {
+        &self.granted
+    }
+
+    /// The QoS granted for the (first) topic filter, or `None` if it was refused
+    pub fn granted_qos(&self) -> Option<i32> {
+        self.granted.first().copied().flatten()
+    }
+
+    /// True if every topic filter was granted, at any QoS
+    pub fn is_success(&self) -> bool {
+        self.granted.iter().all(|g| g.is_some())
+    }
+}
+
+impl From<&SubAck> for SubscribeResponse {
+    fn from(suback: &SubAck) -> Self {
+        SubscribeResponse {
+            packet_id: suback.pkid,
+            granted: suback
+                .return_codes
+                .iter()
+                .map(|code| match code {
+                    SubscribeReasonCode::Success(qos) => Some(match qos {
+                        QoS::AtMostOnce => 0,
+                        QoS::AtLeastOnce => 1,
+                        QoS::ExactlyOnce => 2,
+                    }),
+                    SubscribeReasonCode::Failure => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The subscriptions handed to the client, in the order it sends them, so that each SubAck
+/// (which only carries a packet ID) can be matched to its topic filter and to whoever is
+/// waiting for it; and the filters the broker has responded to, for as long as the
+/// connection (or session) lasts
+#[derive(Default)]
+pub(crate) struct SubscriptionRegistry {
+    /// Held while a subscription is handed to the client, so that the queue is in the same
+    /// order as the client's requests
+    sending: Mutex<()>,
+    state: Mutex<RegistryState>,
+}
+
+/// A subscription handed to the client
+#[derive(Default)]
+struct Requested {
+    /// None for subscriptions which are not listed
+    topic: Option<String>,
+    /// Where to send the broker's response, if anyone is waiting for it
+    waiter: Option<mpsc::Sender<SubscribeResponse>>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    /// Handed to the client, not sent yet
+    queued: VecDeque<Requested>,
+    /// Sent, and waiting for a SubAck, by packet ID
+    sent: HashMap<u16, Requested>,
+    /// The filters the broker has responded to, and whether it granted them
+    confirmed: HashMap<String, bool>,
+    /// Those confirmed before the connection was lost, which still hold if the broker
+    /// kept the session
+    suspended: HashMap<String, bool>,
+}
+
+impl SubscriptionRegistry {
+    fn state(&self) -> MutexGuard<'_, RegistryState> {
+        self.state.lock().expect("failed to lock mutex")
+    }
+
+    /// Hand the subscription to the client, to be matched to its SubAck; a `topic` of None
+    /// is sent all the same, but not listed (e.g. a brief check for a retained message).
+    /// If `wait_for_response`, the broker's response to this very subscription is sent to
+    /// the returned receiver, which is disconnected instead if the connection is lost first.
+    pub(crate) fn subscribe(
+        &self,
+        client: &Client,
+        topic: Option<&str>,
+        broker_topic: String,
+        qos: QoS,
+        wait_for_response: bool,
+    ) -> Result<Option<mpsc::Receiver<SubscribeResponse>>, ClientError> {
+        let (waiter, response) = match wait_for_response {
+            true => {
+                let (tx, rx) = mpsc::channel();
+                (Some(tx), Some(rx))
+            }
+            false => (None, None),
+        };
+        let _sending = self.sending.lock().expect("failed to lock mutex");
+        self.state().queued.push_back(Requested {
+            topic: topic.map(String::from),
+            waiter,
+        });
+        let result = client.subscribe(broker_topic, qos);
+        if result.is_err() {
+            self.state().queued.pop_back();
+        }
+        result.map(|_| response)
+    }
+
+    /// The client sent the next subscription, with this packet ID
+    pub(crate) fn sent(&self, packet_id: u16) {
+        let mut state = self.state();
+        if let Some(requested) = state.queued.pop_front() {
+            state.sent.insert(packet_id, requested);
+        }
+    }
+
+    /// The broker responded to a subscription, granting or refusing it
+    pub(crate) fn acknowledged(&self, response: &SubscribeResponse) {
+        let mut state = self.state();
+        if let Some(requested) = state.sent.remove(&response.packet_id()) {
+            if let Some(topic) = requested.topic {
+                state.confirmed.insert(topic, response.is_success());
+            }
+            if let Some(waiter) = requested.waiter {
+                // Nobody may be waiting any more, e.g. after timing out
+                let _ = waiter.send(response.clone());
+            }
+        }
+    }
+
+    /// Whether the broker has granted a subscription to this filter on this connection
+    pub(crate) fn is_active(&self, topic: &str) -> bool {
+        self.state().confirmed.get(topic) == Some(&true)
+    }
+
+    /// Unsubscribed from this filter
+    pub(crate) fn forget(&self, topic: &str) {
+        let mut state = self.state();
+        state.confirmed.remove(topic);
+        state.suspended.remove(topic);
+    }
+
+    /// Subscriptions which were not acknowledged are lost along with the connection, and
+    /// those which were only survive if the broker keeps the session
+    pub(crate) fn connection_lost(&self) {
+        let mut state = self.state();
+        state.sent.clear();
+        let confirmed = mem::take(&mut state.confirmed);
+        state.suspended.extend(confirmed);
+    }
+
+    /// (Re)connected; the subscriptions confirmed before still hold if the session did
+    pub(crate) fn connected(&self, session_present: bool) {
+        let mut state = self.state();
+        let suspended = mem::take(&mut state.suspended);
+        if session_present {
+            for (topic, granted) in suspended {
+                state.confirmed.entry(topic).or_insert(granted);
+            }
+        }
+    }
+}
+
+/// How many Input Plugs are subscribed to each topic filter (so that one Plug leaving a
+/// topic does not unsubscribe the others), and how many of them asked for
+/// `RetainHandling::DontSend`; shared with the connection thread(s), which drop a retained
+/// message only if every Plug whose filter matches it asked for this
+#[derive(Default)]
+pub(crate) struct PlugSubscriptions {
+    filters: Mutex<HashMap<String, PlugCount>>,
+}
+
+#[derive(Default)]
+struct PlugCount {
+    plugs: usize,
+    dont_send: usize,
+}
+
+impl PlugSubscriptions {
+    /// A Plug subscribes to the filter
+    pub(crate) fn join(&self, filter: &str, retain_handling: RetainHandling) {
+        let mut filters = self.filters.lock().expect("failed to lock mutex");
+        let count = filters.entry(String::from(filter)).or_default();
+        count.plugs += 1;
+        if retain_handling == RetainHandling::DontSend {
+            count.dont_send += 1;
+        }
+    }
+
+    /// A Plug leaves the filter; returns true if no other Plug uses it, i.e. the filter
+    /// should be unsubscribed
+    pub(crate) fn leave(&self, filter: &str, retain_handling: RetainHandling) -> bool {
+        let mut filters = self.filters.lock().expect("failed to lock mutex");
+        let Some(count) = filters.get_mut(filter) else {
+            return true;
+        };
+        count.plugs = count.plugs.saturating_sub(1);
+        if retain_handling == RetainHandling::DontSend {
+            count.dont_send = count.dont_send.saturating_sub(1);
+        }
+        if count.plugs > 0 {
+            return false;
+        }
+        filters.remove(filter);
+        true
+    }
+
+    /// Whether a retained message on this topic should be dropped
+    pub(crate) fn drops_retained(&self, topic: &str) -> bool {
+        let filters = self.filters.lock().expect("failed to lock mutex");
+        let mut matching = filters
+            .iter()
+            .filter(|(filter, _)| topic_filter_matches(filter, topic))
+            .peekable();
+        matching.peek().is_some() && matching.all(|(_, count)| count.dont_send == count.plugs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::{QoS, SubAck, SubscribeReasonCode};
+
+    use super::{Requested, SubscribeResponse, SubscriptionRegistry};
+
+    fn suback(packet_id: u16, granted: bool) -> SubscribeResponse {
+        let code = if granted {
+            SubscribeReasonCode::Success(QoS::AtLeastOnce)
+        } else {
+            SubscribeReasonCode::Failure
+        };
+        SubscribeResponse::from(&SubAck::new(packet_id, vec![code]))
+    }
+
+    #[test]
+    fn subacks_matched_by_packet_id() {
+        let registry = SubscriptionRegistry::default();
+        {
+            let mut state = registry.state();
+            for topic in [Some("a/b/c"), None, Some("x/y/z")] {
+                state.queued.push_back(Requested {
+                    topic: topic.map(String::from),
+                    waiter: None,
+                });
+            }
+        }
+        registry.sent(7);
+        registry.sent(8);
+        registry.sent(9);
+
+        // Responses can come in any order; refusals are not active
+        registry.acknowledged(&suback(9, false));
+        registry.acknowledged(&suback(8, true));
+        assert!(!registry.is_active("a/b/c"));
+        registry.acknowledged(&suback(7, true));
+        assert!(registry.is_active("a/b/c"));
+        assert!(!registry.is_active("x/y/z"));
+
+        // Only a kept session keeps its subscriptions
+        registry.connection_lost();
+        assert!(!registry.is_active("a/b/c"));
+        registry.connected(true);
+        assert!(registry.is_active("a/b/c"));
+        registry.connection_lost();
+        registry.connected(false);
+        assert!(!registry.is_active("a/b/c"));
+    }
+
+    #[test]
+    fn responses_go_to_their_own_waiters() {
+        let registry = SubscriptionRegistry::default();
+        let mut responses = Vec::new();
+        {
+            let mut state = registry.state();
+            for topic in ["first", "second"] {
+                let (tx, rx) = std::sync::mpsc::channel();
+                state.queued.push_back(Requested {
+                    topic: Some(String::from(topic)),
+                    waiter: Some(tx),
+                });
+                responses.push(rx);
+            }
+        }
+        registry.sent(1);
+        registry.sent(2);
+
+        // The later subscription is answered first, and refused
+        registry.acknowledged(&suback(2, false));
+        registry.acknowledged(&suback(1, true));
+        assert!(responses[0].try_recv().unwrap().is_success());
+        assert!(!responses[1].try_recv().unwrap().is_success());
+
+        // A waiter whose subscription is lost along with the connection hears so
+        let (tx, rx) = std::sync::mpsc::channel();
+        registry.state().queued.push_back(Requested {
+            topic: None,
+            waiter: Some(tx),
+        });
+        registry.sent(3);
+        registry.connection_lost();
+        assert_eq!(
+            rx.try_recv(),
+            Err(std::sync::mpsc::TryRecvError::Disconnected)
+        );
+    }
+}
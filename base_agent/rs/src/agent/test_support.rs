@@ -0,0 +1,21 @@
+use std::{
+    io,
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+/// Copy everything between the two streams, in both directions, until closed; for tests
+/// which need a relay between the Agent and the broker
+pub(crate) fn pipe(a: TcpStream, b: TcpStream) -> io::Result<()> {
+    let (mut a_read, mut b_write) = (a.try_clone()?, b.try_clone()?);
+    thread::spawn(move || {
+        let _ = io::copy(&mut a_read, &mut b_write);
+        let _ = b_write.shutdown(Shutdown::Write);
+    });
+    let (mut b_read, mut a_write) = (b, a);
+    thread::spawn(move || {
+        let _ = io::copy(&mut b_read, &mut a_write);
+        let _ = a_write.shutdown(Shutdown::Write);
+    });
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use rumqttc::tokio_rustls::rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+
+/// Parse the name to verify the broker's certificate against; see
+/// `TetherAgentOptionsBuilder::server_name`
+pub(crate) fn parse_server_name(server_name: &str) -> anyhow::Result<ServerName<'static>> {
+    ServerName::try_from(String::from(server_name))
+        .map_err(|_| anyhow!("Invalid TLS server name \"{}\"", server_name))
+}
+
+/// Verifies the broker's certificate as usual, but against the given server name instead
+/// of the host that was connected to
+#[derive(Debug)]
+pub(crate) struct ServerNameVerifier {
+    server_name: ServerName<'static>,
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+impl ServerNameVerifier {
+    pub(crate) fn new(
+        server_name: ServerName<'static>,
+        roots: Arc<RootCertStore>,
+    ) -> anyhow::Result<Self> {
+        Ok(ServerNameVerifier {
+            server_name,
+            inner: WebPkiServerVerifier::builder(roots).build()?,
+        })
+    }
+}
+
+impl ServerCertVerifier for ServerNameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            &self.server_name,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
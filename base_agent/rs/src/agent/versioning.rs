@@ -0,0 +1,123 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{DecodeError, HEADER_KIND_VERSION, HEADER_MARKER};
+
+const HEADER_LENGTH: usize = 4;
+
+/// Why a versioned payload could not be decoded
+#[derive(Debug)]
+pub enum VersionedDecodeError {
+    /// The payload has no version header, e.g. it was sent with plain `encode_and_publish`
+    MissingVersion,
+    /// The payload was encoded with a different schema version than the one expected
+    IncompatibleVersion { expected: u16, found: u16 },
+    /// The version matched, but the payload itself could not be decoded
+    Decode(DecodeError),
+}
+
+impl fmt::Display for VersionedDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingVersion => write!(f, "payload has no schema version header"),
+            Self::IncompatibleVersion { expected, found } => write!(
+                f,
+                "payload has schema version {} but version {} was expected",
+                found, expected
+            ),
+            Self::Decode(e) => write!(f, "failed to decode versioned payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VersionedDecodeError {}
+
+/// Encode the data as MessagePack, preceded by a 4-byte header: the marker and kind
+/// bytes, then the schema version (big-endian u16).
+pub fn encode_versioned<T: Serialize>(
+    data: T,
+    version: u16,
+) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut payload = Vec::with_capacity(64);
+    payload.push(HEADER_MARKER);
+    payload.push(HEADER_KIND_VERSION);
+    payload.extend_from_slice(&version.to_be_bytes());
+    rmp_serde::encode::write_named(&mut payload, &data)?;
+    Ok(payload)
+}
+
+/// Returns the schema version of the payload, or None if it has no version header
+pub fn payload_version(payload: &[u8]) -> Option<u16> {
+    match payload {
+        [HEADER_MARKER, HEADER_KIND_VERSION, high, low, ..] => {
+            Some(u16::from_be_bytes([*high, *low]))
+        }
+        _ => None,
+    }
+}
+
+/// Decode a payload that was encoded with `encode_versioned` (or published with
+/// `TetherAgent::publish_versioned`), failing if the version is not the expected one.
+pub fn decode_versioned<T: DeserializeOwned>(
+    payload: &[u8],
+    expected_version: u16,
+) -> Result<T, VersionedDecodeError> {
+    match payload_version(payload) {
+        None => Err(VersionedDecodeError::MissingVersion),
+        Some(found) if found != expected_version => {
+            Err(VersionedDecodeError::IncompatibleVersion {
+                expected: expected_version,
+                found,
+            })
+        }
+        Some(_) => {
+            rmp_serde::from_slice(&payload[HEADER_LENGTH..]).map_err(VersionedDecodeError::Decode)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{decode_versioned, encode_versioned, payload_version, VersionedDecodeError};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        value: f32,
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = encode_versioned(Reading { value: 0.5 }, 2).unwrap();
+        assert_eq!(payload_version(&payload), Some(2));
+        assert_eq!(
+            decode_versioned::<Reading>(&payload, 2).unwrap(),
+            Reading { value: 0.5 }
+        );
+    }
+
+    #[test]
+    fn incompatible_or_missing() {
+        let payload = encode_versioned(Reading { value: 0.5 }, 3).unwrap();
+        assert!(matches!(
+            decode_versioned::<Reading>(&payload, 2),
+            Err(VersionedDecodeError::IncompatibleVersion {
+                expected: 2,
+                found: 3
+            })
+        ));
+
+        let unversioned = rmp_serde::to_vec_named(&Reading { value: 0.5 }).unwrap();
+        assert_eq!(payload_version(&unversioned), None);
+        assert!(matches!(
+            decode_versioned::<Reading>(&unversioned, 2),
+            Err(VersionedDecodeError::MissingVersion)
+        ));
+        assert!(matches!(
+            decode_versioned::<Reading>(&[], 2),
+            Err(VersionedDecodeError::MissingVersion)
+        ));
+    }
+}
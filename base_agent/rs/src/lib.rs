@@ -1,6 +1,10 @@
 pub mod agent;
 pub mod plugs;
 
+/// The target of all log messages from this crate, so that Tether's own logging can be
+/// filtered separately from the application's, e.g. `RUST_LOG=tether=debug`
+pub const LOG_TARGET: &str = "tether";
+
 pub use agent::*;
 pub use plugs::*;
 pub use rumqttc as mqtt;
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::LOG_TARGET;
+
+#[derive(Debug)]
+struct TopicState {
+    /// Monotonic, so that a clock adjustment cannot hold back (or rush out) the next value
+    last_sent: Instant,
+    pending: Option<Vec<u8>>,
+}
+
+/// Limits publishing on an Output Plug to at most one message per interval (per topic),
+/// for "latest value" state which may change faster than anyone needs to know about it.
+///
+/// Updates which arrive too soon are not sent, but the most recent of them is kept so that
+/// it can be sent once the interval has passed (see `TetherAgent::flush_coalesced`);
+/// anything older is dropped.
+#[derive(Debug)]
+pub struct Coalescer {
+    interval: Duration,
+    topics: Mutex<HashMap<String, TopicState>>,
+    log_target: String,
+}
+
+impl Coalescer {
+    pub fn new(interval: Duration) -> Coalescer {
+        Coalescer {
+            interval,
+            topics: Mutex::new(HashMap::new()),
+            log_target: String::from(LOG_TARGET),
+        }
+    }
+
+    /// Log under this target instead of `LOG_TARGET`, e.g. that of the Agent the Plug
+    /// belongs to (see `TetherAgentOptionsBuilder::label`)
+    pub fn with_log_target(mut self, log_target: &str) -> Coalescer {
+        self.log_target = String::from(log_target);
+        self
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns true if this payload should be sent right away (and records it as sent);
+    /// otherwise keeps it as the pending value for the topic, replacing any earlier one,
+    /// and returns false.
+    pub fn offer(&self, topic: &str, payload: &[u8]) -> bool {
+        let now = Instant::now();
+        let mut topics = self.topics.lock().expect("failed to lock mutex");
+        match topics.get_mut(topic) {
+            Some(state) if now.duration_since(state.last_sent) < self.interval => {
+                debug!(target: &self.log_target, "Coalesced update on topic \"{}\"", topic);
+                state.pending = Some(payload.to_vec());
+                false
+            }
+            Some(state) => {
+                state.last_sent = now;
+                state.pending = None;
+                true
+            }
+            None => {
+                topics.insert(
+                    String::from(topic),
+                    TopicState {
+                        last_sent: now,
+                        pending: None,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Take any pending values whose interval has passed, recording them as sent
+    pub fn take_due(&self) -> Vec<(String, Vec<u8>)> {
+        let now = Instant::now();
+        let mut topics = self.topics.lock().expect("failed to lock mutex");
+        topics
+            .iter_mut()
+            .filter(|(_, state)| {
+                state.pending.is_some() && now.duration_since(state.last_sent) >= self.interval
+            })
+            .filter_map(|(topic, state)| {
+                state.last_sent = now;
+                state.pending.take().map(|p| (topic.clone(), p))
+            })
+            .collect()
+    }
+
+    /// True if there are values waiting to be sent
+    pub fn has_pending(&self) -> bool {
+        self.topics
+            .lock()
+            .expect("failed to lock mutex")
+            .values()
+            .any(|s| s.pending.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Coalescer;
+
+    #[test]
+    fn keeps_only_latest() {
+        let coalescer = Coalescer::new(Duration::from_millis(50));
+        assert!(coalescer.offer("a/b/c", &[0]));
+        assert!(!coalescer.offer("a/b/c", &[1]));
+        assert!(!coalescer.offer("a/b/c", &[2]));
+        // Other topics are independent
+        assert!(coalescer.offer("a/b/d", &[0]));
+
+        assert!(coalescer.has_pending());
+        assert!(coalescer.take_due().is_empty());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(coalescer.take_due(), vec![(String::from("a/b/c"), vec![2])]);
+        assert!(!coalescer.has_pending());
+        assert!(!coalescer.offer("a/b/c", &[3]));
+    }
+}
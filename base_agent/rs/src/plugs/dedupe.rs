@@ -0,0 +1,194 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::LOG_TARGET;
+
+/// Drops repeated messages (e.g. QoS 1 redeliveries after a reconnect) that arrive within a
+/// given time window of the original.
+///
+/// Messages are keyed by topic plus either the value of a sequence field in the (MessagePack)
+/// payload, if one is given, or otherwise the complete payload contents.
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: Duration,
+    sequence_field: Option<String>,
+    seen: Mutex<Seen>,
+    /// Topics already warned about for lacking the sequence field, so that this is only
+    /// logged once per topic rather than for every message
+    missing_field_topics: Mutex<HashSet<String>>,
+    log_target: String,
+}
+
+/// What makes messages equivalent: the whole key is kept (rather than a hash of it), so
+/// that distinct messages can never be taken for duplicates
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    topic: String,
+    /// The sequence field's value (as JSON), or else the whole payload
+    content: Vec<u8>,
+}
+
+/// The keys of the messages seen within the window, in order of arrival (to expire them;
+/// timed with a monotonic clock, so that clock adjustments do not matter) and as a set (to
+/// look them up)
+#[derive(Debug, Default)]
+struct Seen {
+    arrivals: VecDeque<(Arc<Key>, Instant)>,
+    keys: HashSet<Arc<Key>>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Duration, sequence_field: Option<&str>) -> Deduplicator {
+        Deduplicator {
+            window,
+            sequence_field: sequence_field.map(String::from),
+            seen: Mutex::new(Seen::default()),
+            missing_field_topics: Mutex::new(HashSet::new()),
+            log_target: String::from(LOG_TARGET),
+        }
+    }
+
+    /// Log under this target instead of `LOG_TARGET`, e.g. that of the Agent the Plug
+    /// belongs to (see `TetherAgentOptionsBuilder::label`)
+    pub fn with_log_target(mut self, log_target: &str) -> Deduplicator {
+        self.log_target = String::from(log_target);
+        self
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn sequence_field(&self) -> Option<&str> {
+        self.sequence_field.as_deref()
+    }
+
+    /// Returns true if an equivalent message was already seen within the window;
+    /// otherwise remembers this one and returns false.
+    pub fn is_duplicate(&self, topic: &str, payload: &[u8]) -> bool {
+        let Some(key) = self.key(topic, payload) else {
+            return false;
+        };
+        let now = Instant::now();
+        let mut guard = self.seen.lock().expect("failed to lock mutex");
+        let seen = &mut *guard;
+        while let Some((k, t)) = seen.arrivals.front() {
+            if now.duration_since(*t) > self.window {
+                seen.keys.remove(k);
+                seen.arrivals.pop_front();
+            } else {
+                break;
+            }
+        }
+        // A duplicate is not remembered again, so each key is in the window at most once
+        if seen.keys.contains(&key) {
+            debug!(target: &self.log_target, "Duplicate message on topic \"{}\" dropped", topic);
+            true
+        } else {
+            let key = Arc::new(key);
+            seen.keys.insert(Arc::clone(&key));
+            seen.arrivals.push_back((key, now));
+            false
+        }
+    }
+
+    fn key(&self, topic: &str, payload: &[u8]) -> Option<Key> {
+        let content = match &self.sequence_field {
+            Some(field) => {
+                let value = rmp_serde::from_slice::<serde_json::Value>(payload)
+                    .ok()
+                    .and_then(|v| v.get(field).cloned());
+                match value {
+                    Some(v) => v.to_string().into_bytes(),
+                    None => {
+                        let first_time = self
+                            .missing_field_topics
+                            .lock()
+                            .expect("failed to lock mutex")
+                            .insert(String::from(topic));
+                        if first_time {
+                            warn!(
+                                target: &self.log_target,
+                                "No sequence field \"{}\" in message on topic \"{}\"; cannot check for duplicates (not logged again for this topic)",
+                                field, topic
+                            );
+                        } else {
+                            debug!(
+                                target: &self.log_target,
+                                "No sequence field \"{}\" in message on topic \"{}\"",
+                                field, topic
+                            );
+                        }
+                        return None;
+                    }
+                }
+            }
+            None => payload.to_vec(),
+        };
+        Some(Key {
+            topic: String::from(topic),
+            content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::Serialize;
+
+    use super::Deduplicator;
+
+    #[derive(Serialize)]
+    struct Reading {
+        seq: u32,
+        value: f32,
+    }
+
+    fn encode(seq: u32, value: f32) -> Vec<u8> {
+        rmp_serde::to_vec_named(&Reading { seq, value }).unwrap()
+    }
+
+    #[test]
+    fn same_payload_within_window() {
+        let dedupe = Deduplicator::new(Duration::from_secs(10), None);
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(0, 0.5)));
+        assert!(dedupe.is_duplicate("a/b/c", &encode(0, 0.5)));
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(1, 0.5)));
+        // Same payload on a different topic is not a duplicate
+        assert!(!dedupe.is_duplicate("a/b/other", &encode(0, 0.5)));
+        // Messages are compared in full, so distinct ones are never taken for duplicates
+        for seq in 2..10_000 {
+            assert!(!dedupe.is_duplicate("a/b/c", &encode(seq, 0.5)));
+        }
+    }
+
+    #[test]
+    fn sequence_field() {
+        let dedupe = Deduplicator::new(Duration::from_secs(10), Some("seq"));
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(7, 0.5)));
+        // Different contents, but same sequence number
+        assert!(dedupe.is_duplicate("a/b/c", &encode(7, 1.0)));
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(8, 1.0)));
+        // Without the field, nothing is dropped
+        assert!(!dedupe.is_duplicate("a/b/c", &[]));
+        assert!(!dedupe.is_duplicate("a/b/c", &[]));
+    }
+
+    #[test]
+    fn outside_window() {
+        let dedupe = Deduplicator::new(Duration::from_millis(20), None);
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(0, 0.5)));
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(!dedupe.is_duplicate("a/b/c", &encode(0, 0.5)));
+        // ...and is remembered again from then on
+        assert!(dedupe.is_duplicate("a/b/c", &encode(0, 0.5)));
+        assert_eq!(dedupe.seen.lock().unwrap().keys.len(), 1);
+    }
+}
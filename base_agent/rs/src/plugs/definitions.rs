@@ -1,7 +1,32 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 
-use super::three_part_topic::TetherOrCustomTopic;
+use crate::{
+    decrypt_payload, is_encrypted, EncryptionKey, MessageStats, RetainHandling, SubscribeResponse,
+    TetherAgent, TetherError, LOG_TARGET,
+};
+
+use super::{
+    coalesce::Coalescer,
+    dedupe::Deduplicator,
+    metadata::{PlugDescription, PlugDirection, PlugMetadata},
+    sequence::{GapDetector, SequenceGap, Sequencer},
+    three_part_topic::{topic_filter_matches, IdentityParts, TetherOrCustomTopic, ThreePartTopic},
+    topic_template::TopicTemplate,
+};
+
+fn default_log_target() -> String {
+    String::from(LOG_TARGET)
+}
 
 pub trait PlugDefinitionCommon<'a> {
     fn name(&'a self) -> &'a str;
@@ -15,6 +40,25 @@ pub struct InputPlugDefinition {
     name: String,
     topic: TetherOrCustomTopic,
     qos: i32,
+    #[serde(default)]
+    metadata: Option<PlugMetadata>,
+    /// Boxed, since it holds its own state, which Plugs without it need not make room for
+    #[serde(skip)]
+    dedupe: Option<Box<Deduplicator>>,
+    #[serde(skip)]
+    encryption_key: Option<EncryptionKey>,
+    #[serde(skip)]
+    subscribe_response: Option<SubscribeResponse>,
+    #[serde(skip)]
+    pending: Option<Arc<AtomicBool>>,
+    #[serde(skip)]
+    retain_handling: RetainHandling,
+    #[serde(skip)]
+    gap_detector: Option<GapDetector>,
+    #[serde(skip)]
+    identity_parts: IdentityParts,
+    #[serde(skip, default = "default_log_target")]
+    log_target: String,
 }
 
 impl PlugDefinitionCommon<'_> for InputPlugDefinition {
@@ -25,11 +69,15 @@ impl PlugDefinitionCommon<'_> for InputPlugDefinition {
     fn topic_str(&self) -> &str {
         match &self.topic {
             TetherOrCustomTopic::Custom(s) => {
-                debug!("Plug named \"{}\" has custom topic \"{}\"", &self.name, &s);
+                debug!(
+                    target: &self.log_target,
+                    "Plug named \"{}\" has custom topic \"{}\"", &self.name, &s
+                );
                 s
             }
             TetherOrCustomTopic::Tether(t) => {
                 debug!(
+                    target: &self.log_target,
                     "Plug named \"{}\" has Three Part topic \"{:?}\"",
                     &self.name, t
                 );
@@ -53,9 +101,185 @@ impl InputPlugDefinition {
             name: String::from(name),
             topic,
             qos: qos.unwrap_or(1),
+            metadata: None,
+            dedupe: None,
+            encryption_key: None,
+            subscribe_response: None,
+            pending: None,
+            retain_handling: RetainHandling::default(),
+            gap_detector: None,
+            identity_parts: IdentityParts::default(),
+            log_target: default_log_target(),
+        }
+    }
+
+    /// Log under this target instead of `LOG_TARGET`, as the Agent which builds the Plug
+    /// does (see `TetherAgentOptionsBuilder::label`)
+    pub fn with_log_target(mut self, log_target: &str) -> InputPlugDefinition {
+        self.dedupe = self.dedupe.map(|d| Box::new(d.with_log_target(log_target)));
+        self.gap_detector = self.gap_detector.map(|d| d.with_log_target(log_target));
+        self.log_target = String::from(log_target);
+        self
+    }
+
+    pub(crate) fn set_subscribe_response(&mut self, response: Option<SubscribeResponse>) {
+        self.subscribe_response = response;
+    }
+
+    /// A copy with just what is needed to match (and decrypt) incoming messages
+    pub(crate) fn matcher(&self) -> InputPlugDefinition {
+        InputPlugDefinition {
+            encryption_key: self.encryption_key.clone(),
+            log_target: self.log_target.clone(),
+            ..InputPlugDefinition::new(&self.name, self.topic.clone(), Some(self.qos))
+        }
+    }
+
+    /// Whether to receive the retained message sent on subscribing; see `RetainHandling`
+    pub fn with_retain_handling(mut self, retain_handling: RetainHandling) -> InputPlugDefinition {
+        self.retain_handling = retain_handling;
+        self
+    }
+
+    pub fn retain_handling(&self) -> RetainHandling {
+        self.retain_handling
+    }
+
+    pub(crate) fn set_pending(&mut self, pending: Arc<AtomicBool>) {
+        self.pending = Some(pending);
+    }
+
+    /// True if the Plug was built before the Agent connected, and the subscription
+    /// will only be made once it does
+    pub fn is_pending(&self) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|p| p.load(Ordering::SeqCst))
+    }
+
+    pub(crate) fn set_topic(&mut self, topic: TetherOrCustomTopic) {
+        self.topic = topic;
+    }
+
+    /// Subscribe with the role and/or ID given as the Agent's own, so that they follow it
+    /// to a new identity (see `TetherAgent::reidentify`)
+    pub fn with_identity_parts(mut self, parts: IdentityParts) -> InputPlugDefinition {
+        self.identity_parts = parts;
+        self
+    }
+
+    pub fn identity_parts(&self) -> IdentityParts {
+        self.identity_parts
+    }
+
+    pub(crate) fn set_qos(&mut self, qos: i32) {
+        self.qos = qos;
+    }
+
+    /// The broker's response to the subscription, if the Plug was built with
+    /// `.wait_for_subscribe_response(true)` (or resubscribed since)
+    pub fn subscribe_response(&self) -> Option<&SubscribeResponse> {
+        self.subscribe_response.as_ref()
+    }
+
+    /// The QoS the broker actually granted for the subscription, which may be lower than
+    /// requested; `None` if refused, or if the subscribe response is not known
+    pub fn granted_qos(&self) -> Option<i32> {
+        self.subscribe_response
+            .as_ref()
+            .and_then(|r| r.granted_qos())
+    }
+
+    /// Decrypt incoming payloads using this key; see `decrypt`
+    pub fn with_encryption(mut self, key: EncryptionKey) -> InputPlugDefinition {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    pub fn encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    /// If this Plug has an encryption key, decrypt an incoming payload that was encrypted
+    /// by an Output Plug sharing the same key. Fails if the key does not match, or if the
+    /// payload is not encrypted at all (`TetherError::UnencryptedPayload`).
+    ///
+    /// Empty payloads (e.g. when clearing retained messages) are returned as-is, and so is
+    /// everything if this Plug has no key.
+    pub fn decrypt(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match &self.encryption_key {
+            Some(key) if is_encrypted(payload) => decrypt_payload(key, payload),
+            Some(_) if !payload.is_empty() => Err(TetherError::UnencryptedPayload {
+                plug_name: self.name.clone(),
+            }
+            .into()),
+            _ => Ok(payload.to_vec()),
         }
     }
 
+    /// Drop repeated messages arriving within the given window; see `is_duplicate`.
+    /// If a `sequence_field` is given, messages are considered repeats if they have the
+    /// same value for that field in the payload; otherwise the whole payload is compared.
+    pub fn with_dedupe(
+        mut self,
+        window: Duration,
+        sequence_field: Option<&str>,
+    ) -> InputPlugDefinition {
+        self.dedupe = Some(Box::new(
+            Deduplicator::new(window, sequence_field).with_log_target(&self.log_target),
+        ));
+        self
+    }
+
+    pub fn dedupe(&self) -> Option<&Deduplicator> {
+        self.dedupe.as_deref()
+    }
+
+    /// If deduplication was enabled for this Plug, returns true when the message is a
+    /// repeat of one already seen within the window (e.g. a QoS 1 redelivery after
+    /// a reconnect), so that it can be skipped. Always false if deduplication is not enabled.
+    pub fn is_duplicate(&self, incoming_topic: &TetherOrCustomTopic, payload: &[u8]) -> bool {
+        match &self.dedupe {
+            Some(d) => d.is_duplicate(&incoming_topic.full_topic_string(), payload),
+            None => false,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: PlugMetadata) -> InputPlugDefinition {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn metadata(&self) -> Option<&PlugMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Detect missed messages, using the sequence numbers which an Output Plug built with
+    /// the same `sequence_field` adds to each payload; see `check_sequence`
+    pub fn with_gap_detection(mut self, sequence_field: &str) -> InputPlugDefinition {
+        self.gap_detector =
+            Some(GapDetector::new(sequence_field).with_log_target(&self.log_target));
+        self
+    }
+
+    /// The gap detector, if enabled, which also counts the gaps detected so far
+    pub fn gap_detector(&self) -> Option<&GapDetector> {
+        self.gap_detector.as_ref()
+    }
+
+    /// If gap detection was enabled for this Plug, returns the gap (if any) between the
+    /// sequence number in this (decrypted) payload and the last one seen on the same topic.
+    /// Always None if gap detection is not enabled.
+    pub fn check_sequence(
+        &self,
+        incoming_topic: &TetherOrCustomTopic,
+        payload: &[u8],
+    ) -> Option<SequenceGap> {
+        self.gap_detector
+            .as_ref()
+            .and_then(|d| d.check(&incoming_topic.full_topic_string(), payload))
+    }
+
     /// Use the topic of an incoming message to check against the definition of an Input Plug.
     ///
     /// Due to the use of wildcard subscriptions, multiple topic strings might match a given
@@ -63,9 +287,9 @@ impl InputPlugDefinition {
     /// should both match on an Input Plug named `plugMessages` unless more specific Role and/or ID
     /// parts were specified in the Input Plug Definition.
     ///
-    /// In the case where an Input Plug was defined with a completely manually-specified topic string,
-    /// this function returns a warning and marks ANY incoming message as a valid match; the end-user
-    /// developer is expected to match against topic strings themselves.
+    /// In the case where an Input Plug was defined with a completely manually-specified topic string
+    /// (or one generated from a custom topic schema), the incoming topic is matched against it using
+    /// the MQTT wildcard rules (`+` and `#`).
     pub fn matches(&self, incoming_topic: &TetherOrCustomTopic) -> bool {
         match incoming_topic {
             TetherOrCustomTopic::Tether(incoming_three_parts) => match &self.topic {
@@ -76,27 +300,29 @@ impl InputPlugDefinition {
                         my_tpt.id() == "+" || my_tpt.id().eq(incoming_three_parts.id());
                     let matches_plug_name = my_tpt.plug_name() == "+"
                         || my_tpt.plug_name().eq(incoming_three_parts.plug_name());
-                    debug!("Test match for plug named \"{}\" with def {:?} against {:?} => matches_role? {}, matches_id? {}, matches_plug_name? {}", &self.name, &self.topic, &incoming_three_parts, matches_role, matches_id, matches_plug_name);
+                    debug!(
+                        target: &self.log_target,
+                        "Test match for plug named \"{}\" with def {:?} against {:?} => matches_role? {}, matches_id? {}, matches_plug_name? {}", &self.name, &self.topic, &incoming_three_parts, matches_role, matches_id, matches_plug_name
+                    );
                     matches_role && matches_id && matches_plug_name
                 }
                 TetherOrCustomTopic::Custom(my_custom_topic) => {
                     debug!(
-                    "Custom/manual topic \"{}\" on Plug \"{}\" cannot be matched automatically; please filter manually for this",
-                    &my_custom_topic,
-                    self.name()
-                );
-                    my_custom_topic.as_str() == "#"
-                        || my_custom_topic.as_str() == incoming_three_parts.topic()
+                        target: &self.log_target,
+                        "Custom/manual topic \"{}\" on Plug \"{}\" matched as an MQTT topic filter",
+                        &my_custom_topic,
+                        self.name()
+                    );
+                    topic_filter_matches(my_custom_topic, incoming_three_parts.topic())
                 }
             },
             TetherOrCustomTopic::Custom(incoming_custom) => match &self.topic {
                 TetherOrCustomTopic::Custom(my_custom_topic) => {
-                    if my_custom_topic.as_str() == "#"
-                        || my_custom_topic.as_str() == incoming_custom.as_str()
-                    {
+                    if topic_filter_matches(my_custom_topic, incoming_custom) {
                         true
                     } else {
                         warn!(
+                            target: &self.log_target,
                             "Incoming topic \"{}\" is not a three-part topic",
                             &incoming_custom
                         );
@@ -104,7 +330,10 @@ impl InputPlugDefinition {
                     }
                 }
                 TetherOrCustomTopic::Tether(_) => {
-                    error!("Incoming is NOT Three Part Topic but this plug DOES have Three Part Topic; cannot decide match");
+                    error!(
+                        target: &self.log_target,
+                        "Incoming is NOT Three Part Topic but this plug DOES have Three Part Topic; cannot decide match"
+                    );
                     false
                 }
             },
@@ -118,6 +347,24 @@ pub struct OutputPlugDefinition {
     topic: TetherOrCustomTopic,
     qos: i32,
     retain: bool,
+    #[serde(default)]
+    topic_template: Option<TopicTemplate>,
+    #[serde(default)]
+    metadata: Option<PlugMetadata>,
+    #[serde(skip)]
+    encryption_key: Option<EncryptionKey>,
+    #[serde(skip)]
+    coalesce: Option<Coalescer>,
+    #[serde(skip)]
+    sequencer: Option<Arc<Sequencer>>,
+    #[serde(skip)]
+    persist_last_value: bool,
+    #[serde(skip)]
+    brokers: Option<Vec<String>>,
+    #[serde(skip)]
+    identity_parts: IdentityParts,
+    #[serde(skip, default = "default_log_target")]
+    log_target: String,
 }
 
 impl PlugDefinitionCommon<'_> for OutputPlugDefinition {
@@ -153,12 +400,165 @@ impl OutputPlugDefinition {
             topic,
             qos: qos.unwrap_or(1),
             retain: retain.unwrap_or(false),
+            topic_template: None,
+            metadata: None,
+            encryption_key: None,
+            coalesce: None,
+            sequencer: None,
+            persist_last_value: false,
+            brokers: None,
+            identity_parts: IdentityParts::default(),
+            log_target: default_log_target(),
         }
     }
 
+    /// Log under this target instead of `LOG_TARGET`, as the Agent which builds the Plug
+    /// does (see `TetherAgentOptionsBuilder::label`)
+    pub fn with_log_target(mut self, log_target: &str) -> OutputPlugDefinition {
+        self.coalesce = self.coalesce.map(|c| c.with_log_target(log_target));
+        self.log_target = String::from(log_target);
+        self
+    }
+
+    /// An Output Plug for republishing (e.g. transformed) messages from this Input Plug,
+    /// under this Agent's own Role and ID, i.e. `agentRole/agentId/plugName`, where the
+    /// Plug name is:
+    /// - for a Three Part Topic, its Plug name part, or the Input Plug's name if that part
+    ///   is a wildcard (`+`); any Role or ID in the input topic is not carried over
+    /// - for a custom topic (which need not have a Plug name part at all), the Input
+    ///   Plug's name
+    ///
+    /// The QoS is the Agent's `default_publish_qos`, or 1, and messages are not retained.
+    pub fn mirroring(input: &InputPlugDefinition, agent: &TetherAgent) -> OutputPlugDefinition {
+        let plug_name = match input.topic() {
+            TetherOrCustomTopic::Tether(t) if t.plug_name() != "+" => t.plug_name(),
+            _ => input.name(),
+        };
+        OutputPlugDefinition::new(
+            plug_name,
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_publish(
+                None, None, plug_name, agent,
+            )),
+            agent.default_publish_qos(),
+            None,
+        )
+        .with_identity_parts(IdentityParts {
+            role: true,
+            id: true,
+        })
+        .with_log_target(agent.log_target())
+    }
+
+    /// Publish at most one message per interval (per topic) on this Plug, keeping only the
+    /// most recent of any updates in between; intended for retained "latest value" state.
+    /// See `Coalescer`.
+    pub fn with_coalesce(mut self, interval: Duration) -> OutputPlugDefinition {
+        self.coalesce = Some(Coalescer::new(interval).with_log_target(&self.log_target));
+        self
+    }
+
+    pub fn coalesce(&self) -> Option<&Coalescer> {
+        self.coalesce.as_ref()
+    }
+
+    /// Add a sequence number (counting up from zero) to every (non-empty) payload published
+    /// on this Plug, in the given field, so that consumers can detect missed messages
+    pub fn with_sequence(mut self, sequence_field: &str) -> OutputPlugDefinition {
+        self.sequencer = Some(Arc::new(Sequencer::new(sequence_field)));
+        self
+    }
+
+    pub fn sequencer(&self) -> Option<&Sequencer> {
+        self.sequencer.as_deref()
+    }
+
+    /// The Plug's own sequencer, for republishing a persisted value with the next number
+    pub(crate) fn shared_sequencer(&self) -> Option<Arc<Sequencer>> {
+        self.sequencer.clone()
+    }
+
+    /// Have the Agent remember the latest payload published (per topic) on this Plug, and
+    /// publish it again whenever it reconnects, so that the broker has it even if it lost
+    /// it (e.g. after a restart). Only a value the broker no longer retains is republished,
+    /// with the Plug's retain flag and a new sequence number (if any).
+    pub fn with_persist_last_value(mut self) -> OutputPlugDefinition {
+        self.persist_last_value = true;
+        self
+    }
+
+    pub fn persists_last_value(&self) -> bool {
+        self.persist_last_value
+    }
+
+    /// Publish only to the brokers with these tags, out of those the Agent is connected
+    /// to (see `TetherAgentOptionsBuilder::additional_brokers`), instead of all of them
+    pub fn with_brokers(mut self, tags: Vec<String>) -> OutputPlugDefinition {
+        self.brokers = Some(tags);
+        self
+    }
+
+    /// The tags of the brokers to publish to, or None for every broker
+    pub fn brokers(&self) -> Option<&[String]> {
+        self.brokers.as_deref()
+    }
+
+    pub fn with_metadata(mut self, metadata: PlugMetadata) -> OutputPlugDefinition {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn metadata(&self) -> Option<&PlugMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Encrypt all (non-empty) payloads published on this Plug, using this key
+    pub fn with_encryption(mut self, key: EncryptionKey) -> OutputPlugDefinition {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    pub fn encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    /// Attach a Topic Template; any placeholders remaining in the template
+    /// must be provided each time a message is published on this Plug.
+    pub fn with_topic_template(mut self, template: TopicTemplate) -> OutputPlugDefinition {
+        self.topic_template = Some(template);
+        self
+    }
+
     pub fn retain(&self) -> bool {
         self.retain
     }
+
+    pub(crate) fn set_topic(&mut self, topic: TetherOrCustomTopic) {
+        self.topic = topic;
+    }
+
+    /// Publish with the role and/or ID taken from the Agent's own identity, so that they
+    /// follow it to a new one (see `TetherAgent::reidentify`)
+    pub fn with_identity_parts(mut self, parts: IdentityParts) -> OutputPlugDefinition {
+        self.identity_parts = parts;
+        self
+    }
+
+    pub fn identity_parts(&self) -> IdentityParts {
+        self.identity_parts
+    }
+
+    pub fn topic_template(&self) -> Option<&TopicTemplate> {
+        self.topic_template.as_ref()
+    }
+
+    /// Resolve the final topic to publish on, filling any placeholders in the
+    /// Topic Template (if there is one) from the given parameters.
+    pub fn render_topic(&self, params: &[(&str, &str)]) -> anyhow::Result<String> {
+        match &self.topic_template {
+            Some(template) => template.render(params),
+            None => Ok(String::from(self.topic_str())),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -182,25 +582,276 @@ impl PlugDefinition {
         }
     }
 
+    pub fn qos(&self) -> i32 {
+        match self {
+            PlugDefinition::InputPlug(p) => p.qos(),
+            PlugDefinition::OutputPlug(p) => p.qos(),
+        }
+    }
+
+    /// Whether messages are published with the retain flag; None for Input Plugs
+    pub fn retain(&self) -> Option<bool> {
+        match self {
+            PlugDefinition::InputPlug(_) => None,
+            PlugDefinition::OutputPlug(p) => Some(p.retain()),
+        }
+    }
+
+    /// See `PlugOptionsBuilder::metadata`
+    pub fn metadata(&self) -> Option<&PlugMetadata> {
+        match self {
+            PlugDefinition::InputPlug(p) => p.metadata(),
+            PlugDefinition::OutputPlug(p) => p.metadata(),
+        }
+    }
+
+    /// The entry for this Plug in the Agent's `Manifest`
+    pub fn description(&self) -> PlugDescription {
+        PlugDescription {
+            name: String::from(self.name()),
+            direction: match self {
+                PlugDefinition::InputPlug(_) => PlugDirection::Input,
+                PlugDefinition::OutputPlug(_) => PlugDirection::Output,
+            },
+            topic: String::from(self.topic()),
+            qos: self.qos(),
+            retain: self.retain(),
+            metadata: self.metadata().cloned(),
+        }
+    }
+
+    /// Message and byte counts for this Plug, as recorded by the Agent; see
+    /// `TetherAgent::plug_stats`
+    pub fn stats(&self, tether_agent: &TetherAgent) -> MessageStats {
+        tether_agent.plug_stats(self)
+    }
+
     pub fn matches(&self, topic: &TetherOrCustomTopic) -> bool {
         match self {
             PlugDefinition::InputPlug(p) => p.matches(topic),
-            PlugDefinition::OutputPlug(_) => {
-                error!("We don't check matches for Output Plugs");
+            PlugDefinition::OutputPlug(p) => {
+                error!(target: &p.log_target, "We don't check matches for Output Plugs");
+                false
+            }
+        }
+    }
+
+    pub fn decrypt(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            PlugDefinition::InputPlug(p) => p.decrypt(payload),
+            PlugDefinition::OutputPlug(_) => Err(anyhow::anyhow!(
+                "Cannot decrypt incoming messages with an Output Plug"
+            )),
+        }
+    }
+
+    pub fn check_sequence(
+        &self,
+        topic: &TetherOrCustomTopic,
+        payload: &[u8],
+    ) -> Option<SequenceGap> {
+        match self {
+            PlugDefinition::InputPlug(p) => p.check_sequence(topic, payload),
+            PlugDefinition::OutputPlug(p) => {
+                error!(target: &p.log_target, "We don't check sequence numbers for Output Plugs");
+                None
+            }
+        }
+    }
+
+    pub fn is_duplicate(&self, topic: &TetherOrCustomTopic, payload: &[u8]) -> bool {
+        match self {
+            PlugDefinition::InputPlug(p) => p.is_duplicate(topic, payload),
+            PlugDefinition::OutputPlug(p) => {
+                error!(target: &p.log_target, "We don't check duplicates for Output Plugs");
                 false
             }
         }
     }
 }
 
+impl fmt::Display for InputPlugDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "InputPlug '{}' ← {} qos={}",
+            self.name,
+            self.topic_str(),
+            self.qos
+        )
+    }
+}
+
+impl fmt::Display for OutputPlugDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OutputPlug '{}' → ", self.name)?;
+        match &self.topic_template {
+            Some(template) => write!(f, "{}", template)?,
+            None => write!(f, "{}", self.topic_str())?,
+        }
+        write!(f, " qos={} retain={}", self.qos, self.retain)
+    }
+}
+
+/// A concise, single-line summary for log messages, e.g.
+/// `OutputPlug 'brightness' → my-role/any/brightness qos=1 retain=false`;
+/// use `{:?}` for the full details.
+impl fmt::Display for PlugDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlugDefinition::InputPlug(p) => fmt::Display::fmt(p, f),
+            PlugDefinition::OutputPlug(p) => fmt::Display::fmt(p, f),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
 
     use crate::{
+        encrypt_payload,
         three_part_topic::{parse_plug_name, TetherOrCustomTopic, ThreePartTopic},
-        InputPlugDefinition, PlugDefinitionCommon,
+        EncryptionKey, InputPlugDefinition, OutputPlugDefinition, PlugDefinition,
+        PlugDefinitionCommon, PlugOptionsBuilder, TetherAgentOptionsBuilder, TetherError,
     };
 
+    #[test]
+    fn plaintext_refused_with_key() {
+        let key = EncryptionKey::generate();
+        let input = InputPlugDefinition::new(
+            "secrets",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked("tester", "any", "secrets")),
+            None,
+        )
+        .with_encryption(key.clone());
+
+        let plaintext = rmp_serde::to_vec("hello").unwrap();
+        let error = input.decrypt(&plaintext).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TetherError>(),
+            Some(&TetherError::UnencryptedPayload {
+                plug_name: "secrets".into()
+            })
+        );
+
+        let encrypted = encrypt_payload(&key, &plaintext).unwrap();
+        assert_eq!(input.decrypt(&encrypted).unwrap(), plaintext);
+        // Empty payloads still clear retained messages
+        assert!(input.decrypt(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn display_summary() {
+        let output = PlugDefinition::OutputPlug(OutputPlugDefinition::new(
+            "brightness",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
+                "my-role",
+                "any",
+                "brightness",
+            )),
+            None,
+            None,
+        ));
+        assert_eq!(
+            output.to_string(),
+            "OutputPlug 'brightness' → my-role/any/brightness qos=1 retain=false"
+        );
+
+        let input = PlugDefinition::InputPlug(InputPlugDefinition::new(
+            "brightness",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
+                "brightness",
+                None,
+                None,
+                None,
+            )),
+            Some(2),
+        ));
+        assert_eq!(
+            input.to_string(),
+            "InputPlug 'brightness' ← +/+/brightness qos=2"
+        );
+    }
+
+    #[test]
+    fn qos_and_retain_accessors() {
+        let mut agent = TetherAgentOptionsBuilder::new("tester")
+            .auto_connect(false)
+            .build()
+            .unwrap();
+
+        let output = PlugOptionsBuilder::create_output("state")
+            .qos(Some(2))
+            .retain(Some(true))
+            .build(&mut agent)
+            .unwrap();
+        assert_eq!(output.qos(), 2);
+        assert_eq!(output.retain(), Some(true));
+
+        let output = PlugOptionsBuilder::create_output("values")
+            .build(&mut agent)
+            .unwrap();
+        assert_eq!(output.qos(), 1);
+        assert_eq!(output.retain(), Some(false));
+
+        let input = PlugOptionsBuilder::create_input("values")
+            .qos(Some(0))
+            .build(&mut agent)
+            .unwrap();
+        assert_eq!(input.qos(), 0);
+        assert_eq!(input.retain(), None);
+    }
+
+    #[test]
+    fn output_mirroring_input() {
+        let agent = TetherAgentOptionsBuilder::new("processor")
+            .id(Some("smoothed"))
+            .auto_connect(false)
+            .build()
+            .unwrap();
+
+        let tether_input = InputPlugDefinition::new(
+            "temperature",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
+                "temperature",
+                Some("sensor"),
+                None,
+                None,
+            )),
+            Some(2),
+        );
+        let output = OutputPlugDefinition::mirroring(&tether_input, &agent);
+        assert_eq!(output.name(), "temperature");
+        assert_eq!(output.topic_str(), "processor/smoothed/temperature");
+        assert_eq!(output.qos(), 1);
+        assert!(!output.retain());
+
+        let any_plug_input = InputPlugDefinition::new(
+            "anything",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
+                "anything",
+                Some("sensor"),
+                None,
+                Some("+"),
+            )),
+            None,
+        );
+        assert_eq!(
+            OutputPlugDefinition::mirroring(&any_plug_input, &agent).topic_str(),
+            "processor/smoothed/anything"
+        );
+
+        let custom_input = InputPlugDefinition::new(
+            "legacy",
+            TetherOrCustomTopic::Custom("some/legacy/topic/structure".into()),
+            None,
+        );
+        let output = OutputPlugDefinition::mirroring(&custom_input, &agent);
+        assert_eq!(output.name(), "legacy");
+        assert_eq!(output.topic_str(), "processor/smoothed/legacy");
+    }
+
     #[test]
     fn input_match_tpt() {
         let plug_def = InputPlugDefinition::new(
@@ -215,12 +866,12 @@ mod tests {
         assert_eq!(plug_def.topic_str(), "+/+/testPlug");
         assert_eq!(parse_plug_name("+/+/testPlug"), Some("testPlug"));
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "dummy", "any", "testPlug"
             )))
         );
         assert!(
-            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "dummy",
                 "any",
                 "anotherPlug"
@@ -245,28 +896,28 @@ mod tests {
         assert_eq!(&plug_def.name, "customPlug");
         assert_eq!(plug_def.topic_str(), "customRole/+/customPlug");
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "customRole",
                 "any",
                 "customPlug"
             )))
         );
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "customRole",
                 "andAnythingElse",
                 "customPlug"
             )))
         );
         assert!(
-            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "customRole",
                 "any",
                 "notMyPlug"
             )))
         ); // wrong incoming Plug N.into())ame
         assert!(
-            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "someOtherRole",
                 "any",
                 "customPlug"
@@ -290,28 +941,28 @@ mod tests {
         assert_eq!(&plug_def.name, "customPlug");
         assert_eq!(plug_def.topic_str(), "+/specificID/customPlug");
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "anyRole",
                 "specificID",
                 "customPlug"
             )))
         );
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "anotherRole",
                 "specificID",
                 "customPlug"
             )))
         ); // wrong incoming Role
         assert!(
-            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "anyRole",
                 "specificID",
                 "notMyPlug"
             )))
         ); // wrong incoming Plug Name
         assert!(
-            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            !plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "anyRole",
                 "anotherID",
                 "customPlug"
@@ -335,7 +986,7 @@ mod tests {
         assert_eq!(&plug_def.name, "customPlug");
         assert_eq!(plug_def.topic_str(), "specificRole/specificID/customPlug");
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "specificRole",
                 "specificID",
                 "customPlug"
@@ -370,6 +1021,57 @@ mod tests {
         assert!(!plug_def.matches(&TetherOrCustomTopic::Custom("one/one/one/one/one".into())));
     }
 
+    #[test]
+    fn input_dedupe_single_delivery() {
+        let plug_def = InputPlugDefinition::new(
+            "testPlug",
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
+                "testPlug", None, None, None,
+            )),
+            None,
+        )
+        .with_dedupe(Duration::from_secs(10), None);
+
+        let incoming = vec![
+            (
+                ThreePartTopic::new_unchecked("dummy", "any", "testPlug"),
+                vec![1, 2, 3],
+            ),
+            (
+                ThreePartTopic::new_unchecked("dummy", "any", "testPlug"),
+                vec![1, 2, 3],
+            ), // redelivered
+            (
+                ThreePartTopic::new_unchecked("dummy", "any", "testPlug"),
+                vec![4, 5, 6],
+            ),
+            (
+                ThreePartTopic::new_unchecked("dummy", "any", "testPlug"),
+                vec![4, 5, 6],
+            ), // redelivered
+        ];
+
+        let delivered: Vec<Vec<u8>> = incoming
+            .into_iter()
+            .map(|(t, payload)| (TetherOrCustomTopic::Tether(t), payload))
+            .filter(|(topic, payload)| {
+                plug_def.matches(topic) && !plug_def.is_duplicate(topic, payload)
+            })
+            .map(|(_, payload)| payload)
+            .collect();
+
+        assert_eq!(delivered, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+    }
+
+    #[test]
+    fn input_no_dedupe_by_default() {
+        let plug_def =
+            InputPlugDefinition::new("testPlug", TetherOrCustomTopic::Custom("#".into()), None);
+        let topic = TetherOrCustomTopic::Custom("one/two".into());
+        assert!(!plug_def.is_duplicate(&topic, &[1]));
+        assert!(!plug_def.is_duplicate(&topic, &[1]));
+    }
+
     #[test]
     fn input_match_wildcard() {
         let plug_def = InputPlugDefinition::new(
@@ -382,7 +1084,7 @@ mod tests {
 
         // Standard TPT will match
         assert!(
-            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new(
+            plug_def.matches(&TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
                 "any", "any", "plugName"
             )))
         );
@@ -0,0 +1,152 @@
+use serde::Serialize;
+
+use crate::{PlugDefinition, TetherAgent};
+
+/// The name of the Plug for one index of a `PlugGroup`, e.g. `led-3`. The index is part of
+/// the Plug Name (rather than a topic level of its own), so that every topic in the group
+/// is still a Three Part Topic.
+pub fn group_plug_name(name: &str, index: usize) -> String {
+    format!("{}-{}", name, index)
+}
+
+/// A collection of Output Plugs with the same options and a shared base name, one for each
+/// index, e.g. one per LED in a strip: Plugs `led-0`, `led-1`, etc. (see `group_plug_name`).
+/// This saves creating and keeping track of many near-identical Plug Definitions.
+///
+/// Build one with `PlugOptionsBuilder::build_group`; each Plug is built (and described,
+/// counted in the stats, etc.) just like any other Output Plug.
+#[derive(Debug)]
+pub struct PlugGroup {
+    name: String,
+    plugs: Vec<PlugDefinition>,
+}
+
+impl PlugGroup {
+    pub(crate) fn new(name: &str, plugs: Vec<PlugDefinition>) -> PlugGroup {
+        PlugGroup {
+            name: String::from(name),
+            plugs,
+        }
+    }
+
+    /// The base name, without any index
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.plugs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugs.is_empty()
+    }
+
+    /// The Plug for this index, if the group has one
+    pub fn plug(&self, index: usize) -> Option<&PlugDefinition> {
+        self.plugs.get(index)
+    }
+
+    pub fn plugs(&self) -> &[PlugDefinition] {
+        &self.plugs
+    }
+
+    /// Publish on the Plug for this index; see `TetherAgent::publish`
+    pub fn publish(
+        &self,
+        tether_agent: &TetherAgent,
+        index: usize,
+        payload: &[u8],
+    ) -> anyhow::Result<()> {
+        tether_agent.publish(self.plug_or_error(index)?, payload)
+    }
+
+    /// Encode and publish on the Plug for this index; see `TetherAgent::encode_and_publish`
+    pub fn encode_and_publish<T: Serialize>(
+        &self,
+        tether_agent: &TetherAgent,
+        index: usize,
+        data: T,
+    ) -> anyhow::Result<()> {
+        tether_agent.encode_and_publish(self.plug_or_error(index)?, data)
+    }
+
+    fn plug_or_error(&self, index: usize) -> anyhow::Result<&PlugDefinition> {
+        self.plug(index).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Plug group \"{}\" has no index {} (it has {} Plugs)",
+                self.name,
+                index,
+                self.plugs.len()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+    #[test]
+    fn publish_by_index() {
+        let id = Uuid::new_v4().to_string();
+        let mut tether_agent = TetherAgentOptionsBuilder::new("strip")
+            .id(Some(&id))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let group = PlugOptionsBuilder::create_output("led")
+            .qos(Some(0))
+            .build_group(&mut tether_agent, 4)
+            .unwrap();
+        assert_eq!(group.len(), 4);
+        assert_eq!(group.plug(2).unwrap().name(), "led-2");
+        assert_eq!(group.plug(0).unwrap().qos(), 0);
+
+        let _input = PlugOptionsBuilder::create_input("any")
+            .role(Some("strip"))
+            .id(Some(&id))
+            .any_plug()
+            .wait_for_subscribe_response(true)
+            .build(&mut tether_agent)
+            .unwrap();
+        for index in [3, 0, 1] {
+            group
+                .encode_and_publish(&tether_agent, index, index as u8)
+                .unwrap();
+        }
+        assert!(group.publish(&tether_agent, 4, &[1]).is_err());
+
+        // Each index goes to its own topic
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            let message = tether_agent
+                .check_received_timeout(std::time::Duration::from_secs(5))
+                .expect("timed out waiting for messages");
+            let value: u8 = rmp_serde::from_slice(message.payload()).unwrap();
+            received.push((message.topic().full_topic_string(), value));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (format!("strip/{}/led-3", id), 3),
+                (format!("strip/{}/led-0", id), 0),
+                (format!("strip/{}/led-1", id), 1)
+            ]
+        );
+        assert_eq!(
+            group.plug(3).unwrap().stats(&tether_agent).message_count(),
+            1
+        );
+
+        let e = PlugOptionsBuilder::create_output("led")
+            .topic(Some("custom/topic"))
+            .build_group(&mut tether_agent, 2)
+            .unwrap_err();
+        assert!(e.to_string().contains("own topic"));
+        assert!(PlugOptionsBuilder::create_input("led")
+            .build_group(&mut tether_agent, 2)
+            .is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{three_part_topic::build_topic, AgentIdentity};
+
+/// The Plug name of the topic on which an Agent publishes its `Manifest`
+pub const MANIFEST_PLUG_NAME: &str = "_manifest";
+
+/// How other Agents are intended to use a Plug's topic. This is only a hint, for
+/// documentation and discovery tools; nothing is enforced, by the Agent or the broker.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlugAccess {
+    /// Others should only subscribe, e.g. a sensor reading published by this Agent
+    ReadOnly,
+    /// Others should only publish, e.g. commands which this Agent listens for
+    WriteOnly,
+    ReadWrite,
+}
+
+/// Optional information about a Plug, set using `PlugOptionsBuilder::metadata`. Every
+/// field is optional, so fill in what is known and use `..Default::default()` for the rest.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PlugMetadata {
+    pub access: Option<PlugAccess>,
+    /// The expected number of messages per second
+    pub rate: Option<f32>,
+    pub units: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlugDirection {
+    Input,
+    Output,
+}
+
+/// One entry in the list of Plugs in an Agent's `Manifest`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlugDescription {
+    pub name: String,
+    pub direction: PlugDirection,
+    pub topic: String,
+    pub qos: i32,
+    /// Whether messages are published with the retain flag; None for Input Plugs
+    #[serde(default)]
+    pub retain: Option<bool>,
+    pub metadata: Option<PlugMetadata>,
+}
+
+/// Everything an Agent offers: its identity and every Plug it has built, as published
+/// (retained) on `role/id/_manifest` by `TetherAgent::publish_manifest`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub role: String,
+    pub id: String,
+    pub plugs: Vec<PlugDescription>,
+}
+
+/// The topic an Agent with this identity publishes its manifest on, e.g. `brain/any/_manifest`
+pub fn manifest_topic(identity: &AgentIdentity) -> String {
+    build_topic(identity.role(), identity.id(), MANIFEST_PLUG_NAME)
+}
@@ -1,6 +1,22 @@
+pub mod coalesce;
+pub mod dedupe;
 pub mod definitions;
+pub mod group;
+pub mod metadata;
+pub mod named;
 pub mod options;
+pub mod sequence;
+pub mod subscription_filter;
 pub mod three_part_topic;
+pub mod topic_rewrite;
+pub mod topic_template;
+pub mod typed;
 
 pub use definitions::*;
+pub use group::PlugGroup;
+pub use metadata::*;
 pub use options::*;
+pub use subscription_filter::SubscriptionFilter;
+pub use three_part_topic::{IdentityParts, TetherOrCustomTopic, ThreePartTopic};
+pub use topic_rewrite::TopicRewrite;
+pub use typed::TypedInputPlug;
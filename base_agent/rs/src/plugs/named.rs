@@ -0,0 +1,77 @@
+/// Declare Plug names once, as types, so that a misspelt Plug name is a compile error
+/// rather than a Plug which silently never receives anything. Each entry becomes a unit
+/// struct with a `NAME` constant and `create_input`/`create_output` constructors, which
+/// return a `PlugOptionsBuilder` just like `PlugOptionsBuilder::create_input(NAME)` etc.
+///
+/// ```
+/// use tether_agent::tether_plugs;
+///
+/// tether_plugs! {
+///     Brightness => "brightness",
+///     pub Temp => "temperature",
+/// }
+///
+/// assert_eq!(Brightness::NAME, "brightness");
+/// let _builder = Temp::create_output().retain(Some(true));
+/// ```
+///
+/// Using a Plug which was not declared does not compile:
+///
+/// ```compile_fail
+/// use tether_agent::tether_plugs;
+///
+/// tether_plugs! {
+///     Brightness => "brightness",
+/// }
+///
+/// let _builder = Brightnes::create_input();
+/// ```
+#[macro_export]
+macro_rules! tether_plugs {
+    ($($(#[$meta:meta])* $vis:vis $plug:ident => $name:literal),* $(,)?) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            $vis struct $plug;
+
+            #[allow(dead_code)]
+            impl $plug {
+                pub const NAME: &'static str = $name;
+
+                pub fn create_input() -> $crate::PlugOptionsBuilder {
+                    $crate::PlugOptionsBuilder::create_input(Self::NAME)
+                }
+
+                pub fn create_output() -> $crate::PlugOptionsBuilder {
+                    $crate::PlugOptionsBuilder::create_output(Self::NAME)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::TetherAgentOptionsBuilder;
+
+    tether_plugs! {
+        /// Documented like any other struct
+        Brightness => "brightness",
+        Temp => "temperature",
+    }
+
+    #[test]
+    fn declared_plugs() {
+        assert_eq!(Brightness::NAME, "brightness");
+        assert_eq!(Temp::NAME, "temperature");
+
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let input = Brightness::create_input().build(&mut tether_agent).unwrap();
+        assert_eq!(input.name(), "brightness");
+        assert_eq!(input.topic(), "+/+/brightness");
+        let output = Temp::create_output().build(&mut tether_agent).unwrap();
+        assert_eq!(output.topic(), "tester/any/temperature");
+    }
+}
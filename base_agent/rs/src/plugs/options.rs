@@ -1,14 +1,20 @@
+use std::{fmt, time::Duration};
+
 use anyhow::anyhow;
 use log::{debug, error, info, warn};
 
 use crate::{
     definitions::{InputPlugDefinition, OutputPlugDefinition, PlugDefinitionCommon},
-    three_part_topic::ThreePartTopic,
-    PlugDefinition, TetherAgent,
+    group::{group_plug_name, PlugGroup},
+    metadata::PlugMetadata,
+    three_part_topic::{IdentityParts, ThreePartTopic},
+    topic_template::{TopicTemplate, ID_PLACEHOLDER, PLUG_PLACEHOLDER, ROLE_PLACEHOLDER},
+    EncryptionKey, PlugDefinition, RetainHandling, SubscriptionFilter, TetherAgent, LOG_TARGET,
 };
 
 use super::three_part_topic::TetherOrCustomTopic;
 
+#[derive(Clone)]
 pub struct InputPlugOptions {
     plug_name: String,
     qos: Option<i32>,
@@ -16,21 +22,129 @@ pub struct InputPlugOptions {
     override_subscribe_id: Option<String>,
     override_subscribe_plug_name: Option<String>,
     override_topic: Option<String>,
+    subscription_filter: Option<SubscriptionFilter>,
+    dedupe_window: Option<Duration>,
+    dedupe_sequence_field: Option<String>,
+    encryption_key: Option<EncryptionKey>,
+    wait_for_subscribe_response: bool,
+    sequence_field: Option<String>,
+    retain_handling: Option<RetainHandling>,
+    metadata: Option<PlugMetadata>,
+    ignored: Vec<BuilderWarning>,
 }
 
+#[derive(Clone)]
 pub struct OutputPlugOptions {
     plug_name: String,
     qos: Option<i32>,
     override_publish_role: Option<String>,
     override_publish_id: Option<String>,
     override_topic: Option<String>,
+    topic_template: Option<String>,
     retain: Option<bool>,
+    encryption_key: Option<EncryptionKey>,
+    coalesce: Option<Duration>,
+    sequence_field: Option<String>,
+    persist_last_value: bool,
+    brokers: Option<Vec<String>>,
+    metadata: Option<PlugMetadata>,
+    ignored: Vec<BuilderWarning>,
+}
+
+/// A problem with the options given to a `PlugOptionsBuilder`. These do not stop the Plug
+/// being built (most are logged as they happen), but can all be checked at once using
+/// `PlugOptionsBuilder::warnings`, or turned into an error by `build_strict`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuilderWarning {
+    /// The option only applies to Input Plugs, so it was ignored
+    InputOnly(&'static str),
+    /// The option only applies to Output Plugs, so it was ignored
+    OutputOnly(&'static str),
+    /// The option was ignored because another one takes precedence, e.g. `role` when
+    /// an override `topic` was also provided
+    Overridden {
+        option: &'static str,
+        by: &'static str,
+    },
+    /// Not a valid QoS level (0, 1 or 2); a default is used instead
+    InvalidQos(i32),
+    /// Input Plugs can only override the Plug Name part of their topic with the wildcard `+`
+    InvalidNameOverride(String),
+    /// The option has no effect without another one, e.g. `dedupe_sequence_field`
+    /// without `dedupe_window`
+    Requires {
+        option: &'static str,
+        requires: &'static str,
+    },
+    /// The option requires MQTT 5, but this Agent uses MQTT 3.1.1, so it has no effect
+    RequiresMqtt5(&'static str),
+}
+
+impl fmt::Display for BuilderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuilderWarning::InputOnly(option) => {
+                write!(f, "\"{}\" only applies to Input Plugs; ignored", option)
+            }
+            BuilderWarning::OutputOnly(option) => {
+                write!(f, "\"{}\" only applies to Output Plugs; ignored", option)
+            }
+            BuilderWarning::Overridden { option, by } => {
+                write!(f, "\"{}\" is ignored, since \"{}\" takes precedence", option, by)
+            }
+            BuilderWarning::InvalidQos(qos) => {
+                write!(f, "QoS {} is not valid (must be 0, 1 or 2)", qos)
+            }
+            BuilderWarning::InvalidNameOverride(name) => write!(
+                f,
+                "Input Plugs can only override the Plug Name part with the wildcard \"+\", not \"{}\"",
+                name
+            ),
+            BuilderWarning::Requires { option, requires } => {
+                write!(f, "\"{}\" has no effect without \"{}\"", option, requires)
+            }
+            BuilderWarning::RequiresMqtt5(option) => write!(
+                f,
+                "\"{}\" requires MQTT 5, but this Agent uses MQTT 3.1.1; it has no effect",
+                option
+            ),
+        }
+    }
+}
+
+/// A topic generated from the Agent's topic schema: a Tether topic if it has three parts,
+/// otherwise a custom one
+fn schema_topic(topic: String) -> TetherOrCustomTopic {
+    match ThreePartTopic::try_from(topic.as_str()) {
+        Ok(t) => TetherOrCustomTopic::Tether(t),
+        Err(_) => TetherOrCustomTopic::Custom(topic),
+    }
+}
+
+/// Only the Three Part Topics generated for Plugs are lowercased (see
+/// `TetherAgentOptionsBuilder::lowercase_topics`); topics given explicitly are left alone
+fn lowercase_if_enabled(
+    tether_agent: &TetherAgent,
+    topic: TetherOrCustomTopic,
+) -> TetherOrCustomTopic {
+    if tether_agent.is_lowercasing_topics() {
+        topic.to_lowercase()
+    } else {
+        topic
+    }
+}
+
+/// Log a builder option that does not apply to this Plug, and keep it for `warnings`
+fn ignore_option(ignored: &mut Vec<BuilderWarning>, warning: BuilderWarning) {
+    error!(target: LOG_TARGET, "{}", warning);
+    ignored.push(warning);
 }
 
 /// This is the definition of an Input or Output Plug.
 ///
 /// You typically don't use an instance of this directly; call `.build()` at the
 /// end of the chain to get a usable **PlugDefinition**
+#[derive(Clone)]
 pub enum PlugOptionsBuilder {
     InputPlugOptions(InputPlugOptions),
     OutputPlugOptions(OutputPlugOptions),
@@ -44,7 +158,16 @@ impl PlugOptionsBuilder {
             override_subscribe_role: None,
             override_subscribe_plug_name: None,
             override_topic: None,
+            subscription_filter: None,
             qos: None,
+            dedupe_window: None,
+            dedupe_sequence_field: None,
+            encryption_key: None,
+            wait_for_subscribe_response: false,
+            sequence_field: None,
+            retain_handling: None,
+            metadata: None,
+            ignored: Vec::new(),
         })
     }
 
@@ -54,11 +177,22 @@ impl PlugOptionsBuilder {
             override_publish_id: None,
             override_publish_role: None,
             override_topic: None,
+            topic_template: None,
             qos: None,
             retain: None,
+            encryption_key: None,
+            coalesce: None,
+            sequence_field: None,
+            persist_last_value: false,
+            brokers: None,
+            metadata: None,
+            ignored: Vec::new(),
         })
     }
 
+    /// Set the QoS for this Plug: used for subscribing (Input) or publishing (Output).
+    /// Provide None to fall back to the Agent's `default_subscribe_qos` or
+    /// `default_publish_qos` respectively, or QoS 1 if that is not set either.
     pub fn qos(mut self, qos: Option<i32>) -> Self {
         match &mut self {
             PlugOptionsBuilder::InputPlugOptions(s) => s.qos = qos,
@@ -80,17 +214,21 @@ impl PlugOptionsBuilder {
         match &mut self {
             PlugOptionsBuilder::InputPlugOptions(s) => {
                 if s.override_topic.is_some() {
-                    error!("Override topic was also provided; this will take precedence");
-                } else {
-                    s.override_subscribe_role = role.map(|s| s.into());
+                    error!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; this will take precedence"
+                    );
                 }
+                s.override_subscribe_role = role.map(|s| s.into());
             }
             PlugOptionsBuilder::OutputPlugOptions(s) => {
                 if s.override_topic.is_some() {
-                    error!("Override topic was also provided; this will take precedence");
-                } else {
-                    s.override_publish_role = role.map(|s| s.into());
+                    error!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; this will take precedence"
+                    );
                 }
+                s.override_publish_role = role.map(|s| s.into());
             }
         };
         self
@@ -110,17 +248,21 @@ impl PlugOptionsBuilder {
         match &mut self {
             PlugOptionsBuilder::InputPlugOptions(s) => {
                 if s.override_topic.is_some() {
-                    error!("Override topic was also provided; this will take precedence");
-                } else {
-                    s.override_subscribe_id = id.map(|s| s.into());
+                    error!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; this will take precedence"
+                    );
                 }
+                s.override_subscribe_id = id.map(|s| s.into());
             }
             PlugOptionsBuilder::OutputPlugOptions(s) => {
                 if s.override_topic.is_some() {
-                    error!("Override topic was also provided; this will take precedence");
-                } else {
-                    s.override_publish_id = id.map(|s| s.into());
+                    error!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; this will take precedence"
+                    );
                 }
+                s.override_publish_id = id.map(|s| s.into());
             }
         };
         self
@@ -140,25 +282,33 @@ impl PlugOptionsBuilder {
         match &mut self {
             PlugOptionsBuilder::InputPlugOptions(opt) => {
                 if opt.override_topic.is_some() {
-                    error!("Override topic was also provided; this will take precedence");
+                    error!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; this will take precedence"
+                    );
                 }
                 if let Some(s) = override_plug_name {
                     if s.eq("+") {
                         info!(
+                            target: LOG_TARGET,
                             "Plug Name part given is a wildcard; subscribe topic will use this but (internally) Plug Name will remain \"{}\"", &opt.plug_name
                         );
                     } else {
-                        error!("Input Plugs cannot change their name after ::create_input constructor EXCEPT for wildcard \"+\"");
+                        error!(
+                            target: LOG_TARGET,
+                            "Input Plugs cannot change their name after ::create_input constructor EXCEPT for wildcard \"+\""
+                        );
                     }
                     opt.override_subscribe_plug_name = override_plug_name.map(|s| s.into());
                 } else {
-                    debug!("Override plug name set to None; will use original name \"{}\" given in ::create_input constructor", opt.plug_name);
+                    debug!(
+                        target: LOG_TARGET,
+                        "Override plug name set to None; will use original name \"{}\" given in ::create_input constructor", opt.plug_name
+                    );
                 }
             }
-            PlugOptionsBuilder::OutputPlugOptions(_) => {
-                error!(
-                    "Output Plugs cannot change their name part after ::create_output constructor"
-                );
+            PlugOptionsBuilder::OutputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::InputOnly("name"));
             }
         };
         self
@@ -178,10 +328,8 @@ impl PlugOptionsBuilder {
             PlugOptionsBuilder::InputPlugOptions(opt) => {
                 opt.override_subscribe_plug_name = Some("+".into());
             }
-            PlugOptionsBuilder::OutputPlugOptions(_) => {
-                error!(
-                    "Output Plugs cannot change their name part after ::create_output constructor"
-                );
+            PlugOptionsBuilder::OutputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::InputOnly("any_plug"));
             }
         }
         self
@@ -198,11 +346,15 @@ impl PlugOptionsBuilder {
         match override_topic {
             Some(t) => {
                 if TryInto::<ThreePartTopic>::try_into(t).is_ok() {
-                    info!("Custom topic passes Three Part Topic validation");
+                    info!(target: LOG_TARGET, "Custom topic passes Three Part Topic validation");
                 } else if t == "#" {
-                    info!("Wildcard \"#\" custom topics are not Three Part Topics but are valid");
+                    info!(
+                        target: LOG_TARGET,
+                        "Wildcard \"#\" custom topics are not Three Part Topics but are valid"
+                    );
                 } else {
                     warn!(
+                        target: LOG_TARGET,
                         "Could not convert \"{}\" into Tether 3 Part Topic; presumably you know what you're doing!",
                         t
                     );
@@ -222,10 +374,208 @@ impl PlugOptionsBuilder {
         self
     }
 
+    /// Subscribe according to the given filter, which specifies the role, ID and Plug Name
+    /// parts of the topic, or leaves them as wildcards; see `SubscriptionFilter`. This
+    /// replaces any `.role(...)`, `.id(...)` or `.name(...)` overrides (but an override topic
+    /// from `.topic(...)` still takes precedence). Only applies to Input Plugs.
+    pub fn subscription_filter(mut self, filter: Option<&SubscriptionFilter>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => {
+                s.subscription_filter = filter.cloned();
+            }
+            Self::OutputPlugOptions(s) => {
+                ignore_option(
+                    &mut s.ignored,
+                    BuilderWarning::InputOnly("subscription_filter"),
+                );
+            }
+        }
+        self
+    }
+
+    /// Use a Topic Template with named placeholders for publishing, e.g.
+    /// `"{role}/{id}/sensors/{index}"`. This allows dynamic sub-topics without
+    /// building a new Plug for each one.
+    ///
+    /// The placeholders `{role}`, `{id}` and `{plug}` are filled in when the Plug
+    /// is built, using any `.role(...)` or `.id(...)` overrides or else the Agent's
+    /// own Role and ID. Any other placeholders must be provided on every publish,
+    /// e.g. via `TetherAgent::publish_with_params`.
+    ///
+    /// If a Topic Template is provided, any override topic from `.topic(...)` is ignored.
+    /// Only applies to Output Plugs.
+    pub fn topic_template(mut self, template: Option<&str>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::OutputOnly("topic_template"));
+            }
+            Self::OutputPlugOptions(s) => {
+                if s.override_topic.is_some() {
+                    warn!(
+                        target: LOG_TARGET,
+                        "Override topic was also provided; the Topic Template will take precedence"
+                    );
+                }
+                s.topic_template = template.map(|t| t.into());
+            }
+        }
+        self
+    }
+
+    /// Enable deduplication for an Input Plug: messages which repeat one already received within
+    /// this window (e.g. QoS 1 redeliveries after a reconnect) will be reported by
+    /// `PlugDefinition::is_duplicate`, so that they can be skipped.
+    ///
+    /// By default the whole payload is compared; see also `.dedupe_sequence_field(...)`.
+    pub fn dedupe_window(mut self, window: Option<Duration>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.dedupe_window = window,
+            Self::OutputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::InputOnly("dedupe_window"));
+            }
+        }
+        self
+    }
+
+    /// Whether to receive the retained message (if any) sent by the broker on subscribing;
+    /// see `RetainHandling`. Provide None for the default, `RetainHandling::Send`, which
+    /// matches MQTT 3.1.1 behaviour.
+    ///
+    /// Since all incoming messages share one queue, a retained message is only dropped if
+    /// every Input Plug whose topic matches it asked for `RetainHandling::DontSend`.
+    pub fn retain_handling(mut self, retain_handling: Option<RetainHandling>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.retain_handling = retain_handling,
+            Self::OutputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::InputOnly("retain_handling"));
+            }
+        }
+        self
+    }
+
+    /// Wait for the broker to respond to the subscription when building an Input Plug,
+    /// and keep the response (see `InputPlugDefinition::subscribe_response`), e.g. to
+    /// check the QoS actually granted. Off by default, since it costs a round trip.
+    pub fn wait_for_subscribe_response(mut self, should_wait: bool) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.wait_for_subscribe_response = should_wait,
+            Self::OutputPlugOptions(s) => {
+                ignore_option(
+                    &mut s.ignored,
+                    BuilderWarning::InputOnly("wait_for_subscribe_response"),
+                );
+            }
+        }
+        self
+    }
+
+    /// Name of a field in the (MessagePack-encoded) payload which uniquely identifies each message,
+    /// such as a sequence number, to use for deduplication instead of comparing whole payloads.
+    /// Only applies if `.dedupe_window(...)` was also set.
+    pub fn dedupe_sequence_field(mut self, field: Option<&str>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.dedupe_sequence_field = field.map(|f| f.into()),
+            Self::OutputPlugOptions(s) => {
+                ignore_option(
+                    &mut s.ignored,
+                    BuilderWarning::InputOnly("dedupe_sequence_field"),
+                );
+            }
+        }
+        self
+    }
+
+    /// Encrypt payloads with a key shared by all the Agents involved.
+    /// - For Output Plugs, all (non-empty) payloads are encrypted before publishing
+    /// - For Input Plugs, use `PlugDefinition::decrypt` on incoming payloads
+    ///
+    /// See `EncryptionKey` for how this differs from using TLS.
+    pub fn encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.encryption_key = key,
+            Self::OutputPlugOptions(s) => s.encryption_key = key,
+        }
+        self
+    }
+
+    /// Number messages in sequence, using this field in the (MessagePack map) payload.
+    /// - For Output Plugs, a sequence number counting up from zero is added to every
+    ///   (non-empty) payload as it is published; messages which are held back (see
+    ///   `coalesce`) or fail to publish do not use up a number
+    /// - For Input Plugs, use `PlugDefinition::check_sequence` on incoming (decrypted)
+    ///   payloads to detect missed messages, per topic
+    pub fn sequence_field(mut self, field: Option<&str>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.sequence_field = field.map(|f| f.into()),
+            Self::OutputPlugOptions(s) => s.sequence_field = field.map(|f| f.into()),
+        }
+        self
+    }
+
+    /// For Output Plugs carrying frequently-changing "latest value" state (usually retained),
+    /// publish at most once per interval, dropping intermediate updates. The most recent value
+    /// is held back until the interval has passed; call `TetherAgent::flush_coalesced` regularly
+    /// so that it is eventually sent even if no further updates come along.
+    pub fn coalesce(mut self, interval: Option<Duration>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::OutputOnly("coalesce"));
+            }
+            Self::OutputPlugOptions(s) => s.coalesce = interval,
+        }
+        self
+    }
+
+    /// For Output Plugs holding state (e.g. a retained "current brightness"), remember the
+    /// most recent payload published, and publish it again every time the Agent reconnects,
+    /// in case the broker lost it, e.g. because it was restarted. A retained value is only
+    /// republished if the broker no longer has it; either way, the Plug's own retain flag and
+    /// a new sequence number (if the Plug adds them) are used. Only the latest value per
+    /// topic is kept; publishing an empty payload (clearing it) forgets it.
+    pub fn persist_last_value(mut self, should_persist: bool) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => {
+                ignore_option(
+                    &mut s.ignored,
+                    BuilderWarning::OutputOnly("persist_last_value"),
+                );
+            }
+            Self::OutputPlugOptions(s) => s.persist_last_value = should_persist,
+        }
+        self
+    }
+
+    /// For an Agent connected to more than one broker (see
+    /// `TetherAgentOptionsBuilder::additional_brokers`), publish only to the brokers with
+    /// these tags; the Agent's own broker is `PRIMARY_BROKER_TAG`. Building the Plug fails
+    /// if any tag is unknown. Provide None (the default) to publish to every broker.
+    pub fn brokers(mut self, tags: Option<Vec<&str>>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::OutputOnly("brokers"));
+            }
+            Self::OutputPlugOptions(s) => {
+                s.brokers = tags.map(|tags| tags.into_iter().map(String::from).collect())
+            }
+        }
+        self
+    }
+
+    /// Describe the intended use of this Plug (access, expected rate, units...), for
+    /// documentation and discovery tools. This does not change how the Plug behaves; see
+    /// `TetherAgentOptionsBuilder::announce_manifest` to publish it.
+    pub fn metadata(mut self, metadata: Option<PlugMetadata>) -> Self {
+        match &mut self {
+            Self::InputPlugOptions(s) => s.metadata = metadata,
+            Self::OutputPlugOptions(s) => s.metadata = metadata,
+        }
+        self
+    }
+
     pub fn retain(mut self, should_retain: Option<bool>) -> Self {
         match &mut self {
-            Self::InputPlugOptions(_) => {
-                error!("Cannot set retain flag on Input Plug / subscription");
+            Self::InputPlugOptions(s) => {
+                ignore_option(&mut s.ignored, BuilderWarning::OutputOnly("retain"));
             }
             Self::OutputPlugOptions(s) => {
                 s.retain = should_retain;
@@ -234,64 +584,400 @@ impl PlugOptionsBuilder {
         self
     }
 
+    /// Every problem with the options given so far: options which do not apply to this
+    /// direction of Plug, options overridden by others, invalid values, etc.
+    pub fn warnings(&self) -> Vec<BuilderWarning> {
+        let mut warnings = Vec::new();
+        let overridden =
+            |warnings: &mut Vec<BuilderWarning>, options: &[(&'static str, bool)], by| {
+                for (option, _) in options.iter().filter(|(_, is_set)| *is_set) {
+                    warnings.push(BuilderWarning::Overridden { option, by });
+                }
+            };
+        match self {
+            Self::InputPlugOptions(s) => {
+                warnings.extend(s.ignored.iter().cloned());
+                let parts = [
+                    ("role", s.override_subscribe_role.is_some()),
+                    ("id", s.override_subscribe_id.is_some()),
+                    ("name", s.override_subscribe_plug_name.is_some()),
+                ];
+                if s.override_topic.is_some() {
+                    overridden(&mut warnings, &parts, "topic");
+                    overridden(
+                        &mut warnings,
+                        &[("subscription_filter", s.subscription_filter.is_some())],
+                        "topic",
+                    );
+                } else if s.subscription_filter.is_some() {
+                    overridden(&mut warnings, &parts, "subscription_filter");
+                }
+                if let Some(name) = s
+                    .override_subscribe_plug_name
+                    .as_ref()
+                    .filter(|n| n.as_str() != "+")
+                {
+                    warnings.push(BuilderWarning::InvalidNameOverride(name.clone()));
+                }
+                if s.dedupe_sequence_field.is_some() && s.dedupe_window.is_none() {
+                    warnings.push(BuilderWarning::Requires {
+                        option: "dedupe_sequence_field",
+                        requires: "dedupe_window",
+                    });
+                }
+                if s.retain_handling == Some(RetainHandling::SendIfNew) {
+                    warnings.push(BuilderWarning::RequiresMqtt5("retain_handling"));
+                }
+                if let Some(qos) = s.qos.filter(|q| !(0..=2).contains(q)) {
+                    warnings.push(BuilderWarning::InvalidQos(qos));
+                }
+            }
+            Self::OutputPlugOptions(s) => {
+                warnings.extend(s.ignored.iter().cloned());
+                if s.topic_template.is_some() {
+                    overridden(
+                        &mut warnings,
+                        &[("topic", s.override_topic.is_some())],
+                        "topic_template",
+                    );
+                } else if s.override_topic.is_some() {
+                    overridden(
+                        &mut warnings,
+                        &[
+                            ("role", s.override_publish_role.is_some()),
+                            ("id", s.override_publish_id.is_some()),
+                        ],
+                        "topic",
+                    );
+                }
+                if let Some(qos) = s.qos.filter(|q| !(0..=2).contains(q)) {
+                    warnings.push(BuilderWarning::InvalidQos(qos));
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Like `build`, but fails (listing every problem at once) if there are any `warnings`,
+    /// instead of building the Plug regardless.
+    pub fn build_strict(self, tether_agent: &mut TetherAgent) -> anyhow::Result<PlugDefinition> {
+        let warnings = self.warnings();
+        if warnings.is_empty() {
+            return self.build(tether_agent);
+        }
+        let name = match &self {
+            Self::InputPlugOptions(s) => &s.plug_name,
+            Self::OutputPlugOptions(s) => &s.plug_name,
+        };
+        Err(anyhow!(
+            "Plug \"{}\" has {} problem(s): {}",
+            name,
+            warnings.len(),
+            warnings
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        ))
+    }
+
     /// Finalise the options (substituting suitable defaults if no custom values have been
     /// provided) and return a valid PlugDefinition that you can actually use.
+    ///
+    /// Problems with the options do not stop the Plug being built; see `warnings` to
+    /// check for them, or use `build_strict` instead.
     pub fn build(self, tether_agent: &mut TetherAgent) -> anyhow::Result<PlugDefinition> {
+        let plug_definition = self.build_undescribed(tether_agent)?;
+        tether_agent.describe_plugs([&plug_definition]);
+        Ok(plug_definition)
+    }
+
+    /// Like `build`, but without adding the Plug to the Agent's manifest
+    fn build_undescribed(self, tether_agent: &mut TetherAgent) -> anyhow::Result<PlugDefinition> {
+        let metadata = match &self {
+            Self::InputPlugOptions(s) => s.metadata.clone(),
+            Self::OutputPlugOptions(s) => s.metadata.clone(),
+        };
+        let plug_definition = match (self.build_definition(tether_agent)?, metadata) {
+            (PlugDefinition::InputPlug(p), Some(metadata)) => {
+                PlugDefinition::InputPlug(p.with_metadata(metadata))
+            }
+            (PlugDefinition::OutputPlug(p), Some(metadata)) => {
+                PlugDefinition::OutputPlug(p.with_metadata(metadata))
+            }
+            (plug_definition, None) => plug_definition,
+        };
+        Ok(plug_definition)
+    }
+
+    /// Build a `PlugGroup` of `count` Output Plugs with these options, one per index, named
+    /// after this Plug plus the index (see `group_plug_name`). Fails for an Input Plug, or
+    /// with a custom `topic` or `topic_template`, since every Plug in the group needs its
+    /// own topic.
+    pub fn build_group(
+        self,
+        tether_agent: &mut TetherAgent,
+        count: usize,
+    ) -> anyhow::Result<PlugGroup> {
+        let Self::OutputPlugOptions(options) = self else {
+            return Err(anyhow!(
+                "A Plug group can only be built from Output Plug options"
+            ));
+        };
+        if options.override_topic.is_some() || options.topic_template.is_some() {
+            return Err(anyhow!(
+                "Plugs in group \"{}\" cannot have a custom topic; each needs its own topic",
+                options.plug_name
+            ));
+        }
+        let plugs = (0..count)
+            .map(|index| {
+                Self::OutputPlugOptions(OutputPlugOptions {
+                    plug_name: group_plug_name(&options.plug_name, index),
+                    ..options.clone()
+                })
+                .build_undescribed(tether_agent)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Described all at once, rather than republishing the manifest for every Plug
+        tether_agent.describe_plugs(&plugs);
+        Ok(PlugGroup::new(&options.plug_name, plugs))
+    }
+
+    fn build_definition(self, tether_agent: &mut TetherAgent) -> anyhow::Result<PlugDefinition> {
         match self {
             Self::InputPlugOptions(plug_options) => {
-                let tpt: TetherOrCustomTopic = match plug_options.override_topic {
-                    Some(custom) => TetherOrCustomTopic::Custom(custom),
-                    None => {
-                        debug!("Not a custom topic; provided overrides: role = {:?}, id = {:?}, name = {:?}", plug_options.override_subscribe_role, plug_options.override_subscribe_id, plug_options.override_subscribe_plug_name);
-
-                        TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
-                            &plug_options.plug_name,
-                            plug_options.override_subscribe_role.as_deref(),
-                            plug_options.override_subscribe_id.as_deref(),
-                            plug_options.override_subscribe_plug_name.as_deref(),
-                        ))
+                if !tether_agent.is_consuming_incoming() {
+                    return Err(anyhow!(
+                        "Cannot create Input Plug \"{}\"; this Agent does not consume incoming messages",
+                        plug_options.plug_name
+                    ));
+                }
+                // Only a plain Three Part Topic can follow the Agent's identity, and only
+                // where the role or ID was given as the Agent's own
+                let identity_parts = match (
+                    &plug_options.override_topic,
+                    &plug_options.subscription_filter,
+                    tether_agent.topic_schema(),
+                ) {
+                    (None, None, None) => IdentityParts {
+                        role: plug_options.override_subscribe_role.as_deref()
+                            == Some(tether_agent.role()),
+                        id: plug_options.override_subscribe_id.as_deref()
+                            == Some(tether_agent.id()),
+                    },
+                    _ => IdentityParts::default(),
+                };
+                let tpt: TetherOrCustomTopic = match (
+                    plug_options.override_topic,
+                    &plug_options.subscription_filter,
+                    tether_agent.topic_schema(),
+                ) {
+                    (Some(custom), _, _) => TetherOrCustomTopic::Custom(custom),
+                    (None, Some(filter), _) => TetherOrCustomTopic::Tether(filter.to_topic()?),
+                    (None, None, Some(schema)) => {
+                        let plug_name_part = plug_options
+                            .override_subscribe_plug_name
+                            .as_deref()
+                            .unwrap_or(&plug_options.plug_name);
+                        // Anything not given (or given as "+") is left as a wildcard
+                        let params: Vec<(&str, &str)> = [
+                            (
+                                ROLE_PLACEHOLDER,
+                                plug_options.override_subscribe_role.as_deref(),
+                            ),
+                            (
+                                ID_PLACEHOLDER,
+                                plug_options.override_subscribe_id.as_deref(),
+                            ),
+                            (PLUG_PLACEHOLDER, Some(plug_name_part)),
+                        ]
+                        .into_iter()
+                        .filter_map(|(name, value)| value.filter(|v| *v != "+").map(|v| (name, v)))
+                        .collect();
+                        schema_topic(schema.fill(&params)?.to_subscribe_filter())
+                    }
+                    (None, None, None) => {
+                        debug!(
+                            target: tether_agent.log_target(),
+                            "Not a custom topic; provided overrides: role = {:?}, id = {:?}, name = {:?}", plug_options.override_subscribe_role, plug_options.override_subscribe_id, plug_options.override_subscribe_plug_name
+                        );
+
+                        lowercase_if_enabled(
+                            tether_agent,
+                            TetherOrCustomTopic::Tether(ThreePartTopic::new_for_subscribe(
+                                &plug_options.plug_name,
+                                plug_options.override_subscribe_role.as_deref(),
+                                plug_options.override_subscribe_id.as_deref(),
+                                plug_options.override_subscribe_plug_name.as_deref(),
+                            )),
+                        )
                     }
                 };
-                let plug_definition =
-                    InputPlugDefinition::new(&plug_options.plug_name, tpt, plug_options.qos);
-                if let Some(client) = &tether_agent.client {
-                    match client.subscribe(
+                let mut plug_definition = InputPlugDefinition::new(
+                    &plug_options.plug_name,
+                    tpt,
+                    plug_options.qos.or(tether_agent.default_subscribe_qos()),
+                )
+                .with_identity_parts(identity_parts)
+                .with_log_target(tether_agent.log_target());
+                if let Some(window) = plug_options.dedupe_window {
+                    plug_definition = plug_definition
+                        .with_dedupe(window, plug_options.dedupe_sequence_field.as_deref());
+                }
+                if let Some(key) = plug_options.encryption_key {
+                    plug_definition = plug_definition.with_encryption(key);
+                }
+                if let Some(field) = &plug_options.sequence_field {
+                    plug_definition = plug_definition.with_gap_detection(field);
+                }
+                let retain_handling = plug_options.retain_handling.unwrap_or_default();
+                if retain_handling == RetainHandling::SendIfNew {
+                    warn!(
+                        target: tether_agent.log_target(),
+                        "Retain handling \"send if new\" on Plug \"{}\" requires MQTT 5, but this Agent uses MQTT 3.1.1; retained messages will be sent",
+                        plug_options.plug_name
+                    );
+                }
+                plug_definition = plug_definition.with_retain_handling(retain_handling);
+                if !tether_agent.is_connected() && !tether_agent.is_lazy_connect() {
+                    info!(
+                        target: tether_agent.log_target(),
+                        "Not connected yet; subscription to \"{}\" deferred until connect",
+                        plug_definition.topic_str()
+                    );
+                    let pending = tether_agent.defer_subscription(
                         plug_definition.topic_str(),
-                        match plug_definition.qos() {
-                            0 => rumqttc::QoS::AtMostOnce,
-                            1 => rumqttc::QoS::AtLeastOnce,
-                            2 => rumqttc::QoS::ExactlyOnce,
-                            _ => rumqttc::QoS::AtLeastOnce,
-                        },
-                    ) {
-                        Ok(res) => {
-                            debug!("This topic was fine: \"{}\"", plug_definition.topic_str());
-                            debug!("Server respond OK for subscribe: {res:?}");
-                            Ok(PlugDefinition::InputPlug(plug_definition))
-                        }
-                        Err(_e) => Err(anyhow!("ClientError")),
-                    }
-                } else {
-                    Err(anyhow!("Client not available for subscription"))
+                        plug_definition.qos(),
+                        plug_definition.retain_handling(),
+                    );
+                    plug_definition.set_pending(pending);
+                    return Ok(PlugDefinition::InputPlug(plug_definition));
                 }
+                let response = tether_agent
+                    .subscribe_plug(
+                        plug_definition.topic_str(),
+                        plug_definition.qos(),
+                        plug_definition.retain_handling(),
+                        plug_options.wait_for_subscribe_response,
+                    )
+                    .map_err(|e| anyhow!("Failed to subscribe: {e}"))?;
+                debug!(
+                    target: tether_agent.log_target(),
+                    "This topic was fine: \"{}\"", plug_definition.topic_str()
+                );
+                plug_definition.set_subscribe_response(response);
+                Ok(PlugDefinition::InputPlug(plug_definition))
             }
             Self::OutputPlugOptions(plug_options) => {
-                let tpt: TetherOrCustomTopic = match plug_options.override_topic {
-                    Some(custom) => TetherOrCustomTopic::Custom(custom),
-                    None => TetherOrCustomTopic::Tether(ThreePartTopic::new_for_publish(
-                        plug_options.override_publish_role.as_deref(),
-                        plug_options.override_publish_id.as_deref(),
-                        &plug_options.plug_name,
-                        tether_agent,
-                    )),
-                };
+                if let Some(tags) = &plug_options.brokers {
+                    let known = tether_agent.broker_tags();
+                    if let Some(tag) = tags.iter().find(|t| !known.contains(&t.as_str())) {
+                        return Err(anyhow!(
+                            "Unknown broker \"{}\" for Plug \"{}\"; this Agent has {:?}",
+                            tag,
+                            plug_options.plug_name,
+                            known
+                        ));
+                    }
+                }
+                let (tpt, template, identity_parts) =
+                    if let Some(template) = &plug_options.topic_template {
+                        let template = TopicTemplate::new(template)?.fill(&[
+                            (
+                                ROLE_PLACEHOLDER,
+                                plug_options
+                                    .override_publish_role
+                                    .as_deref()
+                                    .unwrap_or(tether_agent.role()),
+                            ),
+                            (
+                                ID_PLACEHOLDER,
+                                plug_options
+                                    .override_publish_id
+                                    .as_deref()
+                                    .unwrap_or(tether_agent.id()),
+                            ),
+                            (PLUG_PLACEHOLDER, &plug_options.plug_name),
+                        ])?;
+                        let topic_string = template.to_string();
+                        let tpt = match ThreePartTopic::try_from(topic_string.as_str()) {
+                            Ok(t) if template.is_complete() => TetherOrCustomTopic::Tether(t),
+                            _ => TetherOrCustomTopic::Custom(topic_string),
+                        };
+                        (tpt, Some(template), IdentityParts::default())
+                    } else {
+                        // Only a plain Three Part Topic can follow the Agent's identity, and only
+                        // the parts which were not given explicitly
+                        let identity_parts =
+                            match (&plug_options.override_topic, tether_agent.topic_schema()) {
+                                (None, None) => IdentityParts {
+                                    role: plug_options.override_publish_role.is_none(),
+                                    id: plug_options.override_publish_id.is_none(),
+                                },
+                                _ => IdentityParts::default(),
+                            };
+                        let tpt: TetherOrCustomTopic =
+                            match (plug_options.override_topic, tether_agent.topic_schema()) {
+                                (Some(custom), _) => TetherOrCustomTopic::Custom(custom),
+                                (None, Some(schema)) => schema_topic(
+                                    schema.render(&[
+                                        (
+                                            ROLE_PLACEHOLDER,
+                                            plug_options
+                                                .override_publish_role
+                                                .as_deref()
+                                                .unwrap_or(tether_agent.role()),
+                                        ),
+                                        (
+                                            ID_PLACEHOLDER,
+                                            plug_options
+                                                .override_publish_id
+                                                .as_deref()
+                                                .unwrap_or(tether_agent.id()),
+                                        ),
+                                        (PLUG_PLACEHOLDER, &plug_options.plug_name),
+                                    ])?,
+                                ),
+                                (None, None) => lowercase_if_enabled(
+                                    tether_agent,
+                                    TetherOrCustomTopic::Tether(ThreePartTopic::new_for_publish(
+                                        plug_options.override_publish_role.as_deref(),
+                                        plug_options.override_publish_id.as_deref(),
+                                        &plug_options.plug_name,
+                                        tether_agent,
+                                    )),
+                                ),
+                            };
+                        (tpt, None, identity_parts)
+                    };
 
-                let plug_definition = OutputPlugDefinition::new(
+                let mut plug_definition = OutputPlugDefinition::new(
                     &plug_options.plug_name,
                     tpt,
-                    plug_options.qos,
+                    plug_options.qos.or(tether_agent.default_publish_qos()),
                     plug_options.retain,
-                );
+                )
+                .with_identity_parts(identity_parts)
+                .with_log_target(tether_agent.log_target());
+                if let Some(template) = template {
+                    plug_definition = plug_definition.with_topic_template(template);
+                }
+                if let Some(key) = plug_options.encryption_key {
+                    plug_definition = plug_definition.with_encryption(key);
+                }
+                if let Some(interval) = plug_options.coalesce {
+                    plug_definition = plug_definition.with_coalesce(interval);
+                }
+                if let Some(field) = &plug_options.sequence_field {
+                    plug_definition = plug_definition.with_sequence(field);
+                }
+                if plug_options.persist_last_value {
+                    plug_definition = plug_definition.with_persist_last_value();
+                }
+                if let Some(tags) = plug_options.brokers {
+                    plug_definition = plug_definition.with_brokers(tags);
+                }
                 Ok(PlugDefinition::OutputPlug(plug_definition))
             }
         }
@@ -301,7 +987,12 @@ impl PlugOptionsBuilder {
 #[cfg(test)]
 mod tests {
 
-    use crate::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+    use std::time::Duration;
+
+    use crate::{
+        BuilderWarning, EncryptionKey, PlugDefinition, PlugDefinitionCommon, PlugOptionsBuilder,
+        TetherAgentOptionsBuilder,
+    };
 
     // fn verbose_logging() {
     //     use env_logger::{Builder, Env};
@@ -340,6 +1031,28 @@ mod tests {
         assert_eq!(input.topic(), "+/+/one");
     }
 
+    #[test]
+    fn agent_default_qos_per_direction() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .default_subscribe_qos(Some(2))
+            .default_publish_qos(Some(0))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let input = PlugOptionsBuilder::create_input("one")
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(input.qos(), 2);
+        let output = PlugOptionsBuilder::create_output("two")
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(output.qos(), 0);
+        let explicit = PlugOptionsBuilder::create_output("three")
+            .qos(Some(1))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(explicit.qos(), 1);
+    }
+
     #[test]
     fn default_output_plug() {
         let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
@@ -509,6 +1222,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn output_topic_template() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+
+        let output_defaults = PlugOptionsBuilder::create_output("readings")
+            .topic_template(Some("{role}/{id}/{plug}/{index}"))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(output_defaults.name(), "readings");
+        assert_eq!(output_defaults.topic(), "tester/any/readings/{index}");
+        if let PlugDefinition::OutputPlug(p) = &output_defaults {
+            assert_eq!(
+                p.render_topic(&[("index", "2")]).unwrap(),
+                "tester/any/readings/2"
+            );
+            assert!(p.render_topic(&[]).is_err());
+        } else {
+            panic!("expected an Output Plug");
+        }
+
+        let output_overrides = PlugOptionsBuilder::create_output("readings")
+            .role(Some("customRole"))
+            .topic_template(Some("{role}/{id}/sensors"))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(output_overrides.topic(), "customRole/any/sensors");
+
+        assert!(PlugOptionsBuilder::create_output("broken")
+            .topic_template(Some("{role}/{id"))
+            .build(&mut tether_agent)
+            .is_err());
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+
+        let key = EncryptionKey::generate();
+        let topic = format!("tester/{}/secrets", uuid::Uuid::new_v4());
+
+        let input = PlugOptionsBuilder::create_input("secrets")
+            .topic(Some(&topic))
+            .encryption_key(Some(key.clone()))
+            .build(&mut tether_agent)
+            .unwrap();
+        let output = PlugOptionsBuilder::create_output("secrets")
+            .topic(Some(&topic))
+            .encryption_key(Some(key))
+            .build(&mut tether_agent)
+            .unwrap();
+
+        tether_agent
+            .encode_and_publish(&output, "hello")
+            .expect("failed to publish");
+
+        let start = std::time::SystemTime::now();
+        loop {
+            if let Some((incoming_topic, payload)) = tether_agent.check_messages() {
+                if incoming_topic.full_topic_string() == topic {
+                    assert!(crate::is_encrypted(&payload));
+                    let decrypted = input.decrypt(&payload).unwrap();
+                    let decoded: String = rmp_serde::from_slice(&decrypted).unwrap();
+                    assert_eq!(decoded, "hello");
+                    break;
+                }
+            }
+            assert!(start.elapsed().unwrap() < std::time::Duration::from_secs(5));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn subscribe_response() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+
+        let input = PlugOptionsBuilder::create_input("one")
+            .qos(Some(2))
+            .wait_for_subscribe_response(true)
+            .build(&mut tether_agent)
+            .unwrap();
+        let PlugDefinition::InputPlug(input) = input else {
+            panic!("expected Input Plug");
+        };
+        let response = input
+            .subscribe_response()
+            .expect("response should be stored");
+        assert_eq!(response.return_codes(), &[Some(2)]);
+        assert_eq!(response.granted_qos(), Some(input.qos()));
+        assert!(response.is_success());
+
+        let input = PlugOptionsBuilder::create_input("two")
+            .build(&mut tether_agent)
+            .unwrap();
+        let PlugDefinition::InputPlug(input) = input else {
+            panic!("expected Input Plug");
+        };
+        assert!(input.subscribe_response().is_none());
+    }
+
     #[test]
     fn input_manual_topics() {
         let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
@@ -529,4 +1347,223 @@ mod tests {
         assert_eq!(input_nontether.name(), "weird");
         assert_eq!(input_nontether.topic(), "foo/bar/baz/one/two/three");
     }
+
+    #[test]
+    fn lowercase_mixed_case_topics() {
+        let id = uuid::Uuid::new_v4().to_string().to_uppercase();
+        let mut tether_agent = TetherAgentOptionsBuilder::new("Tester")
+            .id(Some(&id))
+            .lowercase_topics(true)
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        assert!(tether_agent.is_lowercasing_topics());
+
+        let input = PlugOptionsBuilder::create_input("Temperature")
+            .role(Some("Tester"))
+            .id(Some(&id))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(input.name(), "Temperature");
+        assert_eq!(
+            input.topic(),
+            format!("tester/{}/temperature", id.to_lowercase())
+        );
+
+        let output = PlugOptionsBuilder::create_output("temperature")
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(input.topic(), output.topic());
+
+        // Topics given explicitly are left alone, even if they have three parts
+        let custom_topic = format!("External/{}/Temperature", id);
+        let custom = PlugOptionsBuilder::create_input("custom")
+            .topic(Some(&custom_topic))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(custom.topic(), custom_topic);
+        let templated = PlugOptionsBuilder::create_output("TEMPERATURE")
+            .topic_template(Some("{role}/{id}/{plug}"))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(templated.topic(), format!("Tester/{}/TEMPERATURE", id));
+
+        // ...and so is a topic published on directly
+        tether_agent
+            .publish_raw(&custom_topic, &[], None, None)
+            .unwrap();
+        tether_agent.encode_and_publish(&output, 21.5).unwrap();
+
+        let mut received = Vec::new();
+        let start = std::time::SystemTime::now();
+        while received.len() < 2 {
+            if let Some((topic, _)) = tether_agent.check_messages() {
+                received.push(topic.full_topic_string());
+            }
+            assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(received.contains(&custom_topic));
+        assert!(received.contains(&input.topic().to_string()));
+    }
+
+    #[test]
+    fn builder_reports_all_warnings() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .auto_connect(false)
+            .build()
+            .unwrap();
+
+        let input = PlugOptionsBuilder::create_input("everything")
+            .topic(Some("some/custom/topic"))
+            .role(Some("brain"))
+            .retain(Some(true))
+            .coalesce(Some(Duration::from_millis(10)))
+            .qos(Some(3))
+            .dedupe_sequence_field(Some("seq"));
+        assert_eq!(
+            input.warnings(),
+            vec![
+                BuilderWarning::OutputOnly("retain"),
+                BuilderWarning::OutputOnly("coalesce"),
+                BuilderWarning::Overridden {
+                    option: "role",
+                    by: "topic"
+                },
+                BuilderWarning::Requires {
+                    option: "dedupe_sequence_field",
+                    requires: "dedupe_window"
+                },
+                BuilderWarning::InvalidQos(3),
+            ]
+        );
+        let e = input.build_strict(&mut tether_agent).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("Plug \"everything\" has 5 problem(s)"));
+
+        // The order of the options does not matter
+        let output = PlugOptionsBuilder::create_output("everything")
+            .id(Some("special"))
+            .topic(Some("some/custom/topic"))
+            .name(Some("+"));
+        assert_eq!(
+            output.warnings(),
+            vec![
+                BuilderWarning::InputOnly("name"),
+                BuilderWarning::Overridden {
+                    option: "id",
+                    by: "topic"
+                },
+            ]
+        );
+
+        // Problems do not stop a lenient build
+        let output = output.build(&mut tether_agent).unwrap();
+        assert_eq!(output.topic(), "some/custom/topic");
+
+        let fine = PlugOptionsBuilder::create_input("fine")
+            .role(Some("brain"))
+            .any_plug()
+            .qos(Some(2));
+        assert!(fine.warnings().is_empty());
+        assert_eq!(
+            fine.build_strict(&mut tether_agent).unwrap().topic(),
+            "brain/+/+"
+        );
+    }
+
+    #[test]
+    fn default_topics_by_topic_schema() {
+        let topics = |schema: Option<&str>| {
+            let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+                .id(Some("left"))
+                .topic_schema(schema)
+                .auto_connect(false)
+                .build()
+                .unwrap();
+            let input = PlugOptionsBuilder::create_input("one")
+                .build(&mut tether_agent)
+                .unwrap();
+            let specific_input = PlugOptionsBuilder::create_input("one")
+                .role(Some("brain"))
+                .any_plug()
+                .build(&mut tether_agent)
+                .unwrap();
+            let output = PlugOptionsBuilder::create_output("one")
+                .build(&mut tether_agent)
+                .unwrap();
+            (
+                String::from(input.topic()),
+                String::from(specific_input.topic()),
+                String::from(output.topic()),
+            )
+        };
+
+        // The standard Three Part Topics, whether or not the schema is given explicitly
+        let standard = (
+            "+/+/one".to_string(),
+            "brain/+/+".to_string(),
+            "tester/left/one".to_string(),
+        );
+        assert_eq!(topics(None), standard);
+        assert_eq!(topics(Some("{role}/{id}/{plug}")), standard);
+
+        assert_eq!(
+            topics(Some("myApp/{role}/{id}/{plug}")),
+            (
+                "myApp/+/+/one".to_string(),
+                "myApp/brain/+/+".to_string(),
+                "myApp/tester/left/one".to_string()
+            )
+        );
+        assert_eq!(
+            topics(Some("site/{role}/{id}/sensors/{plug}/data")),
+            (
+                "site/+/+/sensors/one/data".to_string(),
+                "site/brain/+/sensors/+/data".to_string(),
+                "site/tester/left/sensors/one/data".to_string()
+            )
+        );
+
+        assert!(TetherAgentOptionsBuilder::new("tester")
+            .topic_schema(Some("myApp/{role}/{id}"))
+            .auto_connect(false)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn namespaced_round_trip() {
+        let namespace = format!("test-{}", uuid::Uuid::new_v4());
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .topic_schema(Some(&format!("{}/{{role}}/{{id}}/{{plug}}", namespace)))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let input = PlugOptionsBuilder::create_input("values")
+            .build(&mut tether_agent)
+            .unwrap();
+        let other_input = PlugOptionsBuilder::create_input("others")
+            .build(&mut tether_agent)
+            .unwrap();
+        let output = PlugOptionsBuilder::create_output("values")
+            .build(&mut tether_agent)
+            .unwrap();
+        tether_agent.encode_and_publish(&output, 42).unwrap();
+
+        let start = std::time::SystemTime::now();
+        loop {
+            assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+            if let Some((topic, payload)) = tether_agent.check_messages() {
+                assert_eq!(
+                    topic.full_topic_string(),
+                    format!("{}/tester/any/values", namespace)
+                );
+                assert!(input.matches(&topic));
+                assert!(!other_input.matches(&topic));
+                assert_eq!(rmp_serde::from_slice::<i32>(&payload).unwrap(), 42);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
 }
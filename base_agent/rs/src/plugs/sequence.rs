@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::anyhow;
+use log::{debug, warn};
+use rmpv::Value;
+
+use crate::LOG_TARGET;
+
+/// Messages were missed between two consecutive sequence numbers received on a topic
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceGap {
+    pub topic: String,
+    /// The sequence number that should have come next
+    pub expected: u64,
+    /// The sequence number that actually arrived
+    pub received: u64,
+}
+
+impl SequenceGap {
+    /// How many messages are missing
+    pub fn missed(&self) -> u64 {
+        self.received - self.expected
+    }
+}
+
+/// Adds a monotonically-increasing sequence number to every payload published on an
+/// Output Plug, as a field in the (MessagePack map) payload, starting at zero.
+#[derive(Debug)]
+pub struct Sequencer {
+    field: String,
+    next: AtomicU64,
+}
+
+impl Sequencer {
+    pub fn new(field: &str) -> Sequencer {
+        Sequencer {
+            field: String::from(field),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Returns a copy of the payload with the next sequence number set in the sequence
+    /// field (replacing any existing value). Fails if the payload is not a map.
+    pub fn stamp(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        self.stamp_next(payload).map(|(stamped, _)| stamped)
+    }
+
+    /// Like `stamp`, but also returns the sequence number used, so that it can be given
+    /// back with `release` if the message is not sent after all. A payload which cannot be
+    /// stamped does not use up a number.
+    pub(crate) fn stamp_next(&self, payload: &[u8]) -> anyhow::Result<(Vec<u8>, u64)> {
+        let mut value = rmpv::decode::read_value(&mut &payload[..])?;
+        let Value::Map(entries) = &mut value else {
+            return Err(anyhow!(
+                "Cannot add sequence field \"{}\" to a payload which is not a map",
+                self.field
+            ));
+        };
+        let sequence = self.next.fetch_add(1, Ordering::SeqCst);
+        match entries
+            .iter_mut()
+            .find(|(k, _)| k.as_str() == Some(self.field.as_str()))
+        {
+            Some((_, v)) => *v = Value::from(sequence),
+            None => entries.push((Value::from(self.field.as_str()), Value::from(sequence))),
+        }
+        let mut stamped = Vec::with_capacity(payload.len() + self.field.len() + 10);
+        if let Err(e) = rmpv::encode::write_value(&mut stamped, &value) {
+            self.release(sequence);
+            return Err(e.into());
+        }
+        Ok((stamped, sequence))
+    }
+
+    /// Give back a sequence number from `stamp_next` for a message which was not sent, so
+    /// that receivers do not see a gap. Only possible if no later number has been used
+    /// since; otherwise the gap remains.
+    pub(crate) fn release(&self, sequence: u64) {
+        let _ =
+            self.next
+                .compare_exchange(sequence + 1, sequence, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+/// Tracks the last sequence number received on each topic (i.e. per source), and reports
+/// a gap whenever a sequence number is skipped, e.g. because QoS 0 messages were dropped.
+///
+/// A sequence number lower than expected is taken to mean that the publisher restarted
+/// (or the message arrived out of order); tracking simply continues from there.
+#[derive(Debug)]
+pub struct GapDetector {
+    field: String,
+    last_received: Mutex<HashMap<String, u64>>,
+    gap_count: AtomicU64,
+    missed_count: AtomicU64,
+    log_target: String,
+}
+
+impl GapDetector {
+    pub fn new(field: &str) -> GapDetector {
+        GapDetector {
+            field: String::from(field),
+            last_received: Mutex::new(HashMap::new()),
+            gap_count: AtomicU64::new(0),
+            missed_count: AtomicU64::new(0),
+            log_target: String::from(LOG_TARGET),
+        }
+    }
+
+    /// Log under this target instead of `LOG_TARGET`, e.g. that of the Agent the Plug
+    /// belongs to (see `TetherAgentOptionsBuilder::label`)
+    pub fn with_log_target(mut self, log_target: &str) -> GapDetector {
+        self.log_target = String::from(log_target);
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Check the sequence number in this payload against the last one seen on the topic
+    pub fn check(&self, topic: &str, payload: &[u8]) -> Option<SequenceGap> {
+        let Some(received) = read_sequence(&self.field, payload) else {
+            warn!(
+                target: &self.log_target,
+                "No sequence field \"{}\" in message on topic \"{}\"; cannot check for gaps",
+                self.field, topic
+            );
+            return None;
+        };
+        let mut last_received = self.last_received.lock().expect("failed to lock mutex");
+        let previous = last_received.insert(String::from(topic), received)?;
+        let expected = previous + 1;
+        if received > expected {
+            let gap = SequenceGap {
+                topic: String::from(topic),
+                expected,
+                received,
+            };
+            warn!(
+                target: &self.log_target,
+                "Missed {} message(s) on topic \"{}\"",
+                gap.missed(),
+                gap.topic
+            );
+            self.gap_count.fetch_add(1, Ordering::SeqCst);
+            self.missed_count.fetch_add(gap.missed(), Ordering::SeqCst);
+            Some(gap)
+        } else {
+            if received < expected {
+                debug!(
+                    target: &self.log_target,
+                    "Sequence on topic \"{}\" went back from {} to {}; publisher restarted?",
+                    topic, previous, received
+                );
+            }
+            None
+        }
+    }
+
+    /// How many gaps have been detected so far, on all topics
+    pub fn gap_count(&self) -> u64 {
+        self.gap_count.load(Ordering::SeqCst)
+    }
+
+    /// How many messages have been missed in total, on all topics
+    pub fn missed_count(&self) -> u64 {
+        self.missed_count.load(Ordering::SeqCst)
+    }
+}
+
+fn read_sequence(field: &str, payload: &[u8]) -> Option<u64> {
+    let value = rmpv::decode::read_value(&mut &payload[..]).ok()?;
+    value
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(field))
+        .and_then(|(_, v)| v.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{GapDetector, SequenceGap, Sequencer};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        value: f32,
+        seq: Option<u64>,
+    }
+
+    #[test]
+    fn stamp_and_detect_gap() {
+        let sequencer = Sequencer::new("seq");
+        let detector = GapDetector::new("seq");
+        let payload = rmp_serde::to_vec_named(&Reading {
+            value: 0.5,
+            seq: None,
+        })
+        .unwrap();
+
+        let stamped: Vec<Vec<u8>> = (0..5).map(|_| sequencer.stamp(&payload).unwrap()).collect();
+        let decoded: Reading = rmp_serde::from_slice(&stamped[3]).unwrap();
+        assert_eq!(
+            decoded,
+            Reading {
+                value: 0.5,
+                seq: Some(3)
+            }
+        );
+
+        assert_eq!(detector.check("a/b/c", &stamped[0]), None);
+        assert_eq!(detector.check("a/b/c", &stamped[1]), None);
+        // Messages 2 and 3 go missing
+        assert_eq!(
+            detector.check("a/b/c", &stamped[4]),
+            Some(SequenceGap {
+                topic: "a/b/c".into(),
+                expected: 2,
+                received: 4
+            })
+        );
+        assert_eq!(detector.gap_count(), 1);
+        assert_eq!(detector.missed_count(), 2);
+
+        // Tracked separately per topic; and a restarted publisher is not a gap
+        assert_eq!(detector.check("x/y/z", &stamped[2]), None);
+        assert_eq!(detector.check("a/b/c", &stamped[0]), None);
+        assert_eq!(detector.gap_count(), 1);
+    }
+
+    #[test]
+    fn only_maps_stamped() {
+        let sequencer = Sequencer::new("seq");
+        assert!(sequencer
+            .stamp(&rmp_serde::to_vec(&[1, 2, 3]).unwrap())
+            .is_err());
+    }
+}
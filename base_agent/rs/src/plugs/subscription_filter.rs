@@ -0,0 +1,138 @@
+use crate::three_part_topic::{validate_part, ThreePartTopic};
+
+/// A subscription to Tether topics, built part by part: each of the role, ID and Plug
+/// Name parts is either a specific value or (if not specified) the wildcard `+`, e.g.
+/// `SubscriptionFilter::new().role(Some("brain")).plug(Some("decisions"))` gives
+/// `brain/+/decisions`. Use it with `PlugOptionsBuilder::subscription_filter`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionFilter {
+    role: Option<String>,
+    id: Option<String>,
+    plug: Option<String>,
+}
+
+impl SubscriptionFilter {
+    /// Matches everything (`+/+/+`) until any parts are specified
+    pub fn new() -> SubscriptionFilter {
+        SubscriptionFilter::default()
+    }
+
+    /// Provide Some(value) to match only that role, or None (or `+`) to match any
+    pub fn role(mut self, role: Option<&str>) -> Self {
+        self.role = role.filter(|r| *r != "+").map(|r| r.into());
+        self
+    }
+
+    /// Provide Some(value) to match only that ID, or None (or `+`) to match any
+    pub fn id(mut self, id: Option<&str>) -> Self {
+        self.id = id.filter(|i| *i != "+").map(|i| i.into());
+        self
+    }
+
+    /// Provide Some(value) to match only that Plug Name, or None (or `+`) to match any
+    pub fn plug(mut self, plug: Option<&str>) -> Self {
+        self.plug = plug.filter(|p| *p != "+").map(|p| p.into());
+        self
+    }
+
+    /// The filter as a topic, with `+` for each part that was not specified. Fails if any
+    /// specified part is empty or contains separators (`/`) or wildcards.
+    pub fn to_topic(&self) -> anyhow::Result<ThreePartTopic> {
+        let role = self.role.as_deref().unwrap_or("+");
+        let id = self.id.as_deref().unwrap_or("+");
+        let plug = self.plug.as_deref().unwrap_or("+");
+        if let Some(role) = &self.role {
+            validate_part("role", role)?;
+        }
+        if let Some(id) = &self.id {
+            validate_part("id", id)?;
+        }
+        if let Some(plug) = &self.plug {
+            validate_part("plug name", plug)?;
+        }
+        Ok(ThreePartTopic::new_unchecked(role, id, plug))
+    }
+
+    /// The validated filter string, e.g. `brain/+/decisions`
+    pub fn build(&self) -> anyhow::Result<String> {
+        self.to_topic().map(|t| String::from(t.topic()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+    use super::SubscriptionFilter;
+
+    #[test]
+    fn specified_and_wildcard_parts() {
+        let filter = |role, id, plug| {
+            SubscriptionFilter::new()
+                .role(role)
+                .id(id)
+                .plug(plug)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(filter(None, None, None), "+/+/+");
+        assert_eq!(filter(Some("brain"), None, None), "brain/+/+");
+        assert_eq!(filter(None, Some("left"), None), "+/left/+");
+        assert_eq!(filter(None, None, Some("decisions")), "+/+/decisions");
+        assert_eq!(filter(Some("brain"), Some("left"), None), "brain/left/+");
+        assert_eq!(
+            filter(Some("brain"), None, Some("decisions")),
+            "brain/+/decisions"
+        );
+        assert_eq!(
+            filter(None, Some("left"), Some("decisions")),
+            "+/left/decisions"
+        );
+        assert_eq!(
+            filter(Some("brain"), Some("left"), Some("decisions")),
+            "brain/left/decisions"
+        );
+
+        // An explicit wildcard is the same as leaving the part out
+        assert_eq!(filter(Some("+"), Some("left"), Some("+")), "+/left/+");
+        assert_eq!(
+            SubscriptionFilter::new().id(Some("left")),
+            SubscriptionFilter::new().role(Some("+")).id(Some("left"))
+        );
+    }
+
+    #[test]
+    fn invalid_parts_rejected() {
+        assert!(SubscriptionFilter::new().role(Some("")).build().is_err());
+        assert!(SubscriptionFilter::new().id(Some("a/b")).build().is_err());
+        assert!(SubscriptionFilter::new().plug(Some("#")).build().is_err());
+    }
+
+    #[test]
+    fn input_plug_with_filter() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .auto_connect(false)
+            .build()
+            .unwrap();
+        let filter = SubscriptionFilter::new()
+            .role(Some("brain"))
+            .plug(Some("decisions"));
+
+        let input = PlugOptionsBuilder::create_input("brainDecisions")
+            .subscription_filter(Some(&filter))
+            .build(&mut tether_agent)
+            .unwrap();
+        assert_eq!(input.name(), "brainDecisions");
+        assert_eq!(input.topic(), "brain/+/decisions");
+
+        let topic = "brain/left/decisions".try_into().unwrap();
+        assert!(input.matches(&crate::TetherOrCustomTopic::Tether(topic)));
+        let topic = "brain/left/other".try_into().unwrap();
+        assert!(!input.matches(&crate::TetherOrCustomTopic::Tether(topic)));
+
+        assert!(PlugOptionsBuilder::create_input("broken")
+            .subscription_filter(Some(&SubscriptionFilter::new().id(Some(""))))
+            .build(&mut tether_agent)
+            .is_err());
+    }
+}
@@ -2,9 +2,10 @@ use anyhow::anyhow;
 use log::{debug, error};
 use serde::{Deserialize, Serialize};
 
-use crate::TetherAgent;
+use crate::{AgentIdentity, TetherAgent, LOG_TARGET};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A topic following the Tether convention of exactly three parts: `role/id/plugName`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ThreePartTopic {
     role: String,
     id: String,
@@ -12,7 +13,15 @@ pub struct ThreePartTopic {
     full_topic: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Which parts of a Plug's Three Part Topic were built from the Agent's own identity, and
+/// so follow it to a new one (see `TetherAgent::reidentify`); any other part stays as it is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdentityParts {
+    pub role: bool,
+    pub id: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum TetherOrCustomTopic {
     Tether(ThreePartTopic),
     Custom(String),
@@ -25,6 +34,39 @@ impl TetherOrCustomTopic {
             TetherOrCustomTopic::Custom(t) => String::from(t),
         }
     }
+
+    /// The same topic for an Agent which has changed identity to `new`: each role or ID
+    /// part which came from the Agent's own identity is replaced. None if nothing changes
+    /// (including for custom topics).
+    pub(crate) fn reidentified(
+        &self,
+        parts: IdentityParts,
+        new: &AgentIdentity,
+    ) -> Option<TetherOrCustomTopic> {
+        let TetherOrCustomTopic::Tether(t) = self else {
+            return None;
+        };
+        let role = if parts.role { new.role() } else { &t.role };
+        let id = if parts.id { new.id() } else { &t.id };
+        (role != t.role || id != t.id).then(|| {
+            TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(role, id, &t.plug_name))
+        })
+    }
+
+    /// The same topic with its role, ID and Plug Name parts lowercased; a custom topic is
+    /// returned unchanged, since it may be shared with case-sensitive external systems
+    pub fn to_lowercase(&self) -> TetherOrCustomTopic {
+        match self {
+            TetherOrCustomTopic::Tether(t) => {
+                TetherOrCustomTopic::Tether(ThreePartTopic::new_unchecked(
+                    &t.role.to_lowercase(),
+                    &t.id.to_lowercase(),
+                    &t.plug_name.to_lowercase(),
+                ))
+            }
+            TetherOrCustomTopic::Custom(t) => TetherOrCustomTopic::Custom(t.clone()),
+        }
+    }
 }
 
 impl ThreePartTopic {
@@ -60,7 +102,10 @@ impl ThreePartTopic {
         let plug_name_part = match plug_name_part_override {
             Some(s) => {
                 if !&s.eq("+") {
-                    error!("The only valid override for the Plug Name part is a wildcard (+)");
+                    error!(
+                        target: LOG_TARGET,
+                        "The only valid override for the Plug Name part is a wildcard (+)"
+                    );
                 }
                 s
             }
@@ -76,7 +121,20 @@ impl ThreePartTopic {
         }
     }
 
-    pub fn new(role: &str, id: &str, plug_name: &str) -> ThreePartTopic {
+    /// Construct a canonical topic from its three parts, checking that each part is
+    /// non-empty and contains no separators (`/`) or wildcards (`+`, `#`), i.e. a topic
+    /// which is suitable for publishing. See `new_for_publish` / `new_for_subscribe` for
+    /// defaults (and wildcards, to subscribe).
+    pub fn new(role: &str, id: &str, plug_name: &str) -> anyhow::Result<ThreePartTopic> {
+        validate_part("role", role)?;
+        validate_part("id", id)?;
+        validate_part("plug name", plug_name)?;
+        Ok(ThreePartTopic::new_unchecked(role, id, plug_name))
+    }
+
+    /// Construct a topic from its three parts as-is, e.g. with wildcards for a subscription,
+    /// or as received from the broker
+    pub(crate) fn new_unchecked(role: &str, id: &str, plug_name: &str) -> ThreePartTopic {
         ThreePartTopic {
             role: role.into(),
             id: id.into(),
@@ -134,14 +192,28 @@ impl TryFrom<&str> for ThreePartTopic {
                 value
             ));
         } else {
-            debug!("parts: {:?}", parts);
+            debug!(target: LOG_TARGET, "parts: {:?}", parts);
         }
 
         let role = parts.first().expect("the role part should exist");
         let id = parts.get(1).expect("the id part should exist");
         let plug_name = parts.get(2).expect("the plug_name part should exist");
 
-        Ok(ThreePartTopic::new(role, id, plug_name))
+        Ok(ThreePartTopic::new_unchecked(role, id, plug_name))
+    }
+}
+
+pub(crate) fn validate_part(part_name: &str, value: &str) -> anyhow::Result<()> {
+    if value.is_empty() {
+        Err(anyhow!("The {} part of a topic cannot be empty", part_name))
+    } else if value.contains(['/', '+', '#']) {
+        Err(anyhow!(
+            "The {} part of a topic cannot contain separators or wildcards, got \"{}\"",
+            part_name,
+            value
+        ))
+    } else {
+        Ok(())
     }
 }
 
@@ -149,6 +221,25 @@ pub fn build_topic(role: &str, id: &str, plug_name: &str) -> String {
     format!("{role}/{id}/{plug_name}")
 }
 
+/// Whether the topic matches the subscription filter, which may contain MQTT wildcards:
+/// `+` for exactly one level and `#` (last) for any number of levels, including none. As
+/// in MQTT, topics starting with `$` are not matched by a wildcard in the first level.
+pub fn topic_filter_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+    let mut topic_levels = topic.split('/');
+    for filter_level in filter.split('/') {
+        match (filter_level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (f, Some(t)) if f == t => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
 pub fn parse_plug_name(topic: &str) -> Option<&str> {
     let parts: Vec<&str> = topic.split('/').collect();
     match parts.get(2) {
@@ -175,7 +266,27 @@ pub fn parse_agent_role(topic: &str) -> Option<&str> {
 
 #[cfg(test)]
 mod tests {
-    use crate::three_part_topic::{parse_agent_id, parse_agent_role, parse_plug_name};
+    use crate::three_part_topic::{
+        parse_agent_id, parse_agent_role, parse_plug_name, topic_filter_matches, ThreePartTopic,
+    };
+
+    #[test]
+    fn validated_constructor() {
+        let tpt = ThreePartTopic::new("brain", "left", "decisions").unwrap();
+        assert_eq!(tpt.topic(), "brain/left/decisions");
+        assert_eq!(tpt.role(), "brain");
+        assert_eq!(tpt.id(), "left");
+        assert_eq!(tpt.plug_name(), "decisions");
+        assert_eq!(
+            tpt,
+            ThreePartTopic::try_from("brain/left/decisions").unwrap()
+        );
+
+        assert!(ThreePartTopic::new("", "left", "decisions").is_err());
+        assert!(ThreePartTopic::new("brain", "+", "decisions").is_err());
+        assert!(ThreePartTopic::new("brain", "left", "#").is_err());
+        assert!(ThreePartTopic::new("brain/extra", "left", "decisions").is_err());
+    }
 
     #[test]
     fn util_parsers() {
@@ -184,4 +295,20 @@ mod tests {
         assert_eq!(parse_plug_name("one/two/three"), Some("three"));
         assert_eq!(parse_plug_name("just/two"), None);
     }
+
+    #[test]
+    fn filter_matching() {
+        assert!(topic_filter_matches("+/+/plug", "brain/left/plug"));
+        assert!(!topic_filter_matches("+/+/plug", "brain/left/other"));
+        assert!(!topic_filter_matches("+/+/plug", "ns/brain/left/plug"));
+        assert!(topic_filter_matches("ns/+/+/plug", "ns/brain/left/plug"));
+        assert!(topic_filter_matches("ns/#", "ns/brain/left/plug"));
+        assert!(topic_filter_matches("ns/#", "ns"));
+        assert!(topic_filter_matches("#", "any/thing/at/all"));
+        assert!(topic_filter_matches("exact/topic", "exact/topic"));
+        assert!(!topic_filter_matches("exact/topic", "exact/topic/deeper"));
+        assert!(!topic_filter_matches("exact/topic/deeper", "exact/topic"));
+        assert!(!topic_filter_matches("#", "$SYS/broker/uptime"));
+        assert!(topic_filter_matches("$SYS/#", "$SYS/broker/uptime"));
+    }
 }
@@ -0,0 +1,162 @@
+use std::collections::BTreeSet;
+
+use anyhow::anyhow;
+
+use super::topic_template::{has_whole_level_placeholders, TopicTemplate};
+
+/// A rule for mapping Tether topics to and from the topics of another MQTT system which
+/// uses a different (e.g. legacy) naming scheme, given as a pair of templates with the
+/// same placeholders, e.g. `{role}/{id}/{plug}` and `site/{id}/{role}/{plug}/value`.
+///
+/// Each placeholder must be a whole level of the topic. See
+/// `TetherAgentOptionsBuilder::topic_rewrites`, which applies the rules on publishing,
+/// subscribing and receiving.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRewrite {
+    tether: TopicTemplate,
+    legacy: TopicTemplate,
+}
+
+impl TopicRewrite {
+    pub fn new(tether: &str, legacy: &str) -> anyhow::Result<TopicRewrite> {
+        for template in [tether, legacy] {
+            if !has_whole_level_placeholders(template) {
+                return Err(anyhow!(
+                    "Each placeholder in topic rewrite template \"{}\" must be a whole level of the topic",
+                    template
+                ));
+            }
+        }
+        let rewrite = TopicRewrite {
+            tether: TopicTemplate::new(tether)?,
+            legacy: TopicTemplate::new(legacy)?,
+        };
+        let tether_placeholders: BTreeSet<&str> =
+            rewrite.tether.placeholders().into_iter().collect();
+        let legacy_placeholders: BTreeSet<&str> =
+            rewrite.legacy.placeholders().into_iter().collect();
+        if tether_placeholders != legacy_placeholders {
+            return Err(anyhow!(
+                "Topic rewrite templates \"{}\" and \"{}\" must have the same placeholders",
+                tether,
+                legacy
+            ));
+        }
+        Ok(rewrite)
+    }
+
+    /// The other system's topic for this Tether topic, or None if the rule does not apply.
+    /// This also works for subscription filters, as long as any `+` wildcards stand in for
+    /// whole placeholders; they are kept as wildcards.
+    pub fn to_legacy(&self, topic: &str) -> Option<String> {
+        translate(&self.tether, &self.legacy, topic)
+    }
+
+    /// The Tether topic for this topic from the other system, or None if the rule does not
+    /// apply
+    pub fn to_tether(&self, topic: &str) -> Option<String> {
+        translate(&self.legacy, &self.tether, topic)
+    }
+}
+
+/// Apply the first of the rules which matches, in either direction; topics which no rule
+/// matches are left unchanged
+pub(crate) fn rewrite_topic(
+    rewrites: &[TopicRewrite],
+    topic: String,
+    rewrite: fn(&TopicRewrite, &str) -> Option<String>,
+) -> String {
+    rewrites
+        .iter()
+        .find_map(|r| rewrite(r, &topic))
+        .unwrap_or(topic)
+}
+
+/// Match the topic against one template, level by level, and render the other template
+/// with the values of the placeholders found
+fn translate(from: &TopicTemplate, to: &TopicTemplate, topic: &str) -> Option<String> {
+    let pattern = from.to_string();
+    let pattern_levels: Vec<&str> = pattern.split('/').collect();
+    let levels: Vec<&str> = topic.split('/').collect();
+    if pattern_levels.len() != levels.len() {
+        return None;
+    }
+    let mut params: Vec<(&str, &str)> = Vec::new();
+    for (pattern_level, level) in pattern_levels.iter().zip(levels) {
+        match pattern_level
+            .strip_prefix('{')
+            .and_then(|p| p.strip_suffix('}'))
+        {
+            Some(_) if level.is_empty() || level == "#" => return None,
+            Some(name) => match params.iter().find(|(n, _)| *n == name) {
+                Some((_, value)) if *value != level => return None,
+                Some(_) => {}
+                None => params.push((name, level)),
+            },
+            None if *pattern_level == level => {}
+            None => return None,
+        }
+    }
+    // Any wildcards are left unfilled, so that they are rendered as wildcards again
+    params.retain(|(_, value)| *value != "+");
+    Some(to.fill(&params).ok()?.to_subscribe_filter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_topic, TopicRewrite};
+
+    #[test]
+    fn both_directions() {
+        let rule =
+            TopicRewrite::new("{role}/{id}/{plug}", "site/{id}/{role}/{plug}/value").unwrap();
+        assert_eq!(
+            rule.to_legacy("lights/hall/brightness").unwrap(),
+            "site/hall/lights/brightness/value"
+        );
+        assert_eq!(
+            rule.to_tether("site/hall/lights/brightness/value").unwrap(),
+            "lights/hall/brightness"
+        );
+
+        // Subscription filters keep their wildcards
+        assert_eq!(
+            rule.to_legacy("+/+/brightness").unwrap(),
+            "site/+/+/brightness/value"
+        );
+
+        // Topics which do not fit the rule are not rewritten
+        assert_eq!(rule.to_tether("site/hall/lights/brightness"), None);
+        assert_eq!(rule.to_tether("other/hall/lights/brightness/value"), None);
+        assert_eq!(rule.to_legacy("lights/brightness"), None);
+        assert_eq!(rule.to_legacy("+/#"), None);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let e = TopicRewrite::new("{role}/{id}/status", "status/{role}-{id}").unwrap_err();
+        assert!(e.to_string().contains("whole level"));
+        let e = TopicRewrite::new("{role}/{id}/{plug}", "{id}/{plug}").unwrap_err();
+        assert!(e.to_string().contains("same placeholders"));
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules = [
+            TopicRewrite::new("{role}/{id}/status", "status/{id}/{role}").unwrap(),
+            TopicRewrite::new("{role}/{id}/{plug}", "legacy/{role}/{id}/{plug}").unwrap(),
+        ];
+        assert_eq!(
+            rewrite_topic(&rules, "door/front/status".into(), TopicRewrite::to_legacy),
+            "status/front/door"
+        );
+        assert_eq!(
+            rewrite_topic(&rules, "door/front/opened".into(), TopicRewrite::to_legacy),
+            "legacy/door/front/opened"
+        );
+        assert_eq!(
+            rewrite_topic(&rules, "unrelated".into(), TopicRewrite::to_tether),
+            "unrelated"
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+/// The placeholders that Output Plugs fill in automatically (from the Agent and Plug)
+/// unless a value is explicitly provided.
+pub const ROLE_PLACEHOLDER: &str = "role";
+pub const ID_PLACEHOLDER: &str = "id";
+pub const PLUG_PLACEHOLDER: &str = "plug";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum TemplateSegment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A topic string with named placeholders, e.g. `"{role}/{id}/sensors/{index}"`.
+///
+/// The template is validated when constructed, so that any problems are found
+/// before attempting to publish. Placeholders can be filled in stages: an
+/// Output Plug fills `{role}`, `{id}` and `{plug}` when it is built, leaving any
+/// others to be provided for each publish.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopicTemplate {
+    segments: Vec<TemplateSegment>,
+}
+
+impl TopicTemplate {
+    pub fn new(template: &str) -> anyhow::Result<TopicTemplate> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(anyhow!(
+                            "Unclosed placeholder in topic template \"{}\"",
+                            template
+                        ));
+                    }
+                    if name.is_empty()
+                        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        return Err(anyhow!(
+                            "Invalid placeholder name \"{}\" in topic template \"{}\"",
+                            name,
+                            template
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(TemplateSegment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(TemplateSegment::Placeholder(name));
+                }
+                '}' => {
+                    return Err(anyhow!(
+                        "Unexpected closing brace in topic template \"{}\"",
+                        template
+                    ));
+                }
+                '+' | '#' => {
+                    return Err(anyhow!(
+                        "Wildcards are not allowed in topic template \"{}\"",
+                        template
+                    ));
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(TemplateSegment::Literal(literal));
+        }
+
+        if segments.is_empty() {
+            return Err(anyhow!("Topic template cannot be empty"));
+        }
+
+        Ok(TopicTemplate { segments })
+    }
+
+    /// Names of the placeholders which have not (yet) been filled, in order of appearance
+    pub fn placeholders(&self) -> Vec<&str> {
+        self.segments
+            .iter()
+            .filter_map(|s| match s {
+                TemplateSegment::Placeholder(name) => Some(name.as_str()),
+                TemplateSegment::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    /// True if there are no placeholders left to fill
+    pub fn is_complete(&self) -> bool {
+        self.placeholders().is_empty()
+    }
+
+    /// Return a new template with any placeholders found in `params` filled in;
+    /// placeholders not mentioned are left in place. Parameters which do not match
+    /// any placeholder are ignored.
+    pub fn fill(&self, params: &[(&str, &str)]) -> anyhow::Result<TopicTemplate> {
+        let mut segments: Vec<TemplateSegment> = Vec::new();
+        for segment in &self.segments {
+            let filled = match segment {
+                TemplateSegment::Placeholder(name) => {
+                    match params.iter().find(|(key, _)| key == name) {
+                        Some((_, value)) => {
+                            validate_value(name, value)?;
+                            TemplateSegment::Literal(String::from(*value))
+                        }
+                        None => segment.clone(),
+                    }
+                }
+                TemplateSegment::Literal(_) => segment.clone(),
+            };
+            match (segments.last_mut(), filled) {
+                (Some(TemplateSegment::Literal(previous)), TemplateSegment::Literal(s)) => {
+                    previous.push_str(&s)
+                }
+                (_, s) => segments.push(s),
+            }
+        }
+        Ok(TopicTemplate { segments })
+    }
+
+    /// The template as a subscription filter, with a `+` wildcard in place of each
+    /// placeholder that has not been filled
+    pub fn to_subscribe_filter(&self) -> String {
+        self.segments
+            .iter()
+            .map(|s| match s {
+                TemplateSegment::Literal(s) => s.as_str(),
+                TemplateSegment::Placeholder(_) => "+",
+            })
+            .collect()
+    }
+
+    /// Fill all placeholders and return the final topic string. Fails if any
+    /// placeholder is left without a value.
+    pub fn render(&self, params: &[(&str, &str)]) -> anyhow::Result<String> {
+        let filled = self.fill(params)?;
+        let missing = filled.placeholders();
+        if missing.is_empty() {
+            Ok(filled.to_string())
+        } else {
+            Err(anyhow!(
+                "No value provided for placeholder(s) {:?} in topic template \"{}\"",
+                missing,
+                self
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for TopicTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(s) => write!(f, "{}", s)?,
+                TemplateSegment::Placeholder(name) => write!(f, "{{{}}}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for TopicTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        TopicTemplate::new(value)
+    }
+}
+
+/// Parse a topic schema (see `TetherAgentOptionsBuilder::topic_schema`): a template which
+/// must include `{plug}`, and may include `{role}` and `{id}`, but no other placeholders.
+/// Each placeholder must be a whole level of the topic, so that it can be a wildcard.
+pub(crate) fn parse_topic_schema(schema: &str) -> anyhow::Result<TopicTemplate> {
+    let template = TopicTemplate::new(schema)?;
+    let placeholders = template.placeholders();
+    if let Some(other) = placeholders
+        .iter()
+        .find(|p| ![ROLE_PLACEHOLDER, ID_PLACEHOLDER, PLUG_PLACEHOLDER].contains(p))
+    {
+        return Err(anyhow!(
+            "Unknown placeholder \"{}\" in topic schema \"{}\"; only {{role}}, {{id}} and {{plug}} are allowed",
+            other,
+            schema
+        ));
+    }
+    if !placeholders.contains(&PLUG_PLACEHOLDER) {
+        return Err(anyhow!(
+            "Topic schema \"{}\" must include a {{plug}} placeholder",
+            schema
+        ));
+    }
+    if !has_whole_level_placeholders(schema) {
+        return Err(anyhow!(
+            "Each placeholder in topic schema \"{}\" must be a whole level of the topic",
+            schema
+        ));
+    }
+    Ok(template)
+}
+
+/// True if every placeholder in the template is a whole level of the topic, e.g.
+/// `{role}/x/{plug}` but not `{role}-x/{plug}`
+pub(crate) fn has_whole_level_placeholders(template: &str) -> bool {
+    template.split('/').all(|level| {
+        !level.contains('{')
+            || (level.starts_with('{') && level.ends_with('}') && level.matches('{').count() == 1)
+    })
+}
+
+fn validate_value(name: &str, value: &str) -> anyhow::Result<()> {
+    if value.is_empty() {
+        Err(anyhow!("Empty value provided for placeholder \"{}\"", name))
+    } else if value.contains(['+', '#', '{', '}']) {
+        Err(anyhow!(
+            "Value \"{}\" for placeholder \"{}\" contains reserved characters",
+            value,
+            name
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_topic_schema, TopicTemplate};
+
+    #[test]
+    fn validate_on_construction() {
+        assert!(TopicTemplate::new("{role}/{id}/sensors/{index}").is_ok());
+        assert!(TopicTemplate::new("plain/topic/string").is_ok());
+        assert!(TopicTemplate::new("").is_err());
+        assert!(TopicTemplate::new("{role}/{id/sensors").is_err());
+        assert!(TopicTemplate::new("role}/sensors").is_err());
+        assert!(TopicTemplate::new("{}/sensors").is_err());
+        assert!(TopicTemplate::new("{bad name}/sensors").is_err());
+        assert!(TopicTemplate::new("{role}/+/sensors").is_err());
+        assert!(TopicTemplate::new("#").is_err());
+    }
+
+    #[test]
+    fn topic_schemas() {
+        assert!(parse_topic_schema("{role}/{id}/{plug}").is_ok());
+        assert!(parse_topic_schema("myApp/{role}/{id}/{plug}").is_ok());
+        assert!(parse_topic_schema("things/{plug}").is_ok());
+        assert!(parse_topic_schema("{role}/{id}").is_err());
+        assert!(parse_topic_schema("{role}/{id}/{plug}/{index}").is_err());
+        assert!(parse_topic_schema("{role}/{id}/plug-{plug}").is_err());
+        assert!(parse_topic_schema("{role}{id}/{plug}").is_err());
+
+        let schema = parse_topic_schema("myApp/{role}/{id}/{plug}").unwrap();
+        assert_eq!(schema.to_subscribe_filter(), "myApp/+/+/+");
+        assert_eq!(
+            schema
+                .fill(&[("plug", "values")])
+                .unwrap()
+                .to_subscribe_filter(),
+            "myApp/+/+/values"
+        );
+    }
+
+    #[test]
+    fn render_all_provided() {
+        let template = TopicTemplate::new("{role}/{id}/sensors/{index}").unwrap();
+        assert_eq!(template.placeholders(), vec!["role", "id", "index"]);
+        let topic = template
+            .render(&[("role", "brain"), ("id", "left"), ("index", "3")])
+            .unwrap();
+        assert_eq!(topic, "brain/left/sensors/3");
+    }
+
+    #[test]
+    fn render_with_defaults_filled_first() {
+        let template = TopicTemplate::new("{role}/{id}/{plug}/{index}").unwrap();
+        let defaulted = template
+            .fill(&[("role", "tester"), ("id", "any"), ("plug", "readings")])
+            .unwrap();
+        assert_eq!(defaulted.to_string(), "tester/any/readings/{index}");
+        assert_eq!(defaulted.placeholders(), vec!["index"]);
+        assert!(!defaulted.is_complete());
+
+        assert_eq!(
+            defaulted.render(&[("index", "0")]).unwrap(),
+            "tester/any/readings/0"
+        );
+        assert_eq!(
+            defaulted.render(&[("index", "1")]).unwrap(),
+            "tester/any/readings/1"
+        );
+    }
+
+    #[test]
+    fn render_fails_if_missing_or_invalid() {
+        let template = TopicTemplate::new("{role}/{id}/sensors/{index}").unwrap();
+        assert!(template
+            .render(&[("role", "brain"), ("id", "left")])
+            .is_err());
+        assert!(template
+            .render(&[("role", "brain"), ("id", "+"), ("index", "0")])
+            .is_err());
+        assert!(template
+            .render(&[("role", "brain"), ("id", ""), ("index", "0")])
+            .is_err());
+    }
+}
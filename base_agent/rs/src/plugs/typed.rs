@@ -0,0 +1,183 @@
+use std::{marker::PhantomData, sync::mpsc};
+
+use anyhow::anyhow;
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+
+use crate::{agent::routing::RouteOutcome, decode, TetherAgent};
+
+use super::{InputPlugDefinition, PlugDefinition, PlugDefinitionCommon};
+
+/// An Input Plug for messages which should all decode into the same type `T`.
+///
+/// Use `into_channel` to have matching messages decoded as they arrive, so that the
+/// consumer simply receives values of type `T`, with no topic matching or decoding to do.
+pub struct TypedInputPlug<T> {
+    definition: InputPlugDefinition,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned + Send + 'static> TypedInputPlug<T> {
+    /// Wrap a Plug Definition (which must be an Input Plug) that was already built
+    pub fn new(plug_definition: PlugDefinition) -> anyhow::Result<TypedInputPlug<T>> {
+        match plug_definition {
+            PlugDefinition::InputPlug(definition) => Ok(TypedInputPlug {
+                definition,
+                _type: PhantomData,
+            }),
+            PlugDefinition::OutputPlug(_) => {
+                Err(anyhow!("A Typed Input Plug needs an Input Plug Definition"))
+            }
+        }
+    }
+
+    pub fn definition(&self) -> &InputPlugDefinition {
+        &self.definition
+    }
+
+    /// Decrypt (if this Plug has an encryption key) and decode a payload
+    pub fn decode(&self, payload: &[u8]) -> anyhow::Result<T> {
+        let payload = self.definition.decrypt(payload)?;
+        Ok(decode(&payload)?)
+    }
+
+    /// Returns a channel on which every message matching this Plug is delivered, already
+    /// decoded, from the Agent's connection thread. Messages which fail to decode are
+    /// skipped with a warning. Messages delivered this way are no longer returned by
+    /// `TetherAgent::check_messages`; dropping the Receiver stops the routing again.
+    pub fn into_channel(&self, tether_agent: &TetherAgent) -> mpsc::Receiver<T> {
+        let (tx, rx) = mpsc::channel();
+        let plug = TypedInputPlug::<T> {
+            definition: self.definition.matcher(),
+            _type: PhantomData,
+        };
+        let log_target = String::from(tether_agent.log_target());
+        tether_agent.add_route(Box::new(move |topic, payload| {
+            if !plug.definition.matches(topic) {
+                return RouteOutcome::NoMatch;
+            }
+            match plug.decode(payload) {
+                Ok(value) => match tx.send(value) {
+                    Ok(()) => RouteOutcome::Delivered,
+                    Err(_) => {
+                        debug!(
+                            target: &log_target,
+                            "Channel for Plug \"{}\" closed; stop routing",
+                            plug.definition.name()
+                        );
+                        RouteOutcome::Closed
+                    }
+                },
+                Err(e) => {
+                    warn!(
+                        target: &log_target,
+                        "Skipping message on \"{}\" for Plug \"{}\", which could not be decoded: {}",
+                        topic.full_topic_string(),
+                        plug.definition.name(),
+                        e
+                    );
+                    RouteOutcome::Delivered
+                }
+            }
+        }));
+        rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+    use super::TypedInputPlug;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading {
+        index: u32,
+        value: f32,
+    }
+
+    #[test]
+    fn typed_channel_end_to_end() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let input = TypedInputPlug::<Reading>::new(
+            PlugOptionsBuilder::create_input("readings")
+                .id(Some(&id))
+                .build(&mut tether_agent)
+                .unwrap(),
+        )
+        .unwrap();
+        let output = PlugOptionsBuilder::create_output("readings")
+            .id(Some(&id))
+            .build(&mut tether_agent)
+            .unwrap();
+        let other_output = PlugOptionsBuilder::create_output("other")
+            .id(Some(&id))
+            .build(&mut tether_agent)
+            .unwrap();
+        let _other_input = PlugOptionsBuilder::create_input("other")
+            .id(Some(&id))
+            .build(&mut tether_agent)
+            .unwrap();
+
+        let rx = input.into_channel(&tether_agent);
+
+        tether_agent
+            .encode_and_publish(
+                &output,
+                Reading {
+                    index: 0,
+                    value: 0.5,
+                },
+            )
+            .unwrap();
+        // Not a Reading, so skipped (with a warning)
+        tether_agent.encode_and_publish(&output, "garbage").unwrap();
+        tether_agent
+            .encode_and_publish(
+                &output,
+                Reading {
+                    index: 1,
+                    value: 1.5,
+                },
+            )
+            .unwrap();
+        // Not for this Plug at all
+        tether_agent.encode_and_publish(&other_output, 42).unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            Reading {
+                index: 0,
+                value: 0.5
+            }
+        );
+        assert_eq!(
+            rx.recv_timeout(timeout).unwrap(),
+            Reading {
+                index: 1,
+                value: 1.5
+            }
+        );
+
+        // Only the unrelated message is left for check_messages
+        let start = std::time::SystemTime::now();
+        let (topic, _) = loop {
+            if let Some(message) = tether_agent.check_messages() {
+                break message;
+            }
+            assert!(start.elapsed().unwrap() < timeout);
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(topic.full_topic_string().ends_with("/other"));
+        assert!(rx.try_recv().is_err());
+    }
+}
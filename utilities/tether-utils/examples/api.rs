@@ -19,8 +19,7 @@ fn demo_receive() {
     let options = ReceiveOptions::default();
 
     receive(&options, &mut tether_agent, |_plug_name, topic, decoded| {
-        let contents = decoded.unwrap_or("(empty/invalid message)".into());
-        println!("RECEIVE: \"{}\" :: {}", topic, contents);
+        println!("RECEIVE: \"{}\" :: {}", topic, decoded);
     })
 }
 
@@ -57,6 +56,8 @@ fn demo_topics() {
         topic: "#".into(),
         sampler_interval: 1000,
         graph_enable: false,
+        sys_enable: false,
+        stale_timeout: 10,
     };
 
     let mut insights = Insights::new(&options, &mut tether_agent);
@@ -74,6 +75,7 @@ fn demo_playback() {
     let options = PlaybackOptions {
         file_path: "./demo.json".into(),
         override_topic: None,
+        remap: Vec::new(),
         loop_count: 1, // ignored anyway, in this case
         loop_infinite: true,
         ignore_ctrl_c: true, // this is important for programmatic use
@@ -9,7 +9,11 @@ use log::*;
 use clap::{Parser, Subcommand};
 
 use tether_agent::TetherAgentOptionsBuilder;
-use tether_utils::*;
+use tether_utils::{
+    tether_config::{default_search_dirs, AgentArgs, TetherConfig},
+    tether_shutdown::{disconnect_cleanly, ShutdownSignal},
+    *,
+};
 
 use std::{
     io::{stdout, Write},
@@ -22,34 +26,17 @@ pub struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(long = "protocol", default_value_t=String::from("tcp"))]
-    pub tether_protocol: String,
-
-    #[arg(long = "host", default_value_t=String::from("localhost"))]
-    pub tether_host: String,
-
-    #[arg(long = "port", default_value_t = 1883)]
-    pub tether_port: u16,
-
-    #[arg(long = "path", default_value_t=String::from("/"))]
-    pub tether_base_path: String,
+    /// Load settings from this config file (.toml or .json) instead of searching for
+    /// tether.toml or tether.json in the current directory and then ~/.config/tether
+    #[arg(long = "config")]
+    pub config_path: Option<String>,
 
-    #[arg(long = "username", default_value_t=String::from("tether"))]
-    pub tether_username: String,
+    #[command(flatten)]
+    pub agent: AgentArgs,
 
-    #[arg(long = "password", default_value_t=String::from("sp_ceB0ss!"))]
-    pub tether_password: String,
-
-    /// Role to use for any auto-generated topics on publish
-    #[arg(long = "role", default_value_t=String::from("utils"))]
-    pub tether_role: String,
-
-    /// ID/Group to use for any auto-generated topics on publish
-    #[arg(long = "id", default_value_t=String::from("any"))]
-    pub tether_id: String,
-
-    #[arg(long = "loglevel",default_value_t=String::from("info"))]
-    pub log_level: String,
+    /// [default: info]
+    #[arg(long = "loglevel")]
+    pub log_level: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -59,57 +46,72 @@ enum Commands {
     Topics(tether_topics::TopicOptions),
     Playback(tether_playback::PlaybackOptions),
     Record(tether_record::RecordOptions),
+    Repl(tether_repl::ReplOptions),
+    /// Check exactly-once (QoS 2) delivery through the broker, end to end
+    QosTest(tether_qos_test::QosTestOptions),
+    /// Monitor the broker's own stats, published below $SYS
+    Sysmon(tether_sysmon::SysmonOptions),
 }
 
 fn main() {
     let cli = Cli::parse();
     std::io::stdout().flush().unwrap();
 
-    let mut env_builder = Builder::from_env(Env::default().default_filter_or(&cli.log_level));
+    let config = TetherConfig::load_or_default(cli.config_path.as_deref(), &default_search_dirs());
+
+    let log_level = cli
+        .log_level
+        .clone()
+        .or(config.as_ref().ok().and_then(|c| c.loglevel.clone()))
+        .unwrap_or("info".into());
+
+    let mut env_builder = Builder::from_env(Env::default().default_filter_or(&log_level));
     env_builder.filter_module("paho_mqtt", LevelFilter::Warn);
     env_builder.init();
 
     debug!("Debugging is enabled; could be verbose");
 
-    let mut tether_agent = TetherAgentOptionsBuilder::new(&cli.tether_role)
-        .id(Some(cli.tether_id).as_deref())
-        .protocol(Some(cli.tether_protocol).as_deref())
-        .host(Some(cli.tether_host.clone()).as_deref())
-        .port(Some(cli.tether_port))
-        .base_path(Some(cli.tether_base_path).as_deref())
-        .username(Some(cli.tether_username).as_deref())
-        .password(Some(cli.tether_password).as_deref())
+    let config = config.unwrap_or_else(|e| {
+        error!("Failed to load config file: {}", e);
+        panic!("Failed to load config file")
+    });
+
+    // Anything specified on the command line takes precedence over the config file
+    let agent_config = config.agent_config_with(&cli.agent);
+
+    let mut tether_agent = TetherAgentOptionsBuilder::from_config(&agent_config)
         .build()
         .unwrap_or_else(|_| {
             error!("Failed to initialise and/or connect the Tether Agent");
             warn!(
                 "Check your Tether settings and ensure that you have a correctly-configured MQTT broker running at {}:{}",
-                &cli.tether_host, cli.tether_port
+                agent_config.host.as_deref().unwrap_or("localhost"),
+                agent_config.port.unwrap_or(1883)
             );
             panic!("Failed to init/connect Tether Agent")
         });
 
-    match &cli.command {
+    let succeeded = match &cli.command {
         Commands::Receive(options) => {
-            tether_receive::receive(options, &mut tether_agent, |_plug_name, topic, decoded| {
-                let contents = decoded.unwrap_or("(empty/invalid message)".into());
-                info!("Received on topic \"{}\" :: \n{}\n", topic, contents);
-            })
+            let mut options = options.clone();
+            config.apply_to_receive(&mut options);
+            tether_receive::receive(&options, &mut tether_agent, |_plug_name, topic, decoded| {
+                info!("Received on topic \"{}\" :: \n{}\n", topic, decoded);
+            });
+            true
         }
         Commands::Send(options) => {
+            // Disconnecting cleanly (below) waits until the message has been sent
             tether_send::send(options, &mut tether_agent)
-                .unwrap_or_else(|e| error!("Failed to send: {}", e));
-            // This silliness (below) is only needed because the command might
-            // finish before the message is even sent!
-            // Less of an issue with long-running applications, and should
-            // be properly solved with an async version.
-            std::thread::sleep(std::time::Duration::from_millis(1000));
+                .map_err(|e| error!("Failed to send: {}", e))
+                .is_ok()
         }
         Commands::Topics(options) => {
             let mut insights = tether_topics::insights::Insights::new(options, &mut tether_agent);
             let mut last_update = SystemTime::now();
+            let shutdown = ShutdownSignal::from_os();
 
-            loop {
+            while !shutdown.is_requested() {
                 if !insights.sample() {
                     std::thread::sleep(Duration::from_millis(1));
                 }
@@ -119,20 +121,48 @@ fn main() {
                 }
                 if let Ok(elapsed) = last_update.elapsed() {
                     if elapsed > Duration::from_secs(1) {
-                        print_insights_summary(&insights, false, options.graph_enable);
+                        let stale_did_update = insights.check_stale();
+                        print_insights_summary(&insights, stale_did_update, options.graph_enable);
                         last_update = SystemTime::now();
                     }
                 }
             }
+            true
         }
         Commands::Playback(options) => {
             let player = tether_playback::TetherPlaybackUtil::new(options.clone());
             player.start(&tether_agent);
+            true
         }
         Commands::Record(options) => {
             let recorder = tether_record::TetherRecordUtil::new(options.clone());
             recorder.start_recording(&mut tether_agent);
+            true
+        }
+        Commands::Repl(options) => {
+            let mut repl = tether_repl::TetherRepl::new(options.clone());
+            repl.start(&mut tether_agent);
+            true
         }
+        Commands::QosTest(options) => match tether_qos_test::qos_test(options, &mut tether_agent) {
+            Ok(report) => report.is_exactly_once(),
+            Err(e) => {
+                error!("QoS test failed: {}", e);
+                false
+            }
+        },
+        Commands::Sysmon(options) => match tether_sysmon::sysmon(options, &mut tether_agent) {
+            Ok(summary) => summary.is_some(),
+            Err(e) => {
+                error!("Broker stats monitoring failed: {}", e);
+                false
+            }
+        },
+    };
+
+    disconnect_cleanly(&mut tether_agent);
+    if !succeeded {
+        std::process::exit(1);
     }
 }
 
@@ -1,5 +1,10 @@
+pub mod tether_config;
 pub mod tether_playback;
+pub mod tether_qos_test;
 pub mod tether_receive;
 pub mod tether_record;
+pub mod tether_repl;
 pub mod tether_send;
+pub mod tether_shutdown;
+pub mod tether_sysmon;
 pub mod tether_topics;
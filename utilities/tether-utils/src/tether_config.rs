@@ -0,0 +1,308 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use clap::Args;
+use log::{debug, info};
+use serde::Deserialize;
+use tether_agent::AgentConfig;
+
+use crate::tether_receive::ReceiveOptions;
+
+/// File names searched for (in this order) in each config directory
+pub const CONFIG_FILE_NAMES: [&str; 2] = ["tether.toml", "tether.json"];
+
+/// The Role of the Agent used by every subcommand, unless given in the config file or on
+/// the command line
+pub const DEFAULT_ROLE: &str = "utils";
+
+/// Settings which can be loaded from a `tether.toml` or `tether.json` file, to avoid
+/// repeating the same flags on every command. Any flags given on the command line
+/// take precedence over values from the file.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct TetherConfig {
+    pub loglevel: Option<String>,
+    /// The Agent used by every subcommand, under `[agent]`; see `AgentConfig`
+    pub agent: Option<AgentConfig>,
+    pub receive: ReceiveConfig,
+}
+
+/// The Agent options which can be given on the command line, for every subcommand; any
+/// given here take precedence over the `[agent]` section of the config file
+#[derive(Args, Default, Clone, Debug)]
+pub struct AgentArgs {
+    /// Protocol to connect to the MQTT broker with [default: mqtt]
+    #[arg(long = "protocol")]
+    pub tether_protocol: Option<String>,
+
+    /// Host of the MQTT broker [default: localhost]
+    #[arg(long = "host")]
+    pub tether_host: Option<String>,
+
+    /// Port of the MQTT broker [default: 1883]
+    #[arg(long = "port")]
+    pub tether_port: Option<u16>,
+
+    /// Base path of the MQTT broker, for websocket connections [default: /]
+    #[arg(long = "path")]
+    pub tether_base_path: Option<String>,
+
+    #[arg(long = "username")]
+    pub tether_username: Option<String>,
+
+    #[arg(long = "password")]
+    pub tether_password: Option<String>,
+
+    /// Role to use for any auto-generated topics on publish [default: utils]
+    #[arg(long = "role")]
+    pub tether_role: Option<String>,
+
+    /// ID/Group to use for any auto-generated topics on publish [default: any]
+    #[arg(long = "id")]
+    pub tether_id: Option<String>,
+}
+
+/// Defaults for `tether receive`
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ReceiveConfig {
+    pub role: Option<String>,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub ignore_empty: Option<bool>,
+    pub preview_length: Option<usize>,
+    /// JSON key paths to mask in logged payloads, e.g. `["token", "auth.password"]`
+    pub redact: Option<Vec<String>>,
+}
+
+impl TetherConfig {
+    /// Load from a file, using the extension to decide between TOML and JSON
+    pub fn load(path: &Path) -> anyhow::Result<TetherConfig> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read config file {}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            _ => Err(anyhow!(
+                "Config file {} should have a .toml or .json extension",
+                path.display()
+            )),
+        }
+    }
+
+    /// If an explicit path is given, the file must exist. Otherwise, the first
+    /// config file found in the search paths is used, or defaults (i.e. nothing
+    /// overridden) if there is none.
+    pub fn load_or_default(
+        explicit_path: Option<&str>,
+        search_dirs: &[PathBuf],
+    ) -> anyhow::Result<TetherConfig> {
+        if let Some(p) = explicit_path {
+            return TetherConfig::load(Path::new(p));
+        }
+        match find_config_file(search_dirs) {
+            Some(p) => {
+                info!("Using config file {}", p.display());
+                TetherConfig::load(&p)
+            }
+            None => {
+                debug!("No config file found; using defaults");
+                Ok(TetherConfig::default())
+            }
+        }
+    }
+
+    /// The Agent options from the file, or the defaults (with the `DEFAULT_ROLE`) if there
+    /// are none
+    pub fn agent_config(&self) -> AgentConfig {
+        self.agent
+            .clone()
+            .unwrap_or_else(|| AgentConfig::new(DEFAULT_ROLE))
+    }
+
+    /// The Agent options from the file (see `agent_config`), with any given on the command
+    /// line taking precedence
+    pub fn agent_config_with(&self, args: &AgentArgs) -> AgentConfig {
+        let AgentArgs {
+            tether_protocol,
+            tether_host,
+            tether_port,
+            tether_base_path,
+            tether_username,
+            tether_password,
+            tether_role,
+            tether_id,
+        } = args;
+        let mut agent_config = self.agent_config();
+        agent_config.role = tether_role.clone().unwrap_or(agent_config.role);
+        agent_config.id = tether_id.clone().or(agent_config.id);
+        agent_config.protocol = tether_protocol.clone().or(agent_config.protocol);
+        agent_config.host = tether_host.clone().or(agent_config.host);
+        agent_config.port = tether_port.or(agent_config.port);
+        agent_config.base_path = tether_base_path.clone().or(agent_config.base_path);
+        agent_config.username = tether_username.clone().or(agent_config.username);
+        agent_config.password = tether_password.clone().or(agent_config.password);
+        agent_config
+    }
+
+    /// Fill in any receive options not already specified (e.g. on the command line)
+    pub fn apply_to_receive(&self, options: &mut ReceiveOptions) {
+        let ReceiveConfig {
+            role,
+            id,
+            name,
+            topic,
+            ignore_empty,
+            preview_length,
+            redact,
+        } = &self.receive;
+        options.subscribe_role = options.subscribe_role.take().or(role.clone());
+        options.subscribe_id = options.subscribe_id.take().or(id.clone());
+        options.subscribe_plug_name = options.subscribe_plug_name.take().or(name.clone());
+        options.subscribe_topic = options.subscribe_topic.take().or(topic.clone());
+        options.ignore_empty_payloads = options.ignore_empty_payloads.or(*ignore_empty);
+        options.preview_length = options.preview_length.or(*preview_length);
+        options.redact = options
+            .redact
+            .take()
+            .or(redact.as_ref().map(|paths| paths.join(",")));
+    }
+}
+
+/// The current directory, then the standard per-user config directory
+/// (`$XDG_CONFIG_HOME/tether` or `~/.config/tether`; `%APPDATA%\tether` on Windows)
+pub fn default_search_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from(".")];
+    if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        dirs.push(PathBuf::from(config_home).join("tether"));
+    } else if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".config").join("tether"));
+    } else if let Some(app_data) = std::env::var_os("APPDATA") {
+        dirs.push(PathBuf::from(app_data).join("tether"));
+    }
+    dirs
+}
+
+pub fn find_config_file(search_dirs: &[PathBuf]) -> Option<PathBuf> {
+    search_dirs
+        .iter()
+        .flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::tether_receive::ReceiveOptions;
+
+    use super::{AgentArgs, TetherConfig, DEFAULT_ROLE};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tether-config-test-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_files() {
+        let empty = temp_dir("empty");
+        let config = TetherConfig::load_or_default(None, std::slice::from_ref(&empty)).unwrap();
+        assert_eq!(config, TetherConfig::default());
+
+        // ...but an explicitly-requested file must exist
+        let missing = empty.join("nope.toml");
+        assert!(TetherConfig::load_or_default(missing.to_str(), &[]).is_err());
+    }
+
+    #[test]
+    fn search_order_and_formats() {
+        let first = temp_dir("first");
+        let second = temp_dir("second");
+        fs::write(
+            first.join("tether.json"),
+            r#"{ "agent": { "role": "fromJson", "host": "from-json", "port": 1884 } }"#,
+        )
+        .unwrap();
+        fs::write(
+            second.join("tether.toml"),
+            "[agent]\nrole = \"fromToml\"\nhost = \"from-toml\"\n",
+        )
+        .unwrap();
+
+        let config = TetherConfig::load_or_default(None, &[first, second.clone()]).unwrap();
+        assert_eq!(config.agent_config().host.as_deref(), Some("from-json"));
+        assert_eq!(config.agent_config().port, Some(1884));
+
+        let config = TetherConfig::load_or_default(None, &[second]).unwrap();
+        assert_eq!(config.agent_config().role, "fromToml");
+        assert_eq!(config.agent_config().host.as_deref(), Some("from-toml"));
+        assert_eq!(config.agent_config().port, None);
+
+        assert_eq!(TetherConfig::default().agent_config().role, DEFAULT_ROLE);
+    }
+
+    #[test]
+    fn cli_overrides_file() {
+        let config: TetherConfig = toml::from_str(
+            r#"
+            [agent]
+            role = "fileRole"
+            host = "10.0.0.1"
+
+            [receive]
+            role = "fileRole"
+            topic = "file/topic/here"
+            ignoreEmpty = true
+            previewLength = 50
+            redact = ["token", "auth.password"]
+            "#,
+        )
+        .unwrap();
+
+        let mut options = ReceiveOptions {
+            subscribe_role: Some("cliRole".into()),
+            ..ReceiveOptions::default()
+        };
+        config.apply_to_receive(&mut options);
+
+        assert_eq!(options.subscribe_role.as_deref(), Some("cliRole"));
+        assert_eq!(options.subscribe_topic.as_deref(), Some("file/topic/here"));
+        assert_eq!(options.subscribe_id, None);
+        assert!(options.ignore_empty_payloads());
+        assert_eq!(options.preview_length(), 50);
+        assert_eq!(options.redact.as_deref(), Some("token,auth.password"));
+
+        // A flag turned on in the file can still be turned off on the command line
+        let mut options = ReceiveOptions {
+            ignore_empty_payloads: Some(false),
+            ..ReceiveOptions::default()
+        };
+        config.apply_to_receive(&mut options);
+        assert!(!options.ignore_empty_payloads());
+
+        let agent_config = config.agent_config_with(&AgentArgs {
+            tether_role: Some("cliRole".into()),
+            tether_port: Some(1884),
+            ..AgentArgs::default()
+        });
+        assert_eq!(agent_config.role, "cliRole");
+        assert_eq!(agent_config.host.as_deref(), Some("10.0.0.1"));
+        assert_eq!(agent_config.port, Some(1884));
+        assert_eq!(agent_config.id, None);
+
+        let agent_config = config.agent_config_with(&AgentArgs::default());
+        assert_eq!(agent_config.role, "fileRole");
+    }
+
+    #[test]
+    fn unknown_fields_rejected() {
+        assert!(toml::from_str::<TetherConfig>("hots = \"typo\"").is_err());
+        assert!(toml::from_str::<TetherConfig>("[agent]\nrole = \"x\"\nhots = \"typo\"").is_err());
+    }
+}
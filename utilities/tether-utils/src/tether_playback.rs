@@ -4,12 +4,15 @@ use std::{
     sync::mpsc::{self, Receiver},
 };
 
+use anyhow::anyhow;
 use clap::Args;
 use log::{debug, info, warn};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tether_agent::TetherAgent;
+use tether_agent::{three_part_topic::ThreePartTopic, TetherAgent};
+
+use crate::tether_shutdown::ShutdownSignal;
 
 #[derive(Args, Clone)]
 pub struct PlaybackOptions {
@@ -25,6 +28,11 @@ pub struct PlaybackOptions {
     #[arg(long = "topic.override")]
     pub override_topic: Option<String>,
 
+    /// Comma-separated rules to rewrite the role, or role and id, of recorded topics before
+    /// publishing, e.g. "prod=>test" or "brain/prod=>brain/test"; see `TopicRemap`
+    #[arg(long = "remap", value_delimiter = ',', value_parser = TopicRemap::parse)]
+    pub remap: Vec<TopicRemap>,
+
     /// Speed up or slow down playback (e.g. 2.0 = double speed)
     #[arg(long = "playback.speed", default_value_t = 1.0)]
     pub playback_speed: f32,
@@ -48,6 +56,7 @@ impl Default for PlaybackOptions {
         PlaybackOptions {
             file_path: "./demo.json".into(),
             override_topic: None,
+            remap: Vec::new(),
             loop_count: 1,
             loop_infinite: false,
             ignore_ctrl_c: false,
@@ -72,6 +81,84 @@ pub struct SimulationRow {
     pub delta_time: u64,
 }
 
+/// A rule to replay recorded Tether topics under a different role (and optionally id),
+/// e.g. to replay messages captured from `prod` Agents as `test` ones without colliding
+/// with live data. Topics which do not have three parts are never remapped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRemap {
+    from: (String, Option<String>),
+    to: (String, Option<String>),
+}
+
+impl TopicRemap {
+    /// The remapped topic, or None if the rule does not apply to this topic
+    pub fn apply(&self, topic: &str) -> Option<String> {
+        let mut three_part = ThreePartTopic::try_from(topic).ok()?;
+        let (from_role, from_id) = &self.from;
+        if three_part.role() != from_role
+            || from_id.as_ref().is_some_and(|id| three_part.id() != id)
+        {
+            return None;
+        }
+        let (to_role, to_id) = &self.to;
+        three_part.set_role(to_role);
+        if let Some(id) = to_id {
+            three_part.set_id(id);
+        }
+        Some(three_part.topic().into())
+    }
+
+    /// Parse a single rule; see `TryFrom<&str>`
+    pub fn parse(rule: &str) -> anyhow::Result<TopicRemap> {
+        TopicRemap::try_from(rule)
+    }
+
+    /// Parse comma-separated rules, as given to `--remap`
+    pub fn parse_list(rules: &str) -> anyhow::Result<Vec<TopicRemap>> {
+        rules.split(',').map(TopicRemap::parse).collect()
+    }
+}
+
+impl TryFrom<&str> for TopicRemap {
+    type Error = anyhow::Error;
+
+    /// Parse a rule such as `prod=>test` (role only) or `brain/prod=>brain/test` (role and id)
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (from, to) = value
+            .split_once("=>")
+            .ok_or_else(|| anyhow!("Remap rule \"{}\" should look like \"from=>to\"", value))?;
+        let parse_prefix = |prefix: &str| -> anyhow::Result<(String, Option<String>)> {
+            let parts: Vec<&str> = prefix.trim().split('/').collect();
+            if parts.len() > 2 || parts.iter().any(|p| p.is_empty() || p.contains(['+', '#'])) {
+                return Err(anyhow!(
+                    "Remap rule \"{}\" should only give a role, or role/id, without wildcards",
+                    value
+                ));
+            }
+            Ok((parts[0].into(), parts.get(1).map(|id| String::from(*id))))
+        };
+        let remap = TopicRemap {
+            from: parse_prefix(from)?,
+            to: parse_prefix(to)?,
+        };
+        if remap.from.1.is_some() != remap.to.1.is_some() {
+            return Err(anyhow!(
+                "Both sides of remap rule \"{}\" should give either a role, or role/id",
+                value
+            ));
+        }
+        Ok(remap)
+    }
+}
+
+/// The topic to publish a recorded message on: the first remap rule which applies, if any
+fn remap_topic(remaps: &[TopicRemap], topic: &str) -> String {
+    remaps
+        .iter()
+        .find_map(|r| r.apply(topic))
+        .unwrap_or_else(|| String::from(topic))
+}
+
 pub struct TetherPlaybackUtil {
     stop_request_tx: mpsc::Sender<bool>,
     stop_request_rx: mpsc::Receiver<bool>,
@@ -107,18 +194,18 @@ impl TetherPlaybackUtil {
             warn!("Override topic provided; ALL topics in JSON entries will be ignored and replaced with \"{}\"",t);
         }
 
+        let remaps = &self.options.remap;
+        if !remaps.is_empty() {
+            info!("Recorded topics will be remapped using {:?}", remaps);
+        }
+
         let stop_from_key = self.stop_request_tx.clone();
 
         if !self.options.ignore_ctrl_c {
             warn!("Infinite loops requested; Press Ctr+C to stop");
-            ctrlc::set_handler(move || {
-                // should_stop_clone.store(true, Ordering::Relaxed);
-                stop_from_key
-                    .send(true)
-                    .expect("failed to send stop from key");
-                warn!("received Ctrl+C! 2");
-            })
-            .expect("Error setting Ctrl-C handler");
+            ShutdownSignal::from_os().on_request(move || {
+                stop_from_key.send(true).ok();
+            });
         } else {
             warn!(
                 "No Ctrl+C handler set; you may need to kill this process manually, PID: {}",
@@ -146,6 +233,7 @@ impl TetherPlaybackUtil {
                     tether_agent,
                     &filters,
                     &self.options.override_topic,
+                    remaps,
                     &self.stop_request_rx,
                     self.options.playback_speed,
                 ) {
@@ -167,6 +255,7 @@ fn parse_json_rows(
     tether_agent: &TetherAgent,
     filters: &Option<Vec<String>>,
     override_topic: &Option<String>,
+    remaps: &[TopicRemap],
     should_stop_rx: &Receiver<bool>,
     speed_factor: f32,
 ) -> bool {
@@ -211,7 +300,7 @@ fn parse_json_rows(
                     std::thread::sleep(std::time::Duration::from_millis(delta_time as u64));
                     let topic = match &override_topic {
                         Some(t) => String::from(t),
-                        None => String::from(topic),
+                        None => remap_topic(remaps, topic),
                     };
 
                     tether_agent
@@ -228,3 +317,123 @@ fn parse_json_rows(
     }
     early_exit
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use clap::Parser;
+    use tether_agent::{PlugOptionsBuilder, TetherAgentOptionsBuilder};
+
+    use super::{
+        PlaybackOptions, SimulationMessage, SimulationRow, TetherPlaybackUtil, TopicRemap,
+    };
+
+    #[test]
+    fn parse_remap_rules() {
+        let remaps = TopicRemap::parse_list("prod=>test, brain/live=>brain/replay").unwrap();
+        assert_eq!(remaps.len(), 2);
+        assert_eq!(
+            remaps[0].apply("prod/any/data").as_deref(),
+            Some("test/any/data")
+        );
+        assert_eq!(remaps[0].apply("production/any/data"), None);
+        assert_eq!(remaps[0].apply("prod/data"), None);
+        assert_eq!(
+            remaps[1].apply("brain/live/state").as_deref(),
+            Some("brain/replay/state")
+        );
+        assert_eq!(remaps[1].apply("brain/other/state"), None);
+
+        assert!(TopicRemap::try_from("prod").is_err());
+        assert!(TopicRemap::try_from("prod=>").is_err());
+        assert!(TopicRemap::try_from("prod/+=>test/+").is_err());
+        assert!(TopicRemap::try_from("prod/a=>test").is_err());
+        assert!(TopicRemap::try_from("a/b/c=>d/e/f").is_err());
+    }
+
+    #[test]
+    fn remap_rules_from_command_line() {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            options: PlaybackOptions,
+        }
+        let cli = Cli::try_parse_from(["playback", "--remap", "prod=>test,brain/a=>brain/b"])
+            .expect("valid rules should parse");
+        assert_eq!(
+            cli.options.remap,
+            TopicRemap::parse_list("prod=>test,brain/a=>brain/b").unwrap()
+        );
+
+        // A malformed rule is an error on the command line, not once playback starts
+        let error = Cli::try_parse_from(["playback", "--remap", "prod=>test,prod"])
+            .err()
+            .expect("a malformed rule should not parse");
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn remapped_playback() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .to_string();
+        let rows: Vec<SimulationRow> = ["prod", "other"]
+            .iter()
+            .map(|role| SimulationRow {
+                topic: format!("{}/{}/data", role, unique),
+                message: SimulationMessage {
+                    r#type: "Buffer".into(),
+                    data: vec![0x01],
+                },
+                delta_time: 10,
+            })
+            .collect();
+        let file_path = std::env::temp_dir().join(format!("playback-{}.json", unique));
+        std::fs::write(&file_path, serde_json::to_vec(&rows).unwrap()).unwrap();
+
+        let mut receiver = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let _input = PlugOptionsBuilder::create_input("data")
+            .topic(Some(&format!("+/{}/data", unique)))
+            .build(&mut receiver)
+            .unwrap();
+        let publisher = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        std::thread::sleep(Duration::from_millis(200));
+
+        TetherPlaybackUtil::new(PlaybackOptions {
+            file_path: file_path.to_string_lossy().into(),
+            remap: TopicRemap::parse_list("prod=>test").unwrap(),
+            ignore_ctrl_c: true,
+            ..PlaybackOptions::default()
+        })
+        .start(&publisher);
+        std::fs::remove_file(&file_path).ok();
+
+        let mut topics = Vec::new();
+        let start = SystemTime::now();
+        while topics.len() < 2 && start.elapsed().unwrap() < Duration::from_secs(5) {
+            match receiver.check_messages() {
+                Some((topic, _payload)) => topics.push(topic.full_topic_string()),
+                None => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        while let Some((topic, _payload)) = receiver.check_messages() {
+            topics.push(topic.full_topic_string());
+        }
+        topics.sort();
+        assert_eq!(
+            topics,
+            vec![
+                format!("other/{}/data", unique),
+                format!("test/{}/data", unique)
+            ]
+        );
+    }
+}
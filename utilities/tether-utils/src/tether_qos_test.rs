@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Args;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tether_agent::{PlugDefinition, PlugOptionsBuilder, TetherAgent};
+
+#[derive(Args, Clone)]
+pub struct QosTestOptions {
+    /// How many messages to publish
+    #[arg(long = "count", default_value_t = 100)]
+    pub count: u64,
+
+    /// Time to wait between messages, in milliseconds
+    #[arg(long = "interval", default_value_t = 10)]
+    pub interval: u64,
+
+    /// How long to wait (in milliseconds) for any outstanding messages after the
+    /// last one has been published
+    #[arg(long = "timeout", default_value_t = 5000)]
+    pub timeout: u64,
+
+    /// Plug name to publish and subscribe on (under this Agent's own role and ID)
+    #[arg(long = "plug.name", default_value_t = String::from("qosTest"))]
+    pub plug_name: String,
+}
+
+/// The payload of every test message
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QosTestMessage {
+    pub sequence: u64,
+    /// When it was published, in microseconds since the Unix epoch
+    pub sent_at: u64,
+}
+
+/// The outcome of a QoS 2 round trip: with "exactly once" delivery every message
+/// should have arrived once, with no duplicates and no gaps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QosTestReport {
+    pub sent: u64,
+    pub received: u64,
+    /// Sequence numbers received more than once
+    pub duplicates: Vec<u64>,
+    /// Sequence numbers never received
+    pub missing: Vec<u64>,
+    /// How many messages arrived with a lower sequence number than the one before
+    pub out_of_order: u64,
+    pub min_latency: Option<Duration>,
+    pub mean_latency: Option<Duration>,
+    pub max_latency: Option<Duration>,
+}
+
+impl QosTestReport {
+    /// Analyse the sequence numbers (and latencies) of the messages received, in the
+    /// order in which they arrived, given that `0..sent` were published
+    pub fn new(sent: u64, received: &[(u64, Duration)]) -> QosTestReport {
+        let mut counts: HashMap<u64, u64> = HashMap::new();
+        for (sequence, _) in received {
+            *counts.entry(*sequence).or_default() += 1;
+        }
+        let mut duplicates: Vec<u64> = counts
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(sequence, _)| *sequence)
+            .collect();
+        duplicates.sort();
+        let missing = (0..sent).filter(|s| !counts.contains_key(s)).collect();
+        let out_of_order = received.windows(2).filter(|w| w[1].0 < w[0].0).count() as u64;
+
+        let latencies = received.iter().map(|(_, latency)| *latency);
+        QosTestReport {
+            sent,
+            received: received.len() as u64,
+            duplicates,
+            missing,
+            out_of_order,
+            min_latency: latencies.clone().min(),
+            mean_latency: (!received.is_empty())
+                .then(|| latencies.clone().sum::<Duration>() / received.len() as u32),
+            max_latency: latencies.max(),
+        }
+    }
+
+    pub fn is_exactly_once(&self) -> bool {
+        self.received == self.sent && self.duplicates.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl fmt::Display for QosTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Sent: {}, received: {}", self.sent, self.received)?;
+        writeln!(f, "Duplicates: {:?}", self.duplicates)?;
+        writeln!(f, "Missing: {:?}", self.missing)?;
+        writeln!(f, "Out of order: {}", self.out_of_order)?;
+        match (self.min_latency, self.mean_latency, self.max_latency) {
+            (Some(min), Some(mean), Some(max)) => write!(
+                f,
+                "Latency min/mean/max: {:?} / {:?} / {:?}",
+                min, mean, max
+            ),
+            _ => write!(f, "Latency: n/a"),
+        }
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Publish `0..count` at QoS 2 and subscribe (at QoS 2) to the same topic, then check
+/// that every message came back exactly once, e.g. to qualify a new broker deployment
+pub fn qos_test(
+    options: &QosTestOptions,
+    tether_agent: &mut TetherAgent,
+) -> anyhow::Result<QosTestReport> {
+    info!("Tether QoS 2 Test Utility");
+
+    let role = String::from(tether_agent.role());
+    let id = String::from(tether_agent.id());
+    let input = PlugOptionsBuilder::create_input(&options.plug_name)
+        .role(Some(&role))
+        .id(Some(&id))
+        .qos(Some(2))
+        .build(tether_agent)?;
+    let output = PlugOptionsBuilder::create_output(&options.plug_name)
+        .qos(Some(2))
+        .build(tether_agent)?;
+    info!(
+        "Sending {} messages on \"{}\" ...",
+        options.count,
+        output.topic()
+    );
+
+    let mut received: Vec<(u64, Duration)> = Vec::new();
+
+    for sequence in 0..options.count {
+        let message = QosTestMessage {
+            sequence,
+            sent_at: now_micros(),
+        };
+        tether_agent.encode_and_publish(&output, &message)?;
+        take_messages(tether_agent, &input, &mut received);
+        std::thread::sleep(Duration::from_millis(options.interval));
+    }
+
+    let deadline = SystemTime::now() + Duration::from_millis(options.timeout);
+    while received.len() < options.count as usize && SystemTime::now() < deadline {
+        take_messages(tether_agent, &input, &mut received);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    // Anything still arriving now would be a duplicate
+    std::thread::sleep(Duration::from_millis(100));
+    take_messages(tether_agent, &input, &mut received);
+
+    let report = QosTestReport::new(options.count, &received);
+    if report.is_exactly_once() {
+        info!("Exactly-once delivery OK\n{}", report);
+    } else {
+        error!("Exactly-once delivery FAILED\n{}", report);
+    }
+    Ok(report)
+}
+
+/// Take all the test messages waiting, noting their sequence numbers and latency
+fn take_messages(
+    tether_agent: &TetherAgent,
+    input: &PlugDefinition,
+    received: &mut Vec<(u64, Duration)>,
+) {
+    while let Some((topic, payload)) = tether_agent.check_messages() {
+        if !input.matches(&topic) {
+            continue;
+        }
+        match rmp_serde::from_slice::<QosTestMessage>(&payload) {
+            Ok(m) => {
+                let latency = Duration::from_micros(now_micros().saturating_sub(m.sent_at));
+                debug!("Received #{} after {:?}", m.sequence, latency);
+                received.push((m.sequence, latency));
+            }
+            Err(e) => warn!("Ignoring message which is not a test message: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use tether_agent::TetherAgentOptionsBuilder;
+
+    use super::{qos_test, QosTestOptions, QosTestReport};
+
+    #[test]
+    fn report_duplicates_and_gaps() {
+        let ms = Duration::from_millis;
+        let report = QosTestReport::new(5, &[(0, ms(2)), (2, ms(4)), (1, ms(3)), (2, ms(7))]);
+        assert_eq!(report.received, 4);
+        assert_eq!(report.duplicates, vec![2]);
+        assert_eq!(report.missing, vec![3, 4]);
+        assert_eq!(report.out_of_order, 1);
+        assert_eq!(report.min_latency, Some(ms(2)));
+        assert_eq!(report.mean_latency, Some(ms(4)));
+        assert_eq!(report.max_latency, Some(ms(7)));
+        assert!(!report.is_exactly_once());
+
+        let report = QosTestReport::new(2, &[(0, ms(1)), (1, ms(1))]);
+        assert!(report.is_exactly_once());
+        assert_eq!(QosTestReport::new(0, &[]).mean_latency, None);
+    }
+
+    #[test]
+    fn round_trip_exactly_once() {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .to_string();
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .id(Some(&unique))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = QosTestOptions {
+            count: 20,
+            interval: 1,
+            timeout: 5000,
+            plug_name: "qosTest".into(),
+        };
+        let report = qos_test(&options, &mut tether_agent).unwrap();
+        assert!(report.is_exactly_once(), "{}", report);
+        assert_eq!(report.received, 20);
+        assert!(report.mean_latency.is_some());
+    }
+}
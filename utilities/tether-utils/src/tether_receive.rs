@@ -1,8 +1,24 @@
-use clap::Args;
+use std::{collections::HashMap, fmt, time::Duration};
+
+use clap::{Args, ValueEnum};
 use log::{debug, error, info, warn};
-use tether_agent::{three_part_topic::TetherOrCustomTopic, PlugOptionsBuilder, TetherAgent};
+use serde::Deserialize;
+use tether_agent::{
+    three_part_topic::TetherOrCustomTopic, truncate_preview, value_to_json, PlugDefinition,
+    PlugOptionsBuilder, ReceivedMessage, TetherAgent,
+};
+
+use crate::tether_shutdown::ShutdownSignal;
+
+pub use tether_agent::preview_payload;
+
+/// How many characters of each payload to show in log lines, unless specified
+pub const DEFAULT_PREVIEW_LENGTH: usize = 200;
+
+/// What the values of redacted keys are replaced with; see `PayloadRedaction`
+pub const REDACTED: &str = "***";
 
-#[derive(Args, Default)]
+#[derive(Args, Default, Clone)]
 pub struct ReceiveOptions {
     /// Specify a ROLE (instead of wildcard +)
     #[arg(long = "plug.role")]
@@ -21,57 +37,658 @@ pub struct ReceiveOptions {
     /// topic is built manually.
     #[arg(long = "topic")]
     pub subscribe_topic: Option<String>,
+
+    /// Flag to skip messages with empty (zero-length) payloads, e.g. the
+    /// "tombstones" published to clear retained messages; by default these
+    /// are passed on as messages with no decoded contents. Pass
+    /// `--ignoreEmpty=false` to override `ignoreEmpty = true` in a config file.
+    #[arg(long = "ignoreEmpty", num_args = 0..=1, default_missing_value = "true")]
+    pub ignore_empty_payloads: Option<bool>,
+
+    /// Maximum number of characters of each payload to include in
+    /// (debug) log lines; longer payloads are truncated [default: 200]
+    #[arg(long = "preview.length")]
+    pub preview_length: Option<usize>,
+
+    /// Comma-separated JSON key paths (e.g. "token,auth.password") whose values
+    /// are masked in decoded payloads before they are logged
+    #[arg(long = "redact")]
+    pub redact: Option<String>,
+
+    /// Flag to stop receiving if the connection to the broker is lost, instead
+    /// of subscribing again once reconnected; useful for debugging
+    #[arg(long = "reconnect.disable")]
+    pub disable_reconnect: bool,
+
+    /// How to wait for messages when none are arriving [default: block]
+    #[arg(long = "idle", value_enum)]
+    pub idle_strategy: Option<IdleStrategy>,
 }
 
+impl ReceiveOptions {
+    pub fn preview_length(&self) -> usize {
+        self.preview_length.unwrap_or(DEFAULT_PREVIEW_LENGTH)
+    }
+
+    pub fn ignore_empty_payloads(&self) -> bool {
+        self.ignore_empty_payloads.unwrap_or(false)
+    }
+
+    pub fn idle_strategy(&self) -> IdleStrategy {
+        self.idle_strategy.unwrap_or_default()
+    }
+
+    pub fn redaction(&self) -> PayloadRedaction {
+        self.redact
+            .as_deref()
+            .map(PayloadRedaction::new)
+            .unwrap_or_default()
+    }
+}
+
+/// Key paths (e.g. `auth.token`) whose values are masked in decoded payloads before they
+/// are logged, so that secrets embedded in messages do not end up in log files. Arrays
+/// along the way are searched element by element, so `users.password` masks the password
+/// of every user in a list, and strings holding JSON are searched as well. Payloads which
+/// are text but not JSON are masked entirely, since there are no keys to go by.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PayloadRedaction {
+    paths: Vec<Vec<String>>,
+}
+
+impl PayloadRedaction {
+    /// Parse comma-separated key paths, with the keys in each path separated by dots
+    pub fn new(key_paths: &str) -> Self {
+        PayloadRedaction {
+            paths: key_paths
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(|path| path.split('.').map(String::from).collect())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Mask the values in a decoded payload, keeping the order of the keys
+    pub fn apply(&self, value: &mut rmpv::Value) {
+        for path in &self.paths {
+            redact_path(value, path);
+        }
+    }
+
+    /// Mask the values in a JSON string, e.g. as produced by `decode_payload`; anything
+    /// which is not valid JSON is returned unchanged
+    pub fn redact_json(&self, json: String) -> String {
+        if self.is_empty() {
+            return json;
+        }
+        match serde_json::from_str::<rmpv::Value>(&json) {
+            Ok(mut value) => {
+                self.apply(&mut value);
+                value_to_json(&value)
+            }
+            Err(_) => json,
+        }
+    }
+
+    /// Mask the values in a payload which is text rather than MessagePack: JSON text as in
+    /// `redact_json`, and any other text as a whole (unless nothing is to be redacted)
+    pub fn redact_text(&self, text: &str) -> String {
+        if self.is_empty() {
+            return String::from(text);
+        }
+        match serde_json::from_str::<rmpv::Value>(text) {
+            Ok(mut value) => {
+                self.apply(&mut value);
+                value_to_json(&value)
+            }
+            Err(_) => String::from(REDACTED),
+        }
+    }
+}
+
+fn redact_path(value: &mut rmpv::Value, path: &[String]) {
+    match value {
+        rmpv::Value::Array(items) => items.iter_mut().for_each(|item| redact_path(item, path)),
+        rmpv::Value::Map(entries) => {
+            let Some((key, rest)) = path.split_first() else {
+                return;
+            };
+            for (_, found) in entries
+                .iter_mut()
+                .filter(|(k, _)| k.as_str() == Some(key.as_str()))
+            {
+                if rest.is_empty() {
+                    *found = rmpv::Value::from(REDACTED);
+                } else {
+                    redact_path(found, rest);
+                }
+            }
+        }
+        rmpv::Value::String(text) => {
+            // e.g. JSON sent as a MessagePack string
+            let Some(Ok(mut inner)) = text.as_str().map(serde_json::from_str::<rmpv::Value>) else {
+                return;
+            };
+            if inner.is_map() || inner.is_array() {
+                redact_path(&mut inner, path);
+                *value = rmpv::Value::from(value_to_json(&inner));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// How the receive loop waits for messages when none are waiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum IdleStrategy {
+    /// Check again after a fixed, very short sleep (0.1 ms): little added latency, but
+    /// the CPU never rests
+    Poll,
+    /// Sleep for longer the longer nothing arrives (from 0.1 ms, doubling up to 50 ms),
+    /// and go back to the shortest sleep as soon as something does
+    Backoff,
+    /// Wait on the Agent's queue, waking as soon as a message arrives (or at least every
+    /// 100 ms, to notice shutting down or connection changes)
+    #[default]
+    Block,
+}
+
+const POLL_SLEEP: Duration = Duration::from_micros(100);
+const MAX_BACKOFF_SLEEP: Duration = Duration::from_millis(50);
+const BLOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Takes the next message from the Agent, waiting according to an `IdleStrategy` if
+/// there is none
+#[derive(Debug, Clone)]
+pub struct IdleWait {
+    strategy: IdleStrategy,
+    sleep: Duration,
+}
+
+impl IdleWait {
+    pub fn new(strategy: IdleStrategy) -> Self {
+        IdleWait {
+            strategy,
+            sleep: POLL_SLEEP,
+        }
+    }
+
+    /// The next message, if any arrives; when None is returned, some time has been spent
+    /// waiting (or sleeping), so this can be called in a loop without spinning
+    pub fn next_message(&mut self, tether_agent: &TetherAgent) -> Option<ReceivedMessage> {
+        if self.strategy == IdleStrategy::Block {
+            return tether_agent.check_received_timeout(BLOCK_TIMEOUT);
+        }
+        if let Some(message) = tether_agent.check_received() {
+            self.sleep = POLL_SLEEP;
+            return Some(message);
+        }
+        std::thread::sleep(self.sleep);
+        if self.strategy == IdleStrategy::Backoff {
+            self.sleep = (self.sleep * 2).min(MAX_BACKOFF_SLEEP);
+        }
+        None
+    }
+
+    /// How long the next sleep will be, if nothing arrives
+    pub fn current_sleep(&self) -> Duration {
+        self.sleep
+    }
+}
+
+/// Everything about one received message, for tools such as loggers and forwarders which
+/// need the original bytes as well as the decoded contents
+#[derive(Debug, Clone)]
+pub struct ReceivedRecord {
+    /// The Plug Name part of the topic, or "unknown" for a custom topic
+    pub plug_name: String,
+    pub topic: String,
+    /// What the payload was decoded as, with any redacted values masked (see
+    /// `ReceiveOptions::redact`)
+    pub decoded: DecodedPayload,
+    /// The original message, with its raw payload, arrival time, index and flags
+    pub message: ReceivedMessage,
+}
+
+impl ReceivedRecord {
+    /// The raw payload bytes, exactly as received
+    pub fn payload(&self) -> &[u8] {
+        self.message.payload()
+    }
+}
+
+/// What a received payload turned out to be, so that consumers can tell an empty message
+/// apart from one which could not be decoded (e.g. to count errors, or set it aside)
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedPayload {
+    /// No payload at all, e.g. a "tombstone" clearing a retained message
+    Empty,
+    /// Valid MessagePack, converted to JSON
+    Json(String),
+    /// Not valid MessagePack, but valid UTF-8 text
+    Text(String),
+    /// Neither MessagePack nor text
+    DecodeFailed,
+}
+
+impl DecodedPayload {
+    /// The JSON, if the payload was valid MessagePack
+    pub fn json(&self) -> Option<&str> {
+        match self {
+            DecodedPayload::Json(json) => Some(json),
+            _ => None,
+        }
+    }
+
+    /// True for any payload which was not empty, but could not be decoded as MessagePack
+    pub fn is_failure(&self) -> bool {
+        matches!(self, DecodedPayload::Text(_) | DecodedPayload::DecodeFailed)
+    }
+}
+
+impl fmt::Display for DecodedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodedPayload::Empty => write!(f, "(empty message)"),
+            DecodedPayload::Json(json) => write!(f, "{}", json),
+            DecodedPayload::Text(text) => write!(f, "\"{}\" (text, not MessagePack)", text),
+            DecodedPayload::DecodeFailed => write!(f, "(invalid message)"),
+        }
+    }
+}
+
+/// Receive until interrupted by Ctrl+C (or SIGTERM), or until the connection is lost if
+/// reconnecting is disabled
 pub fn receive(
     options: &ReceiveOptions,
     tether_agent: &mut TetherAgent,
-    on_message: fn(plug_name: String, topic: String, decoded: Option<String>),
+    on_message: fn(plug_name: String, topic: String, decoded: DecodedPayload),
+) {
+    receive_until(
+        options,
+        tether_agent,
+        on_message,
+        &ShutdownSignal::from_os(),
+    )
+}
+
+/// Like `receive`, but stops when the given signal is requested instead
+pub fn receive_until(
+    options: &ReceiveOptions,
+    tether_agent: &mut TetherAgent,
+    on_message: fn(plug_name: String, topic: String, decoded: DecodedPayload),
+    shutdown: &ShutdownSignal,
+) {
+    receive_records_until(
+        options,
+        tether_agent,
+        |record| on_message(record.plug_name, record.topic, record.decoded),
+        shutdown,
+    )
+}
+
+/// Like `receive`, but passing a `ReceivedRecord` (with the raw payload and metadata as
+/// well as the decoded contents) for each message
+pub fn receive_records(
+    options: &ReceiveOptions,
+    tether_agent: &mut TetherAgent,
+    on_record: impl FnMut(ReceivedRecord),
+) {
+    receive_records_until(options, tether_agent, on_record, &ShutdownSignal::from_os())
+}
+
+/// Like `receive_records`, but stops when the given signal is requested instead
+pub fn receive_records_until(
+    options: &ReceiveOptions,
+    tether_agent: &mut TetherAgent,
+    mut on_record: impl FnMut(ReceivedRecord),
+    shutdown: &ShutdownSignal,
 ) {
     info!("Tether Receive Utility");
 
     let input_def = build_receiver_plug(options);
 
-    let input = input_def
+    let mut input = input_def
         .build(tether_agent)
         .expect("failed to create input plug");
 
     info!("Subscribed to topic \"{}\" ...", input.topic());
 
+    let redaction = options.redaction();
+    let mut decode_stats = DecodeStats::with_redaction(redaction.clone());
+    let mut connection_watch = ConnectionWatch::new(tether_agent);
+    let mut idle_wait = IdleWait::new(options.idle_strategy());
+
     loop {
-        let mut did_work = false;
-        while let Some((topic, payload)) = tether_agent.check_messages() {
-            did_work = true;
-            let full_topic_string = topic.full_topic_string();
+        if shutdown.is_requested() {
+            info!("Stopped receiving");
+            return;
+        }
+        match connection_watch.poll(tether_agent) {
+            ConnectionChange::Lost if options.disable_reconnect => {
+                error!("Connection lost; reconnect disabled, so stopping");
+                return;
+            }
+            ConnectionChange::Lost => {
+                warn!(
+                    "Connection lost ({}); reconnecting...",
+                    tether_agent
+                        .last_disconnect_reason()
+                        .unwrap_or("unknown reason".into())
+                );
+            }
+            ConnectionChange::Restored => {
+                info!(
+                    "Reconnected (x{}); subscribing again to topic \"{}\"",
+                    tether_agent.reconnect_count(),
+                    input.topic()
+                );
+                if let Err(e) = resubscribe(tether_agent, &mut input) {
+                    error!("Failed to subscribe again: {}", e);
+                }
+            }
+            ConnectionChange::Unchanged => {}
+        }
+
+        while let Some(message) = idle_wait.next_message(tether_agent) {
+            let full_topic_string = message.topic().full_topic_string();
             debug!("Received message on topic \"{}\"", &full_topic_string);
-            let plug_name = match topic {
+            let plug_name = match message.topic() {
                 TetherOrCustomTopic::Custom(_) => String::from("unknown"),
                 TetherOrCustomTopic::Tether(tpt) => String::from(tpt.plug_name()),
             };
 
-            if payload.is_empty() {
+            let decoded = if message.payload().is_empty() {
+                if options.ignore_empty_payloads() {
+                    debug!("Empty message payload; ignored");
+                    continue;
+                }
                 debug!("Empty message payload");
-                on_message(plug_name, full_topic_string, None);
-            } else if let Ok(value) = rmp_serde::from_slice::<rmpv::Value>(&payload) {
-                let json = serde_json::to_string(&value).expect("failed to stringify JSON");
-                debug!("Decoded MessagePack payload: {}", json);
-                on_message(plug_name, full_topic_string, Some(json));
+                DecodedPayload::Empty
             } else {
-                debug!("Failed to decode MessagePack payload");
-                if let Ok(s) = String::from_utf8(payload.to_vec()) {
-                    warn!("String representation of payload: \"{}\"", s);
-                } else {
-                    error!("Could not decode payload bytes as string, either");
-                }
-                on_message(plug_name, full_topic_string, None);
+                debug!(
+                    "Payload: {}",
+                    preview_payload_redacted(
+                        message.payload(),
+                        options.preview_length(),
+                        &redaction
+                    )
+                );
+                decode_stats.decode_outcome(&full_topic_string, message.payload())
+            };
+            on_record(ReceivedRecord {
+                plug_name,
+                topic: full_topic_string,
+                decoded,
+                message,
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionChange {
+    Unchanged,
+    /// The connection was lost (the Agent keeps trying to reconnect)
+    Lost,
+    /// The connection was re-established; subscriptions need to be made again
+    Restored,
+}
+
+/// Notices, when polled, whether the Agent's connection to the broker has been lost or
+/// re-established since the last time, based on its connection stats.
+#[derive(Debug, Clone)]
+pub struct ConnectionWatch {
+    reconnect_count: u32,
+    disconnected: bool,
+}
+
+impl ConnectionWatch {
+    pub fn new(tether_agent: &TetherAgent) -> Self {
+        let stats = tether_agent.connection_stats();
+        ConnectionWatch {
+            reconnect_count: stats.reconnect_count(),
+            disconnected: stats.is_disconnected(),
+        }
+    }
+
+    pub fn poll(&mut self, tether_agent: &TetherAgent) -> ConnectionChange {
+        let stats = tether_agent.connection_stats();
+        let reconnected = stats.reconnect_count() != self.reconnect_count;
+        let was_disconnected = self.disconnected;
+        self.reconnect_count = stats.reconnect_count();
+        self.disconnected = stats.is_disconnected();
+
+        if self.disconnected {
+            if was_disconnected && !reconnected {
+                ConnectionChange::Unchanged
+            } else {
+                ConnectionChange::Lost
+            }
+        } else if reconnected {
+            ConnectionChange::Restored
+        } else {
+            ConnectionChange::Unchanged
+        }
+    }
+}
+
+/// Subscribe again (with the same QoS) for an Input Plug, e.g. after reconnecting,
+/// since the broker does not keep subscriptions for a clean session
+pub fn resubscribe(
+    tether_agent: &TetherAgent,
+    plug_definition: &mut PlugDefinition,
+) -> anyhow::Result<()> {
+    let qos = plug_definition.qos();
+    match plug_definition {
+        PlugDefinition::InputPlug(p) => tether_agent.resubscribe_qos(p, qos),
+        PlugDefinition::OutputPlug(_) => Ok(()),
+    }
+}
+
+/// Counts of payloads which could be decoded, and of those which could not (per topic),
+/// so that a producer which starts sending garbage shows up as a number, not just log lines.
+///
+/// Payloads which decoded, but had extra bytes after the MessagePack value, are counted
+/// (per topic) as decoded and also separately, since they probably indicate a problem.
+#[derive(Debug, Default, Clone)]
+pub struct DecodeStats {
+    decoded: u64,
+    failures: HashMap<String, u64>,
+    trailing: HashMap<String, u64>,
+    redaction: PayloadRedaction,
+}
+
+impl DecodeStats {
+    /// Mask the values of the redacted keys in everything decoded, and in anything logged
+    /// about payloads which could not be decoded
+    pub fn with_redaction(redaction: PayloadRedaction) -> Self {
+        DecodeStats {
+            redaction,
+            ..DecodeStats::default()
+        }
+    }
+
+    /// Decode the payload (see `decode_payload`), counting the result against the topic
+    pub fn decode(&mut self, topic: &str, payload: &[u8]) -> Option<String> {
+        self.count(topic, payload, decode_tolerant(payload))
+    }
+
+    /// Count the result of decoding the payload against the topic
+    fn count(&mut self, topic: &str, payload: &[u8], decoded: TolerantDecode) -> Option<String> {
+        let mut value = match decoded {
+            TolerantDecode::Complete(value) => value,
+            TolerantDecode::TrailingBytes {
+                value, trailing, ..
+            } => {
+                let count = self.trailing.entry(String::from(topic)).or_default();
+                *count += 1;
+                warn!(
+                    "Decoded payload on topic \"{}\", but {} extra byte(s) present ({} such payload(s) so far)",
+                    topic, trailing, count
+                );
+                value
             }
+            TolerantDecode::Truncated { .. } | TolerantDecode::Invalid { .. } => {
+                let count = self.failures.entry(String::from(topic)).or_default();
+                *count += 1;
+                warn!(
+                    "Failed to decode payload on topic \"{}\" ({} failure(s) so far)",
+                    topic, count
+                );
+                log_undecodable(payload, &self.redaction);
+                return None;
+            }
+        };
+        self.decoded += 1;
+        self.redaction.apply(&mut value);
+        Some(value_to_json(&value))
+    }
+
+    /// Like `decode`, but telling apart empty payloads (which are not counted), and
+    /// payloads which are text rather than MessagePack. Text which happens to start with a
+    /// valid MessagePack value (any ASCII character is a MessagePack integer) is taken as
+    /// text, not counted, rather than as a value with trailing bytes.
+    pub fn decode_outcome(&mut self, topic: &str, payload: &[u8]) -> DecodedPayload {
+        if payload.is_empty() {
+            return DecodedPayload::Empty;
         }
-        if !did_work {
-            std::thread::sleep(std::time::Duration::from_micros(100)); //0.1 ms
+        let decoded = decode_tolerant(payload);
+        if let TolerantDecode::TrailingBytes { .. } = decoded {
+            if let Ok(text) = std::str::from_utf8(payload) {
+                return DecodedPayload::Text(self.redaction.redact_text(text));
+            }
         }
+        match self.count(topic, payload, decoded) {
+            Some(json) => DecodedPayload::Json(json),
+            None => match std::str::from_utf8(payload) {
+                Ok(text) => DecodedPayload::Text(self.redaction.redact_text(text)),
+                Err(_) => DecodedPayload::DecodeFailed,
+            },
+        }
+    }
+
+    pub fn decoded_count(&self) -> u64 {
+        self.decoded
+    }
+
+    /// Failures on one topic
+    pub fn failure_count(&self, topic: &str) -> u64 {
+        self.failures.get(topic).copied().unwrap_or_default()
+    }
+
+    /// Failures on all topics
+    pub fn total_failures(&self) -> u64 {
+        self.failures.values().sum()
+    }
+
+    /// Failure counts, keyed by topic
+    pub fn failures(&self) -> &HashMap<String, u64> {
+        &self.failures
+    }
+
+    /// Payloads on all topics which decoded, but with extra bytes left over
+    pub fn total_trailing(&self) -> u64 {
+        self.trailing.values().sum()
+    }
+
+    /// Counts of payloads with extra bytes left over, keyed by topic
+    pub fn trailing(&self) -> &HashMap<String, u64> {
+        &self.trailing
+    }
+}
+
+/// The outcome of decoding a MessagePack payload without requiring it to be exactly
+/// one complete value; see `decode_tolerant`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TolerantDecode {
+    /// The payload is exactly one complete value
+    Complete(rmpv::Value),
+    /// A complete value was decoded from the first `consumed` bytes, but `trailing`
+    /// bytes remain after it
+    TrailingBytes {
+        value: rmpv::Value,
+        consumed: usize,
+        trailing: usize,
+    },
+    /// The payload ends part-way through a value; `consumed` bytes were read
+    Truncated { consumed: usize },
+    /// The payload is not valid MessagePack; `consumed` bytes were read
+    Invalid { consumed: usize },
+}
+
+/// Decode a single MessagePack value from the start of the payload, reporting how many
+/// bytes were consumed and whether any remain, instead of simply succeeding or failing
+pub fn decode_tolerant(payload: &[u8]) -> TolerantDecode {
+    let mut remaining = payload;
+    let result = rmpv::Value::deserialize(&mut rmp_serde::Deserializer::new(&mut remaining));
+    let consumed = payload.len() - remaining.len();
+    match result {
+        Ok(value) if remaining.is_empty() => TolerantDecode::Complete(value),
+        Ok(value) => TolerantDecode::TrailingBytes {
+            value,
+            consumed,
+            trailing: remaining.len(),
+        },
+        Err(
+            rmp_serde::decode::Error::InvalidMarkerRead(e)
+            | rmp_serde::decode::Error::InvalidDataRead(e),
+        ) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            TolerantDecode::Truncated { consumed }
+        }
+        Err(_) => TolerantDecode::Invalid { consumed },
+    }
+}
+
+/// Decode a MessagePack payload into a JSON string, if possible
+pub fn decode_payload(payload: &[u8]) -> Option<String> {
+    if let Ok(value) = rmp_serde::from_slice::<rmpv::Value>(payload) {
+        Some(value_to_json(&value))
+    } else {
+        debug!("Failed to decode MessagePack payload");
+        log_undecodable(payload, &PayloadRedaction::default());
+        None
+    }
+}
+
+fn log_undecodable(payload: &[u8], redaction: &PayloadRedaction) {
+    if std::str::from_utf8(payload).is_ok() {
+        warn!(
+            "String representation of payload: {}",
+            preview_payload_redacted(payload, DEFAULT_PREVIEW_LENGTH, redaction)
+        );
+    } else {
+        error!("Could not decode payload bytes as string, either");
     }
 }
 
+/// Like `preview_payload`, but with the values of any redacted keys masked (and text which
+/// is not JSON masked entirely; see `PayloadRedaction::redact_text`)
+pub fn preview_payload_redacted(
+    payload: &[u8],
+    max_len: usize,
+    redaction: &PayloadRedaction,
+) -> String {
+    // Not `rmp_serde::from_slice`, which ignores anything after the first value, so that
+    // plain text (which starts with a MessagePack integer) is shown as text
+    let text = match decode_tolerant(payload) {
+        TolerantDecode::Complete(mut value) => {
+            redaction.apply(&mut value);
+            value_to_json(&value)
+        }
+        _ => format!(
+            "\"{}\"",
+            redaction.redact_text(&String::from_utf8_lossy(payload))
+        ),
+    };
+    truncate_preview(text, max_len, payload.len())
+}
+
 fn build_receiver_plug(options: &ReceiveOptions) -> PlugOptionsBuilder {
     if options.subscribe_id.is_some()
         || options.subscribe_role.is_some()
@@ -127,11 +744,492 @@ fn build_receiver_plug(options: &ReceiveOptions) -> PlugOptionsBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
     use tether_agent::TetherAgentOptionsBuilder;
 
-    use crate::tether_receive::build_receiver_plug;
+    use crate::{
+        tether_receive::build_receiver_plug,
+        tether_shutdown::{disconnect_cleanly, ShutdownSignal},
+    };
+
+    use super::{
+        decode_payload, decode_tolerant, preview_payload_redacted, receive_records_until,
+        receive_until, resubscribe, ConnectionChange, ConnectionWatch, DecodeStats, DecodedPayload,
+        IdleStrategy, IdleWait, PayloadRedaction, ReceiveOptions, TolerantDecode,
+        DEFAULT_PREVIEW_LENGTH, MAX_BACKOFF_SLEEP, POLL_SLEEP, REDACTED,
+    };
+
+    /// Keeps the message of every log record, from every test in this process
+    struct CapturingLogger {
+        messages: Mutex<Vec<String>>,
+    }
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURING_LOGGER: CapturingLogger = CapturingLogger {
+        messages: Mutex::new(Vec::new()),
+    };
+
+    /// Install the capturing logger (only once, since it is global) and return it
+    fn capture_logs() -> &'static CapturingLogger {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&CAPTURING_LOGGER).expect("no other logger should be installed");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        &CAPTURING_LOGGER
+    }
+
+    #[test]
+    fn redact_key_paths() {
+        let redaction = PayloadRedaction::new("token, auth.password,users.secret,missing.key");
+        let json = r#"{"token":"abc","auth":{"user":"me","password":"hunter2"},"users":[{"name":"a","secret":1},{"name":"b"}],"value":3}"#;
+        assert_eq!(
+            redaction.redact_json(json.into()),
+            r#"{"token":"***","auth":{"user":"me","password":"***"},"users":[{"name":"a","secret":"***"},{"name":"b"}],"value":3}"#
+        );
+
+        // A key path only matches from the top level
+        assert_eq!(
+            PayloadRedaction::new("password").redact_json(json.into()),
+            json
+        );
+        assert!(PayloadRedaction::new(" , ").is_empty());
+        assert_eq!(redaction.redact_json("not json".into()), "not json");
+
+        // JSON sent as a MessagePack string, at the top or further down
+        let mut value = rmpv::Value::from(r#"{"token":"abc","value":3}"#);
+        redaction.apply(&mut value);
+        assert_eq!(value, rmpv::Value::from(r#"{"token":"***","value":3}"#));
+        let nested = rmp_serde::to_vec_named(&serde_json::json!({
+            "auth": r#"{"password":"hunter2"}"#
+        }))
+        .unwrap();
+        assert_eq!(
+            preview_payload_redacted(&nested, DEFAULT_PREVIEW_LENGTH, &redaction),
+            r#"{"auth":"{\"password\":\"***\"}"}"#
+        );
+
+        // Text has no keys to go by, so it is masked as a whole, unless it is JSON
+        assert_eq!(redaction.redact_text("token=abc"), REDACTED);
+        assert_eq!(
+            redaction.redact_text(json),
+            redaction.redact_json(json.into())
+        );
+        assert_eq!(
+            preview_payload_redacted(b"token=abc", DEFAULT_PREVIEW_LENGTH, &redaction),
+            format!("\"{}\"", REDACTED)
+        );
+        assert_eq!(
+            PayloadRedaction::default().redact_text("token=abc"),
+            "token=abc"
+        );
+    }
+
+    #[test]
+    fn tolerant_decoding() {
+        let good = rmp_serde::to_vec(&("hello", 42)).unwrap();
+        assert!(matches!(
+            decode_tolerant(&good),
+            TolerantDecode::Complete(_)
+        ));
+
+        let mut extra = good.clone();
+        extra.extend_from_slice(&[0x01, 0x02, 0x03]);
+        match decode_tolerant(&extra) {
+            TolerantDecode::TrailingBytes {
+                value,
+                consumed,
+                trailing,
+            } => {
+                assert_eq!(value, rmpv::Value::Array(vec!["hello".into(), 42.into()]));
+                assert_eq!(consumed, good.len());
+                assert_eq!(trailing, 3);
+            }
+            other => panic!("expected trailing bytes, got {:?}", other),
+        }
+
+        let truncated = &good[..good.len() - 3];
+        assert!(matches!(
+            decode_tolerant(truncated),
+            TolerantDecode::Truncated { .. }
+        ));
+        assert!(matches!(
+            decode_tolerant(&[0xc1]),
+            TolerantDecode::Invalid { consumed: 1 }
+        ));
+
+        let mut stats = DecodeStats::default();
+        assert!(stats.decode("a/b/c", &extra).is_some());
+        assert!(stats.decode("a/b/c", &good).is_some());
+        assert!(stats.decode("a/b/c", truncated).is_none());
+        assert_eq!(stats.decoded_count(), 2);
+        assert_eq!(stats.total_trailing(), 1);
+        assert_eq!(stats.total_failures(), 1);
+    }
+
+    #[test]
+    fn resubscribes_after_reconnect() {
+        // Connecting a second client with the same MQTT Client ID makes the broker close
+        // the first connection, which (to the first client) looks like a broker restart
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+            .to_string();
+        let client_id = format!("receive-{}", unique);
+        let topic = format!("tester/{}/data", unique);
+
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .mqtt_client_id(Some(&client_id))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = ReceiveOptions {
+            subscribe_topic: Some(topic.clone()),
+            ..ReceiveOptions::default()
+        };
+        let mut input = build_receiver_plug(&options)
+            .build(&mut tether_agent)
+            .unwrap();
+        let mut connection_watch = ConnectionWatch::new(&tether_agent);
+        assert_eq!(
+            connection_watch.poll(&tether_agent),
+            ConnectionChange::Unchanged
+        );
+
+        let rival_agent = TetherAgentOptionsBuilder::new("rival")
+            .mqtt_client_id(Some(&client_id))
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let start = SystemTime::now();
+        while connection_watch.poll(&tether_agent) != ConnectionChange::Lost {
+            assert!(start.elapsed().unwrap() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(rival_agent);
+
+        let publisher = TetherAgentOptionsBuilder::new("publisher")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let start = SystemTime::now();
+        loop {
+            assert!(
+                start.elapsed().unwrap() < Duration::from_secs(15),
+                "nothing received after reconnecting"
+            );
+            if connection_watch.poll(&tether_agent) == ConnectionChange::Restored {
+                resubscribe(&tether_agent, &mut input).ok();
+            }
+            publisher
+                .publish_raw(&topic, &[0x01], Some(1), None)
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            if tether_agent.check_messages().is_some() {
+                break;
+            }
+        }
+        assert!(tether_agent.reconnect_count() >= 1);
+    }
+
+    #[test]
+    fn redacted_payload_logged() {
+        #[derive(serde::Serialize)]
+        struct Login {
+            user: String,
+            token: String,
+        }
+
+        let logs = capture_logs();
+        let topic = format!(
+            "tester/{}/login",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = ReceiveOptions {
+            subscribe_topic: Some(topic.clone()),
+            redact: Some("token".into()),
+            ..ReceiveOptions::default()
+        };
+
+        let shutdown = ShutdownSignal::new();
+        let interrupt = shutdown.clone();
+        let publisher = std::thread::spawn(move || {
+            let publisher = TetherAgentOptionsBuilder::new("publisher")
+                .build()
+                .expect("sorry, these tests require working localhost Broker");
+            let payload = rmp_serde::to_vec_named(&Login {
+                user: "someone".into(),
+                token: "s3cret-t0ken".into(),
+            })
+            .unwrap();
+            let start = SystemTime::now();
+            while !interrupt.is_requested() && start.elapsed().unwrap() < Duration::from_secs(5) {
+                publisher.publish_raw(&topic, &payload, Some(1), None).ok();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            interrupt.request();
+        });
+
+        let mut decoded = Vec::new();
+        receive_records_until(
+            &options,
+            &mut tether_agent,
+            |record| {
+                decoded.push(record.decoded);
+                shutdown.request();
+            },
+            &shutdown,
+        );
+        publisher.join().unwrap();
+
+        assert_eq!(
+            decoded[0],
+            DecodedPayload::Json(r#"{"user":"someone","token":"***"}"#.into())
+        );
+        let messages = logs.messages.lock().unwrap().clone();
+        assert!(messages
+            .iter()
+            .any(|m| m == r#"Payload: {"user":"someone","token":"***"}"#));
+        assert!(!messages.iter().any(|m| m.contains("s3cret-t0ken")));
+    }
+
+    #[test]
+    fn stops_on_interrupt() {
+        static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+        let topic = format!(
+            "tester/{}/interrupted",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = ReceiveOptions {
+            subscribe_topic: Some(topic.clone()),
+            ..ReceiveOptions::default()
+        };
+
+        // Simulate Ctrl+C once something has been received
+        let shutdown = ShutdownSignal::new();
+        let interrupt = shutdown.clone();
+        let publisher = std::thread::spawn(move || {
+            let publisher = TetherAgentOptionsBuilder::new("publisher")
+                .build()
+                .expect("sorry, these tests require working localhost Broker");
+            let start = SystemTime::now();
+            while RECEIVED.load(Ordering::SeqCst) == 0
+                && start.elapsed().unwrap() < Duration::from_secs(5)
+            {
+                publisher.publish_raw(&topic, &[0x01], Some(1), None).ok();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            interrupt.request();
+        });
+
+        receive_until(
+            &options,
+            &mut tether_agent,
+            |_, _, _| {
+                RECEIVED.fetch_add(1, Ordering::SeqCst);
+            },
+            &shutdown,
+        );
+        publisher.join().unwrap();
+        assert!(RECEIVED.load(Ordering::SeqCst) > 0);
+
+        assert!(tether_agent.is_connected());
+        disconnect_cleanly(&mut tether_agent);
+        assert!(!tether_agent.is_connected());
+    }
+
+    #[test]
+    fn records_raw_and_decoded() {
+        let topic = format!(
+            "tester/{}/record",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = ReceiveOptions {
+            subscribe_topic: Some(topic.clone()),
+            ..ReceiveOptions::default()
+        };
+        let payload = rmp_serde::to_vec(&[1, 2, 3]).unwrap();
+
+        let shutdown = ShutdownSignal::new();
+        let publisher = {
+            let shutdown = shutdown.clone();
+            let topic = topic.clone();
+            let payload = payload.clone();
+            std::thread::spawn(move || {
+                let publisher = TetherAgentOptionsBuilder::new("publisher")
+                    .build()
+                    .expect("sorry, these tests require working localhost Broker");
+                let start = SystemTime::now();
+                while !shutdown.is_requested() {
+                    assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+                    publisher.publish_raw(&topic, &payload, Some(1), None).ok();
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            })
+        };
+
+        let mut records = Vec::new();
+        receive_records_until(
+            &options,
+            &mut tether_agent,
+            |record| {
+                records.push(record);
+                shutdown.request();
+            },
+            &shutdown,
+        );
+        publisher.join().unwrap();
+
+        let record = &records[0];
+        assert_eq!(record.topic, topic);
+        assert_eq!(record.plug_name, "record");
+        assert_eq!(record.payload(), payload.as_slice());
+        assert_eq!(record.decoded, DecodedPayload::Json("[1,2,3]".into()));
+        assert!(!record.message.is_retained());
+    }
+
+    #[test]
+    fn idle_backoff() {
+        let tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+
+        let mut poll = IdleWait::new(IdleStrategy::Poll);
+        assert!(poll.next_message(&tether_agent).is_none());
+        assert_eq!(poll.current_sleep(), POLL_SLEEP);
+
+        // Grows while idle, up to the maximum
+        let mut backoff = IdleWait::new(IdleStrategy::Backoff);
+        assert!(backoff.next_message(&tether_agent).is_none());
+        assert_eq!(backoff.current_sleep(), POLL_SLEEP * 2);
+        for _ in 0..12 {
+            backoff.next_message(&tether_agent);
+        }
+        assert_eq!(backoff.current_sleep(), MAX_BACKOFF_SLEEP);
+
+        let start = SystemTime::now();
+        let mut block = IdleWait::new(IdleStrategy::Block);
+        assert!(block.next_message(&tether_agent).is_none());
+        assert!(start.elapsed().unwrap() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn decode_outcomes() {
+        let mut stats = DecodeStats::default();
+        let good = rmp_serde::to_vec(&[1, 2, 3]).unwrap();
+
+        assert_eq!(stats.decode_outcome("a/b/c", &[]), DecodedPayload::Empty);
+        assert_eq!(
+            stats.decode_outcome("a/b/c", &good),
+            DecodedPayload::Json("[1,2,3]".into())
+        );
+        // Starts with 0xc4 (a MessagePack "bin" of 0x85 bytes, which are not all there)
+        let text = "\u{105} is text";
+        assert_eq!(
+            stats.decode_outcome("a/b/c", text.as_bytes()),
+            DecodedPayload::Text(text.into())
+        );
+        let outcome = stats.decode_outcome("a/b/c", &[0xc1, 0xff, 0xfe]);
+        assert_eq!(outcome, DecodedPayload::DecodeFailed);
+        assert!(outcome.is_failure());
+        assert_eq!(outcome.to_string(), "(invalid message)");
+        // Plain ASCII starts with a MessagePack integer, but is still text
+        assert_eq!(
+            stats.decode_outcome("a/b/c", b"hello"),
+            DecodedPayload::Text("hello".into())
+        );
+        assert_eq!(stats.total_trailing(), 0);
+
+        // Empty payloads and text are not counted either way
+        assert_eq!(stats.decoded_count(), 1);
+        assert_eq!(stats.total_failures(), 2);
+
+        let mut redacting = DecodeStats::with_redaction(PayloadRedaction::new("token"));
+        let login = rmp_serde::to_vec_named(&serde_json::json!({ "token": "abc" })).unwrap();
+        assert_eq!(
+            redacting.decode_outcome("a/b/c", &login),
+            DecodedPayload::Json(r#"{"token":"***"}"#.into())
+        );
+        assert_eq!(
+            redacting.decode_outcome("a/b/c", text.as_bytes()),
+            DecodedPayload::Text(REDACTED.into())
+        );
+    }
 
-    use super::ReceiveOptions;
+    #[test]
+    fn non_string_map_keys() {
+        // A map with a nil key is valid MessagePack, but has no JSON equivalent
+        let payload = [0x81, 0xc0, 0x01];
+        let mut stats = DecodeStats::default();
+        assert_eq!(
+            stats.decode_outcome("a/b/c", &payload),
+            DecodedPayload::Json("{nil: 1}".into())
+        );
+        assert_eq!(decode_payload(&payload), Some("{nil: 1}".into()));
+        assert_eq!(
+            preview_payload_redacted(
+                &payload,
+                DEFAULT_PREVIEW_LENGTH,
+                &PayloadRedaction::new("token")
+            ),
+            "{nil: 1}"
+        );
+    }
+
+    #[test]
+    fn decode_failures_counted() {
+        let mut stats = DecodeStats::default();
+        let good = rmp_serde::to_vec(&[1, 2, 3]).unwrap();
+
+        assert!(stats.decode("a/b/c", &good).is_some());
+        assert!(stats.decode("a/b/c", &[0xc1]).is_none());
+        assert!(stats.decode("a/b/c", &[0x92, 0x01]).is_none());
+        assert!(stats.decode("x/y/z", &[0xc1, 0xff, 0xfe]).is_none());
+
+        assert_eq!(stats.decoded_count(), 1);
+        assert_eq!(stats.failure_count("a/b/c"), 2);
+        assert_eq!(stats.failure_count("x/y/z"), 1);
+        assert_eq!(stats.failure_count("never/seen/topic"), 0);
+        assert_eq!(stats.total_failures(), 3);
+    }
 
     #[test]
     fn default_options() {
@@ -160,6 +1258,11 @@ mod tests {
             subscribe_id: None,
             subscribe_plug_name: None,
             subscribe_topic: Some("some/special/plug".into()),
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -181,6 +1284,11 @@ mod tests {
             subscribe_id: None,
             subscribe_plug_name: Some("something".into()),
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -202,6 +1310,11 @@ mod tests {
             subscribe_id: None,
             subscribe_plug_name: None,
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -223,6 +1336,11 @@ mod tests {
             subscribe_id: Some("something".into()),
             subscribe_plug_name: None,
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -244,6 +1362,11 @@ mod tests {
             subscribe_id: Some("y".into()),
             subscribe_plug_name: None,
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -265,6 +1388,11 @@ mod tests {
             subscribe_id: None,
             subscribe_plug_name: Some("z".into()),
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -286,6 +1414,11 @@ mod tests {
             subscribe_id: Some("y".into()),
             subscribe_plug_name: Some("z".into()),
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -307,6 +1440,11 @@ mod tests {
             subscribe_id: None,
             subscribe_plug_name: Some("+".into()),
             subscribe_topic: None,
+            ignore_empty_payloads: None,
+            preview_length: None,
+            redact: None,
+            disable_reconnect: false,
+            idle_strategy: None,
         };
 
         let receive_plug = build_receiver_plug(&options)
@@ -9,7 +9,10 @@ use clap::Args;
 use log::{debug, info, warn};
 use tether_agent::{PlugOptionsBuilder, TetherAgent};
 
-use crate::tether_playback::{SimulationMessage, SimulationRow};
+use crate::{
+    tether_playback::{SimulationMessage, SimulationRow},
+    tether_shutdown::ShutdownSignal,
+};
 
 #[derive(Args, Clone)]
 pub struct RecordOptions {
@@ -167,14 +170,9 @@ impl TetherRecordUtil {
         // let should_stop_clone = Arc::clone(&should_stop);
 
         if !self.options.ignore_ctrl_c {
-            ctrlc::set_handler(move || {
-                // should_stop_clone.store(true, Ordering::Relaxed);
-                stop_from_key
-                    .send(true)
-                    .expect("failed to send stop from key");
-                warn!("received Ctrl+C!");
-            })
-            .expect("Error setting Ctrl-C handler");
+            ShutdownSignal::from_os().on_request(move || {
+                stop_from_key.send(true).ok();
+            });
         } else {
             warn!(
                 "No Ctrl+C handler set; you may need to kill this process manually, PID: {}",
@@ -0,0 +1,292 @@
+use std::{
+    io::{stdin, stdout, BufRead, Write},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use clap::Args;
+use log::{debug, info, warn};
+use tether_agent::{three_part_topic::topic_filter_matches, PlugOptionsBuilder, TetherAgent};
+
+use crate::{
+    tether_receive::decode_payload,
+    tether_send::encode_json_message,
+    tether_shutdown::ShutdownSignal,
+    tether_topics::{insights::Insights, parse_plug_name, TopicOptions},
+};
+
+#[derive(Args, Clone, Default)]
+pub struct ReplOptions {
+    /// Flag to disable registration of Ctrl+C handler - this is usually necessary
+    /// when using the utility programmatically (i.e. not via CLI)
+    #[arg(long = "ignoreCtrlC")]
+    pub ignore_ctrl_c: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplCommand {
+    /// Subscribe to a topic (wildcards allowed) and print any messages received on it
+    Subscribe(String),
+    /// Publish on a topic, with an optional JSON message encoded as MessagePack
+    Publish {
+        topic: String,
+        json: Option<String>,
+    },
+    /// List all topics discovered so far
+    List,
+    /// Print messages on any topic with the given plug name
+    Watch(String),
+    /// Stop printing messages (existing subscriptions remain)
+    Unwatch,
+    Help,
+    Quit,
+}
+
+const HELP: &str = "Commands:
+  sub <topic>            subscribe (wildcards allowed) and print messages on this topic
+  pub <topic> [<json>]   publish a message, optionally with a JSON payload
+  ls                     list all topics discovered so far
+  watch <plugName>       print messages on any topic with this plug name
+  unwatch                stop printing messages
+  help                   show this help
+  quit                   exit (or press Ctrl+C)";
+
+pub fn parse_command(line: &str) -> anyhow::Result<ReplCommand> {
+    let line = line.trim();
+    let (command, rest) = match line.split_once(char::is_whitespace) {
+        Some((c, r)) => (c, r.trim()),
+        None => (line, ""),
+    };
+    match command {
+        "sub" | "subscribe" => match rest {
+            "" => Err(anyhow!("Usage: sub <topic>")),
+            topic => Ok(ReplCommand::Subscribe(topic.into())),
+        },
+        "pub" | "publish" => {
+            let (topic, json) = match rest.split_once(char::is_whitespace) {
+                Some((t, j)) => (t, Some(String::from(j.trim()))),
+                None => (rest, None),
+            };
+            if topic.is_empty() {
+                Err(anyhow!("Usage: pub <topic> [<json>]"))
+            } else if topic.contains(['+', '#']) {
+                Err(anyhow!("Cannot publish on a wildcard topic"))
+            } else {
+                Ok(ReplCommand::Publish {
+                    topic: topic.into(),
+                    json,
+                })
+            }
+        }
+        "ls" | "list" => Ok(ReplCommand::List),
+        "watch" => match rest {
+            "" => Err(anyhow!("Usage: watch <plugName>")),
+            plug_name => Ok(ReplCommand::Watch(plug_name.into())),
+        },
+        "unwatch" => Ok(ReplCommand::Unwatch),
+        "help" | "?" => Ok(ReplCommand::Help),
+        "quit" | "exit" => Ok(ReplCommand::Quit),
+        other => Err(anyhow!("Unknown command \"{}\"; try \"help\"", other)),
+    }
+}
+
+pub struct TetherRepl {
+    options: ReplOptions,
+    subscriptions: Vec<String>,
+    watching: Vec<String>,
+}
+
+impl TetherRepl {
+    pub fn new(options: ReplOptions) -> Self {
+        info!("Tether REPL Utility: initialise");
+        TetherRepl {
+            options,
+            subscriptions: Vec::new(),
+            watching: Vec::new(),
+        }
+    }
+
+    pub fn start(&mut self, tether_agent: &mut TetherAgent) {
+        let (line_tx, line_rx) = mpsc::channel::<String>();
+        let (stop_tx, stop_rx) = mpsc::channel::<bool>();
+
+        if !self.options.ignore_ctrl_c {
+            ShutdownSignal::from_os().on_request(move || {
+                stop_tx.send(true).ok();
+            });
+        } else {
+            warn!(
+                "No Ctrl+C handler set; you may need to kill this process manually, PID: {}",
+                std::process::id()
+            );
+        }
+
+        // Discover topics in the background, so that "ls" has something to show
+        let mut insights = Insights::new(&TopicOptions::default(), tether_agent);
+
+        // Reading from stdin blocks, so it happens on its own thread
+        thread::spawn(move || {
+            for line in stdin().lock().lines() {
+                match line {
+                    Ok(l) => {
+                        if line_tx.send(l).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        println!("Tether REPL; type \"help\" for a list of commands");
+        prompt();
+
+        loop {
+            if stop_rx.try_recv().is_ok() {
+                println!("\nBye");
+                return;
+            }
+
+            let mut did_work = false;
+
+            if let Ok(line) = line_rx.try_recv() {
+                did_work = true;
+                if !line.trim().is_empty() {
+                    match parse_command(&line) {
+                        Ok(ReplCommand::Quit) => {
+                            println!("Bye");
+                            return;
+                        }
+                        Ok(command) => self.run(command, tether_agent, &insights),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                prompt();
+            }
+
+            while let Some((topic, payload)) = tether_agent.check_messages() {
+                did_work = true;
+                let full_topic_string = topic.full_topic_string();
+                let should_print = self
+                    .subscriptions
+                    .iter()
+                    .any(|s| topic_filter_matches(s, &full_topic_string))
+                    || parse_plug_name(&full_topic_string)
+                        .is_some_and(|p| self.watching.iter().any(|w| w == p));
+                if should_print {
+                    let contents = if payload.is_empty() {
+                        String::from("(empty message)")
+                    } else {
+                        decode_payload(&payload).unwrap_or("(invalid message)".into())
+                    };
+                    println!("\n\"{}\" :: {}", full_topic_string, contents);
+                    prompt();
+                }
+                insights.update(&topic, payload);
+            }
+
+            if !did_work {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    fn run(&mut self, command: ReplCommand, tether_agent: &mut TetherAgent, insights: &Insights) {
+        debug!("Run command {:?}", command);
+        match command {
+            ReplCommand::Subscribe(topic) => {
+                // Everything is already subscribed (via "#") for topic discovery, except
+                // for topics starting with "$"; subscribing again would only produce
+                // duplicate messages on overlapping subscriptions
+                if topic.starts_with('$') {
+                    if let Err(e) = PlugOptionsBuilder::create_input("repl")
+                        .topic(Some(&topic))
+                        .build(tether_agent)
+                    {
+                        println!("Failed to subscribe: {}", e);
+                        return;
+                    }
+                }
+                println!("Subscribed to \"{}\"", topic);
+                self.subscriptions.push(topic);
+            }
+            ReplCommand::Publish { topic, json } => {
+                let payload = match json.as_deref().map(encode_json_message) {
+                    Some(Ok(p)) => p,
+                    Some(Err(e)) => {
+                        println!("Invalid JSON: {}", e);
+                        return;
+                    }
+                    None => Vec::new(),
+                };
+                match tether_agent.publish_raw(&topic, &payload, None, None) {
+                    Ok(()) => println!("Published on \"{}\"", topic),
+                    Err(e) => println!("Failed to publish: {}", e),
+                }
+            }
+            ReplCommand::List => {
+                if insights.topics().is_empty() {
+                    println!("No topics discovered yet");
+                }
+                for topic in insights.topics() {
+                    println!("  {}", topic);
+                }
+            }
+            ReplCommand::Watch(plug_name) => {
+                println!("Watching plug \"{}\"", plug_name);
+                self.watching.push(plug_name);
+            }
+            ReplCommand::Unwatch => {
+                self.subscriptions.clear();
+                self.watching.clear();
+                println!("No longer printing messages");
+            }
+            ReplCommand::Help => println!("{}", HELP),
+            ReplCommand::Quit => {}
+        }
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    stdout().flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_command, ReplCommand};
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            parse_command("sub +/+/foo").unwrap(),
+            ReplCommand::Subscribe("+/+/foo".into())
+        );
+        assert_eq!(
+            parse_command("  pub a/b/c {\"x\": [1, 2]} ").unwrap(),
+            ReplCommand::Publish {
+                topic: "a/b/c".into(),
+                json: Some("{\"x\": [1, 2]}".into())
+            }
+        );
+        assert_eq!(
+            parse_command("pub a/b/c").unwrap(),
+            ReplCommand::Publish {
+                topic: "a/b/c".into(),
+                json: None
+            }
+        );
+        assert_eq!(parse_command("ls").unwrap(), ReplCommand::List);
+        assert_eq!(
+            parse_command("watch colours").unwrap(),
+            ReplCommand::Watch("colours".into())
+        );
+        assert_eq!(parse_command("quit").unwrap(), ReplCommand::Quit);
+
+        assert!(parse_command("sub").is_err());
+        assert!(parse_command("pub +/b/c").is_err());
+        assert!(parse_command("frobnicate").is_err());
+    }
+}
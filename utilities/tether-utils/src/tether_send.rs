@@ -78,25 +78,23 @@ pub fn send(options: &SendOptions, tether_agent: &mut TetherAgent) -> anyhow::Re
                 "Attempting to decode provided custom message \"{}\"",
                 &custom_message
             );
-            match serde_json::from_str::<serde_json::Value>(custom_message) {
-                Ok(encoded) => {
-                    let payload =
-                        rmp_serde::to_vec_named(&encoded).expect("failed to encode msgpack");
+            match encode_json_message(custom_message) {
+                Ok(payload) => {
                     tether_agent
-                        .publish(&output, Some(&payload))
+                        .publish(&output, &payload)
                         .expect("failed to publish");
                     info!("Sent message OK");
                     Ok(())
                 }
                 Err(e) => {
                     error!("Could not serialise String -> JSON; error: {}", e);
-                    Err(e.into())
+                    Err(e)
                 }
             }
         }
         None => {
             warn!("Sending empty message");
-            match tether_agent.publish(&output, None) {
+            match tether_agent.publish_empty(&output) {
                 Ok(_) => {
                     info!("Sent empty message OK");
                     Ok(())
@@ -109,3 +107,9 @@ pub fn send(options: &SendOptions, tether_agent: &mut TetherAgent) -> anyhow::Re
         }
     }
 }
+
+/// Convert a JSON string into a MessagePack payload
+pub fn encode_json_message(json: &str) -> anyhow::Result<Vec<u8>> {
+    let value = serde_json::from_str::<serde_json::Value>(json)?;
+    Ok(rmp_serde::to_vec_named(&value)?)
+}
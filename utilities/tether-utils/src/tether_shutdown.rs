@@ -0,0 +1,128 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, OnceLock,
+};
+
+use log::{error, info, warn};
+use tether_agent::TetherAgent;
+
+type ShutdownCallback = Box<dyn Fn() + Send>;
+
+/// Set when the operator asks a utility to stop, so that it can leave its loop and
+/// disconnect cleanly instead of being killed with the connection still open (which the
+/// broker treats as an unclean disconnect, publishing any "last will").
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    requested: Arc<AtomicBool>,
+    callbacks: Arc<Mutex<Vec<ShutdownCallback>>>,
+}
+
+static OS_SIGNAL: OnceLock<ShutdownSignal> = OnceLock::new();
+
+impl ShutdownSignal {
+    /// A signal which is only requested by calling `request`, e.g. to stop a utility
+    /// from another thread (or a test)
+    pub fn new() -> Self {
+        ShutdownSignal::default()
+    }
+
+    /// The signal requested by Ctrl+C (SIGINT) or SIGTERM. The handler is installed the
+    /// first time this is called, and shared by every utility in the process; a second
+    /// Ctrl+C exits straight away, in case shutting down is stuck.
+    pub fn from_os() -> Self {
+        OS_SIGNAL
+            .get_or_init(|| {
+                let signal = ShutdownSignal::new();
+                let handler_signal = signal.clone();
+                if let Err(e) = ctrlc::set_handler(move || {
+                    if handler_signal.is_requested() {
+                        warn!("Interrupted again; exit now");
+                        std::process::exit(130);
+                    }
+                    warn!("Interrupted; shutting down (press Ctrl+C again to force)...");
+                    handler_signal.request();
+                }) {
+                    error!("Error setting Ctrl+C handler: {}", e);
+                    warn!(
+                        "You may need to kill this process manually, PID: {}",
+                        std::process::id()
+                    );
+                }
+                signal
+            })
+            .clone()
+    }
+
+    /// Ask to shut down, calling every `on_request` callback
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        for callback in self.callbacks.lock().expect("failed to lock mutex").iter() {
+            callback();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Also call this when shutting down is requested, e.g. to wake up a loop which is
+    /// waiting on a channel
+    pub fn on_request(&self, callback: impl Fn() + Send + 'static) {
+        self.callbacks
+            .lock()
+            .expect("failed to lock mutex")
+            .push(Box::new(callback));
+    }
+}
+
+/// Disconnect from the broker cleanly, once everything published so far has been sent
+/// (`disconnect` waits for that, for up to a few seconds)
+pub fn disconnect_cleanly(tether_agent: &mut TetherAgent) {
+    if !tether_agent.is_connected() {
+        return;
+    }
+    match tether_agent.disconnect() {
+        Ok(()) => info!("Disconnected cleanly"),
+        Err(e) => error!("Failed to disconnect cleanly: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tether_agent::TetherAgentOptionsBuilder;
+
+    use super::{disconnect_cleanly, ShutdownSignal};
+
+    #[test]
+    fn request_calls_back() {
+        let signal = ShutdownSignal::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        signal.on_request(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(!signal.is_requested());
+
+        // Clones share the same state
+        signal.clone().request();
+        assert!(signal.is_requested());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn disconnects_once() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        assert!(tether_agent.is_connected());
+        disconnect_cleanly(&mut tether_agent);
+        assert!(!tether_agent.is_connected());
+        // Nothing left to do
+        disconnect_cleanly(&mut tether_agent);
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tether_agent::{PlugOptionsBuilder, TetherAgent};
+
+use crate::{
+    tether_receive::{resubscribe, ConnectionChange, ConnectionWatch},
+    tether_shutdown::ShutdownSignal,
+};
+
+#[derive(Args, Clone)]
+pub struct SysmonOptions {
+    /// Topic prefix under which the broker publishes its own stats; everything
+    /// below it (`PREFIX/#`) is subscribed
+    #[arg(long = "sys.prefix", default_value_t = String::from("$SYS"))]
+    pub prefix: String,
+
+    /// How long to wait (in milliseconds) for any stats before concluding that the
+    /// broker does not publish them; Mosquitto only does so every 10 seconds by default
+    #[arg(long = "timeout", default_value_t = 15000)]
+    pub timeout: u64,
+
+    /// Time between summaries, in milliseconds
+    #[arg(long = "interval", default_value_t = 5000)]
+    pub interval: u64,
+
+    /// Flag to print each summary as a single line of JSON (e.g. for exporting to
+    /// a file), instead of logging it as text
+    #[arg(long = "json")]
+    pub json: bool,
+
+    /// Stop after this many summaries, instead of running until interrupted
+    #[arg(long = "count")]
+    pub count: Option<u64>,
+}
+
+/// The broker-level metrics which are understood, whichever broker reports them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Version,
+    Uptime,
+    ClientsConnected,
+    MessagesReceived,
+    MessagesSent,
+}
+
+impl Metric {
+    /// Identify a metric from its topic below the prefix, for both the Mosquitto
+    /// (`broker/...`) and the EMQX (`brokers/<node>/...`) layouts
+    fn from_path(path: &str) -> Option<Metric> {
+        let parts: Vec<&str> = path.split('/').collect();
+        match parts.as_slice() {
+            ["broker", "version"] | ["brokers", _, "version"] => Some(Metric::Version),
+            ["broker", "uptime"] | ["brokers", _, "uptime"] => Some(Metric::Uptime),
+            ["broker", "clients", "connected"]
+            | ["broker", "clients", "active"]
+            | ["brokers", _, "stats", "connections", "count"] => Some(Metric::ClientsConnected),
+            ["broker", "messages", "received"]
+            | ["brokers", _, "metrics", "messages", "received"] => Some(Metric::MessagesReceived),
+            ["broker", "messages", "sent"] | ["brokers", _, "metrics", "messages", "sent"] => {
+                Some(Metric::MessagesSent)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parse an uptime such as `1234 seconds` (Mosquitto), `1 days, 2 hours, 3 minutes,
+/// 4 seconds` (EMQX) or simply `1234`, into a number of seconds
+pub fn parse_uptime(text: &str) -> Option<u64> {
+    let mut total = 0;
+    let mut parsed_any = false;
+    for part in text.split(',') {
+        let mut words = part.split_whitespace();
+        let amount: u64 = words.next()?.parse().ok()?;
+        let unit_seconds = match words.next() {
+            None => 1,
+            Some(unit) => match unit.trim_end_matches('s') {
+                "second" => 1,
+                "minute" => 60,
+                "hour" => 60 * 60,
+                "day" => 24 * 60 * 60,
+                _ => return None,
+            },
+        };
+        total += amount * unit_seconds;
+        parsed_any = true;
+    }
+    parsed_any.then_some(total)
+}
+
+/// One summary of the broker's stats, as printed (or exported as JSON)
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BrokerStats {
+    pub version: Option<String>,
+    /// In seconds
+    pub uptime: Option<u64>,
+    pub clients_connected: Option<u64>,
+    /// Total since the broker started
+    pub messages_received: Option<u64>,
+    /// Total since the broker started
+    pub messages_sent: Option<u64>,
+    /// Since the previous summary; unknown for the first
+    pub received_per_sec: Option<f64>,
+    /// Since the previous summary; unknown for the first
+    pub sent_per_sec: Option<f64>,
+}
+
+impl fmt::Display for BrokerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn or_unknown<T: fmt::Display>(value: &Option<T>) -> String {
+            value.as_ref().map(|v| v.to_string()).unwrap_or("?".into())
+        }
+        fn rate(value: Option<f64>) -> String {
+            value.map(|r| format!("{:.1}", r)).unwrap_or("?".into())
+        }
+        writeln!(f, "Broker: {}", or_unknown(&self.version))?;
+        writeln!(f, "Uptime: {}s", or_unknown(&self.uptime))?;
+        writeln!(
+            f,
+            "Clients connected: {}",
+            or_unknown(&self.clients_connected)
+        )?;
+        writeln!(
+            f,
+            "Messages received: {} ({}/s)",
+            or_unknown(&self.messages_received),
+            rate(self.received_per_sec)
+        )?;
+        write!(
+            f,
+            "Messages sent: {} ({}/s)",
+            or_unknown(&self.messages_sent),
+            rate(self.sent_per_sec)
+        )
+    }
+}
+
+/// Keeps the latest value of every metric reported by the broker, and works out the
+/// message rates from the change in the totals between summaries.
+///
+/// If a clustered broker reports the same metric for several nodes, the most recently
+/// received value is used.
+#[derive(Debug, Clone, Default)]
+pub struct SysStats {
+    latest: BrokerStats,
+    /// Every value seen, keyed by topic below the prefix, whether understood or not
+    raw: BTreeMap<String, String>,
+    previous: Option<(Instant, Option<u64>, Option<u64>)>,
+}
+
+impl SysStats {
+    /// Note a value published by the broker, given its topic below the prefix; returns
+    /// whether it was one of the metrics understood
+    pub fn update(&mut self, path: &str, payload: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(payload).trim().to_string();
+        self.raw.insert(String::from(path), text.clone());
+        let Some(metric) = Metric::from_path(path) else {
+            return false;
+        };
+        let number = || match text.parse() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                warn!("Could not parse \"{}\" as a number on \"{}\"", text, path);
+                None
+            }
+        };
+        match metric {
+            Metric::Version => self.latest.version = Some(text.clone()),
+            Metric::Uptime => self.latest.uptime = parse_uptime(&text),
+            Metric::ClientsConnected => self.latest.clients_connected = number(),
+            Metric::MessagesReceived => self.latest.messages_received = number(),
+            Metric::MessagesSent => self.latest.messages_sent = number(),
+        }
+        true
+    }
+
+    /// Whether anything at all has been received from the broker
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// Every value seen, keyed by topic below the prefix
+    pub fn raw(&self) -> &BTreeMap<String, String> {
+        &self.raw
+    }
+
+    /// The latest stats, with the message rates since the previous summary
+    pub fn summarise(&mut self, now: Instant) -> BrokerStats {
+        let mut stats = self.latest.clone();
+        if let Some((then, received, sent)) = self.previous {
+            let elapsed = now.duration_since(then).as_secs_f64();
+            let rate = |before: Option<u64>, after: Option<u64>| match (before, after) {
+                (Some(before), Some(after)) if elapsed > 0.0 => {
+                    Some(after.saturating_sub(before) as f64 / elapsed)
+                }
+                _ => None,
+            };
+            stats.received_per_sec = rate(received, stats.messages_received);
+            stats.sent_per_sec = rate(sent, stats.messages_sent);
+        }
+        self.previous = Some((now, stats.messages_received, stats.messages_sent));
+        stats
+    }
+}
+
+/// Subscribe to the broker's own stats (`$SYS/#` unless another prefix is given) and
+/// print a summary every interval, until interrupted or `count` summaries have been
+/// printed. Returns the last summary, or None if the broker published nothing below the
+/// prefix before the timeout, i.e. it probably does not expose its stats at all.
+pub fn sysmon(
+    options: &SysmonOptions,
+    tether_agent: &mut TetherAgent,
+) -> anyhow::Result<Option<BrokerStats>> {
+    info!("Tether Broker Stats Utility");
+
+    let prefix = options.prefix.trim_end_matches('/');
+    let mut input = PlugOptionsBuilder::create_input("sys")
+        .topic(Some(&format!("{}/#", prefix)))
+        .build(tether_agent)?;
+    info!("Subscribed to topic \"{}\" ...", input.topic());
+
+    let mut stats = SysStats::default();
+    let mut connection_watch = ConnectionWatch::new(tether_agent);
+    let started = Instant::now();
+    let mut last_summary = Instant::now();
+    let mut summaries = 0;
+    let shutdown = ShutdownSignal::from_os();
+
+    loop {
+        if shutdown.is_requested() {
+            return Ok((!stats.is_empty()).then(|| stats.summarise(Instant::now())));
+        }
+        match connection_watch.poll(tether_agent) {
+            ConnectionChange::Lost => warn!("Connection lost; reconnecting..."),
+            ConnectionChange::Restored => {
+                info!("Reconnected; subscribing again to \"{}\"", input.topic());
+                if let Err(e) = resubscribe(tether_agent, &mut input) {
+                    error!("Failed to subscribe again: {}", e);
+                }
+            }
+            ConnectionChange::Unchanged => {}
+        }
+
+        let mut did_work = false;
+        while let Some((topic, payload)) = tether_agent.check_messages() {
+            did_work = true;
+            let full_topic = topic.full_topic_string();
+            let Some(path) = full_topic
+                .strip_prefix(prefix)
+                .and_then(|p| p.strip_prefix('/'))
+            else {
+                continue;
+            };
+            if !stats.update(path, &payload) {
+                debug!("Ignoring unrecognised broker stat \"{}\"", path);
+            }
+        }
+
+        if stats.is_empty() {
+            if started.elapsed() > Duration::from_millis(options.timeout) {
+                warn!(
+                    "Nothing received on \"{}\" after {}ms; this broker probably does not publish its stats there (or this client is not allowed to subscribe)",
+                    input.topic(),
+                    options.timeout
+                );
+                return Ok(None);
+            }
+        } else if last_summary.elapsed() >= Duration::from_millis(options.interval) {
+            last_summary = Instant::now();
+            let summary = stats.summarise(last_summary);
+            if options.json {
+                println!("{}", serde_json::to_string(&summary)?);
+            } else {
+                info!("Broker stats ------------------------------\n{}\n", summary);
+            }
+            summaries += 1;
+            if options.count.is_some_and(|count| summaries >= count) {
+                return Ok(Some(summary));
+            }
+        }
+
+        if !did_work {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use tether_agent::TetherAgentOptionsBuilder;
+
+    use super::{parse_uptime, sysmon, SysStats, SysmonOptions};
+
+    #[test]
+    fn uptime_formats() {
+        assert_eq!(parse_uptime("1234 seconds"), Some(1234));
+        assert_eq!(parse_uptime("1234"), Some(1234));
+        assert_eq!(
+            parse_uptime("1 days, 2 hours, 3 minutes, 4 seconds"),
+            Some(86400 + 7200 + 180 + 4)
+        );
+        assert_eq!(parse_uptime("1 hour"), Some(3600));
+        assert_eq!(parse_uptime("forever"), None);
+        assert_eq!(parse_uptime(""), None);
+    }
+
+    #[test]
+    fn mosquitto_and_emqx_metrics() {
+        let mut stats = SysStats::default();
+        assert!(stats.is_empty());
+        assert!(stats.update("broker/version", b"mosquitto version 2.0.18"));
+        assert!(stats.update("broker/uptime", b"60 seconds"));
+        assert!(stats.update("broker/clients/connected", b"3"));
+        assert!(stats.update("broker/messages/received", b"100"));
+        assert!(!stats.update("broker/heap/current", b"1024"));
+        assert_eq!(stats.raw().get("broker/heap/current").unwrap(), "1024");
+
+        let start = Instant::now();
+        let first = stats.summarise(start);
+        assert_eq!(first.version.as_deref(), Some("mosquitto version 2.0.18"));
+        assert_eq!(first.uptime, Some(60));
+        assert_eq!(first.clients_connected, Some(3));
+        assert_eq!(first.messages_received, Some(100));
+        assert_eq!(first.messages_sent, None);
+        assert_eq!(first.received_per_sec, None);
+
+        // Same metrics in the EMQX layout
+        assert!(stats.update("brokers/emqx@127.0.0.1/stats/connections/count", b"4"));
+        assert!(stats.update("brokers/emqx@127.0.0.1/metrics/messages/received", b"120"));
+        let second = stats.summarise(start + Duration::from_secs(2));
+        assert_eq!(second.clients_connected, Some(4));
+        assert_eq!(second.received_per_sec, Some(10.0));
+        assert_eq!(second.sent_per_sec, None);
+
+        assert!(stats.update("broker/clients/connected", b"lots"));
+        assert_eq!(stats.summarise(start).clients_connected, None);
+    }
+
+    fn unique_prefix() -> String {
+        let unique = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("tester/{}/sys", unique)
+    }
+
+    #[test]
+    fn stats_below_custom_prefix() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let prefix = unique_prefix();
+        for (path, value) in [
+            ("broker/version", "test broker"),
+            ("broker/clients/connected", "7"),
+        ] {
+            tether_agent
+                .publish_raw(
+                    &format!("{}/{}", prefix, path),
+                    value.as_bytes(),
+                    None,
+                    Some(true),
+                )
+                .unwrap();
+        }
+
+        let options = SysmonOptions {
+            prefix: prefix.clone(),
+            timeout: 2000,
+            interval: 100,
+            json: false,
+            count: Some(1),
+        };
+        let stats = sysmon(&options, &mut tether_agent)
+            .unwrap()
+            .expect("retained stats should have been received");
+        assert_eq!(stats.version.as_deref(), Some("test broker"));
+        assert_eq!(stats.clients_connected, Some(7));
+
+        for path in ["broker/version", "broker/clients/connected"] {
+            tether_agent
+                .publish_raw(&format!("{}/{}", prefix, path), &[], None, Some(true))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn no_stats_published() {
+        let mut tether_agent = TetherAgentOptionsBuilder::new("tester")
+            .build()
+            .expect("sorry, these tests require working localhost Broker");
+        let options = SysmonOptions {
+            prefix: unique_prefix(),
+            timeout: 200,
+            interval: 100,
+            json: false,
+            count: None,
+        };
+        assert_eq!(sysmon(&options, &mut tether_agent).unwrap(), None);
+    }
+}
@@ -1,14 +1,24 @@
 use circular_buffer::CircularBuffer;
+use log::{debug, info, warn};
 use tether_agent::{three_part_topic::TetherOrCustomTopic, PlugOptionsBuilder, TetherAgent};
 
-use crate::tether_topics::{agent_tree::AgentTree, sampler::Sampler};
+use crate::tether_receive::DecodeStats;
+use crate::tether_topics::{
+    agent_tree::AgentTree,
+    presence::{is_sys_topic, parse_client_event, ClientEvent, ClientEventKind},
+    sampler::Sampler,
+    tracker::TopicTracker,
+};
 use std::{
+    collections::HashMap,
     fmt,
+    sync::Arc,
     time::{Duration, SystemTime},
 };
 
 use super::{parse_agent_id, parse_agent_role, parse_plug_name, TopicOptions};
 pub const MONITOR_LOG_LENGTH: usize = 256;
+pub const CLIENT_EVENTS_LOG_LENGTH: usize = 64;
 
 /// Topic, Payload as JSON
 type MessageLogEntry = (String, String);
@@ -23,6 +33,14 @@ pub struct Insights {
     log_start: Option<SystemTime>,
     message_log: CircularBuffer<MONITOR_LOG_LENGTH, MessageLogEntry>,
     sampler: Sampler,
+    /// Last time a message was seen from each Agent, keyed by "role/id"
+    agents_last_seen: HashMap<String, SystemTime>,
+    stale_timeout: Duration,
+    stale_agents: Vec<String>,
+    connected_clients: Vec<String>,
+    client_events: CircularBuffer<CLIENT_EVENTS_LOG_LENGTH, ClientEvent>,
+    decode_stats: DecodeStats,
+    tracker: Arc<TopicTracker>,
 }
 
 impl fmt::Display for Insights {
@@ -34,7 +52,50 @@ impl fmt::Display for Insights {
 
         let trees_formatted = self.trees.iter().map(|x| x.to_string()).collect::<String>();
 
-        write!(f, "{}{}{}{}{}", topics, roles, ids, plugs, trees_formatted)
+        let stale = if self.stale_agents.is_empty() {
+            String::from("")
+        } else {
+            format!(
+                "x{} Stale Agents: {:?} \n",
+                self.stale_agents.len(),
+                self.stale_agents
+            )
+        };
+        let clients = if self.client_events.is_empty() {
+            String::from("")
+        } else {
+            format!(
+                "x{} Connected Clients: {:?} \n",
+                self.connected_clients.len(),
+                self.connected_clients
+            )
+        };
+
+        let decode_failures = if self.decode_stats.total_failures() == 0 {
+            String::from("")
+        } else {
+            format!(
+                "x{} Decode Failures: {:?} \n",
+                self.decode_stats.total_failures(),
+                self.decode_stats.failures()
+            )
+        };
+
+        let trailing = if self.decode_stats.total_trailing() == 0 {
+            String::from("")
+        } else {
+            format!(
+                "x{} Decoded With Extra Data: {:?} \n",
+                self.decode_stats.total_trailing(),
+                self.decode_stats.trailing()
+            )
+        };
+
+        write!(
+            f,
+            "{}{}{}{}{}{}{}{}{}",
+            topics, roles, ids, plugs, stale, clients, decode_failures, trailing, trees_formatted
+        )
     }
 }
 
@@ -48,6 +109,14 @@ impl Insights {
             .build(tether_agent)
             .expect("failed to connect Tether");
 
+        if options.sys_enable {
+            info!("Subscribing to $SYS topics; client events will only be seen if the broker publishes them");
+            let _sys_plug = PlugOptionsBuilder::create_input("sys")
+                .topic(Some("$SYS/#"))
+                .build(tether_agent)
+                .expect("failed to subscribe to $SYS topics");
+        }
+
         Insights {
             topics: Vec::new(),
             roles: Vec::new(),
@@ -58,6 +127,13 @@ impl Insights {
             message_count: 0,
             log_start: None,
             sampler: Sampler::new(options.sampler_interval),
+            agents_last_seen: HashMap::new(),
+            stale_timeout: Duration::from_secs(options.stale_timeout),
+            stale_agents: Vec::new(),
+            connected_clients: Vec::new(),
+            client_events: CircularBuffer::new(),
+            decode_stats: DecodeStats::default(),
+            tracker: Arc::new(TopicTracker::new()),
         }
     }
 
@@ -70,21 +146,34 @@ impl Insights {
     }
 
     pub fn update(&mut self, topic: &TetherOrCustomTopic, payload: Vec<u8>) -> bool {
+        let full_topic_string = topic.full_topic_string();
+
+        if is_sys_topic(&full_topic_string) {
+            return self.update_sys(&full_topic_string);
+        }
+
         self.message_count += 1;
 
+        if let TetherOrCustomTopic::Tether(tpt) = topic {
+            self.agents_last_seen
+                .insert(format!("{}/{}", tpt.role(), tpt.id()), SystemTime::now());
+            if let Some(collision) = self.tracker.record_message(tpt, &payload) {
+                warn!("{}", collision);
+            }
+        }
+
         if self.log_start.is_none() {
             self.log_start = Some(SystemTime::now());
         }
 
-        let full_topic_string = topic.full_topic_string();
-
         if payload.is_empty() {
             self.message_log
                 .push_back((String::from(&full_topic_string), "[EMPTY_MESSAGE]".into()));
         } else {
-            let value: rmpv::Value =
-                rmp_serde::from_slice(&payload).expect("failed to decode msgpack");
-            let json = serde_json::to_string(&value).expect("failed to stringify JSON");
+            let json = self
+                .decode_stats
+                .decode(&full_topic_string, &payload)
+                .unwrap_or("[INVALID_MESSAGE]".into());
             self.message_log
                 .push_back((String::from(&full_topic_string), json));
         }
@@ -122,7 +211,84 @@ impl Insights {
                 .collect::<Vec<AgentTree>>();
         }
 
-        did_change
+        let stale_did_change = self.check_stale();
+
+        did_change || stale_did_change
+    }
+
+    /// Messages on `$SYS` topics are not counted or logged like normal messages;
+    /// only client events (if the broker reports these) are kept.
+    fn update_sys(&mut self, topic: &str) -> bool {
+        match parse_client_event(topic) {
+            Some(event) => {
+                debug!("Client event: {}", event);
+                let did_change = match event.kind {
+                    ClientEventKind::Connected => {
+                        add_if_unique(&event.client_id, &mut self.connected_clients)
+                    }
+                    ClientEventKind::Disconnected => {
+                        let count = self.connected_clients.len();
+                        self.connected_clients.retain(|c| c != &event.client_id);
+                        count != self.connected_clients.len()
+                    }
+                    ClientEventKind::Subscribed | ClientEventKind::Unsubscribed => false,
+                };
+                self.client_events.push_back(event);
+                did_change
+            }
+            None => false,
+        }
+    }
+
+    /// Re-evaluate which Agents have not sent anything within the stale timeout;
+    /// returns true if the list of stale Agents changed.
+    pub fn check_stale(&mut self) -> bool {
+        let mut stale: Vec<String> = self
+            .agents_last_seen
+            .iter()
+            .filter(|(_, last_seen)| {
+                last_seen.elapsed().unwrap_or(Duration::ZERO) > self.stale_timeout
+            })
+            .map(|(agent, _)| String::from(agent))
+            .collect();
+        stale.sort();
+        if stale != self.stale_agents {
+            self.stale_agents = stale;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Agents ("role/id") which have not sent any messages within the stale timeout
+    pub fn stale_agents(&self) -> &[String] {
+        &self.stale_agents
+    }
+
+    pub fn agent_last_seen(&self, role: &str, id: &str) -> Option<SystemTime> {
+        self.agents_last_seen
+            .get(&format!("{}/{}", role, id))
+            .copied()
+    }
+
+    /// Client IDs currently connected, according to `$SYS` client events (if enabled)
+    pub fn connected_clients(&self) -> &[String] {
+        &self.connected_clients
+    }
+
+    pub fn client_events(&self) -> &CircularBuffer<CLIENT_EVENTS_LOG_LENGTH, ClientEvent> {
+        &self.client_events
+    }
+
+    /// Last seen time and message count per Plug; clone the `Arc` to query it
+    /// from another thread
+    pub fn tracker(&self) -> &Arc<TopicTracker> {
+        &self.tracker
+    }
+
+    /// Counts of messages which could not be decoded, per topic
+    pub fn decode_stats(&self) -> &DecodeStats {
+        &self.decode_stats
     }
 
     pub fn topics(&self) -> &[String] {
@@ -2,7 +2,9 @@ use clap::Args;
 
 pub mod agent_tree;
 pub mod insights;
+pub mod presence;
 pub mod sampler;
+pub mod tracker;
 
 #[derive(Args, Clone)]
 pub struct TopicOptions {
@@ -17,6 +19,16 @@ pub struct TopicOptions {
     /// some terminals might break
     #[arg(long = "graph.enable")]
     pub graph_enable: bool,
+
+    /// Flag to also subscribe to the broker's `$SYS` topics, in order to detect
+    /// clients connecting and disconnecting; this depends on the broker
+    #[arg(long = "sys.enable")]
+    pub sys_enable: bool,
+
+    /// Time (in seconds) after which an Agent that has not sent any messages
+    /// is marked as stale
+    #[arg(long = "stale.timeout", default_value_t = 10)]
+    pub stale_timeout: u64,
 }
 
 impl Default for TopicOptions {
@@ -25,6 +37,8 @@ impl Default for TopicOptions {
             topic: "#".into(),
             sampler_interval: 1000,
             graph_enable: false,
+            sys_enable: false,
+            stale_timeout: 10,
         }
     }
 }
@@ -0,0 +1,78 @@
+use std::{fmt, time::SystemTime};
+
+/// Prefix for broker system topics; these are not matched by a plain `#` subscription
+pub const SYS_TOPIC_PREFIX: &str = "$SYS";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEventKind {
+    Connected,
+    Disconnected,
+    Subscribed,
+    Unsubscribed,
+}
+
+/// A client presence event, as reported by the broker on a `$SYS` topic
+#[derive(Debug, Clone)]
+pub struct ClientEvent {
+    pub client_id: String,
+    pub kind: ClientEventKind,
+    pub time: SystemTime,
+}
+
+impl fmt::Display for ClientEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?}", self.client_id, self.kind)
+    }
+}
+
+pub fn is_sys_topic(topic: &str) -> bool {
+    topic.starts_with(SYS_TOPIC_PREFIX)
+}
+
+/// Parse a client event from a `$SYS` topic of the form
+/// `$SYS/brokers/{node}/clients/{clientId}/{event}`, which is how e.g. EMQX
+/// reports clients connecting, disconnecting, subscribing and unsubscribing.
+///
+/// Other brokers (e.g. Mosquitto) only publish aggregate counts on `$SYS`, or
+/// nothing at all, in which case this returns None.
+pub fn parse_client_event(topic: &str) -> Option<ClientEvent> {
+    if !is_sys_topic(topic) {
+        return None;
+    }
+    let parts: Vec<&str> = topic.split('/').collect();
+    let clients_index = parts.iter().position(|p| *p == "clients")?;
+    let client_id = parts.get(clients_index + 1)?;
+    let kind = match *parts.get(clients_index + 2)? {
+        "connected" => ClientEventKind::Connected,
+        "disconnected" => ClientEventKind::Disconnected,
+        "subscribed" => ClientEventKind::Subscribed,
+        "unsubscribed" => ClientEventKind::Unsubscribed,
+        _ => return None,
+    };
+    Some(ClientEvent {
+        client_id: String::from(*client_id),
+        kind,
+        time: SystemTime::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_client_event, ClientEventKind};
+
+    #[test]
+    fn client_events() {
+        let event = parse_client_event("$SYS/brokers/emqx@127.0.0.1/clients/abc-123/connected")
+            .expect("should parse");
+        assert_eq!(event.client_id, "abc-123");
+        assert_eq!(event.kind, ClientEventKind::Connected);
+
+        let event = parse_client_event("$SYS/brokers/node/clients/abc-123/disconnected")
+            .expect("should parse");
+        assert_eq!(event.kind, ClientEventKind::Disconnected);
+
+        assert!(parse_client_event("$SYS/broker/clients/connected").is_none());
+        assert!(parse_client_event("$SYS/broker/uptime").is_none());
+        assert!(parse_client_event("role/id/clients").is_none());
+    }
+}
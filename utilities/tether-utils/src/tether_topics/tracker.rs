@@ -0,0 +1,388 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use rmpv::Value;
+
+use tether_agent::three_part_topic::{topic_filter_matches, ThreePartTopic};
+
+/// Role, ID, Plug name
+pub type PlugKey = (String, String, String);
+
+/// How recently, and how often, messages were seen on a Plug
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlugActivity {
+    pub last_seen: Instant,
+    pub message_count: u64,
+}
+
+/// The same topic carrying payloads of different shapes, which usually means that two
+/// Agents with the same role and ID are publishing on the same Plug name by mistake
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicCollision {
+    pub plug: PlugKey,
+    /// Every payload signature seen on the topic so far, sorted
+    pub signatures: Vec<String>,
+}
+
+impl std::fmt::Display for TopicCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (role, id, plug_name) = &self.plug;
+        write!(
+            f,
+            "Topic \"{}/{}/{}\" carries differently-shaped payloads ({}); are several Agents publishing on it?",
+            role,
+            id,
+            plug_name,
+            self.signatures.join(", ")
+        )
+    }
+}
+
+/// A rough description of the shape of a (MessagePack) payload: its type and, for a map,
+/// its keys, e.g. `map{humidity,temperature}`, or for an array, the types of its elements,
+/// e.g. `array[number]`. Array lengths are left out, and integers and floats are all just
+/// numbers, so that the same kind of value always has the same signature. A single
+/// publisher on a Plug normally always sends payloads of the same shape.
+pub fn payload_signature(payload: &[u8]) -> String {
+    match rmpv::decode::read_value(&mut &payload[..]) {
+        Ok(value) => value_signature(&value),
+        Err(_) => "invalid".into(),
+    }
+}
+
+fn value_signature(value: &Value) -> String {
+    match value {
+        Value::Map(entries) => {
+            let keys: BTreeSet<String> = entries
+                .iter()
+                .map(|(k, _)| k.as_str().map(String::from).unwrap_or(k.to_string()))
+                .collect();
+            format!("map{{{}}}", keys.into_iter().collect::<Vec<_>>().join(","))
+        }
+        Value::Array(items) => {
+            let kinds: BTreeSet<String> = items.iter().map(value_signature).collect();
+            format!("array[{}]", kinds.into_iter().collect::<Vec<_>>().join("|"))
+        }
+        Value::Nil => "nil".into(),
+        Value::Boolean(_) => "bool".into(),
+        Value::Integer(_) | Value::F32(_) | Value::F64(_) => "number".into(),
+        Value::String(_) => "string".into(),
+        Value::Binary(_) => "binary".into(),
+        Value::Ext(kind, _) => format!("ext({})", kind),
+    }
+}
+
+#[derive(Debug)]
+struct PlugState {
+    activity: PlugActivity,
+    signatures: BTreeSet<String>,
+}
+
+/// Keeps track of when each discovered Plug (by role, ID and Plug name) last sent a
+/// message, so that it is possible to ask which ones have gone quiet; and of the shapes
+/// of the payloads seen on each, to detect publishers colliding on the same topic.
+///
+/// All methods take `&self`, so a single tracker can be shared (e.g. in an `Arc`)
+/// between a background receive thread and whatever is reporting on it. The `_at`
+/// variants take the current time explicitly, which is mainly useful for testing.
+#[derive(Debug, Default)]
+pub struct TopicTracker {
+    plugs: Mutex<HashMap<PlugKey, PlugState>>,
+}
+
+impl TopicTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a message seen on this topic, now
+    pub fn record(&self, topic: &ThreePartTopic) {
+        self.record_at(topic, Instant::now());
+    }
+
+    pub fn record_at(&self, topic: &ThreePartTopic, now: Instant) {
+        drop(self.record_state(topic, now));
+    }
+
+    /// Record a message seen on this topic, now, also noting the shape of its payload.
+    /// Returns a collision the first time a payload of a different shape is seen on a
+    /// topic; empty payloads (e.g. clearing a retained message) are not considered.
+    pub fn record_message(&self, topic: &ThreePartTopic, payload: &[u8]) -> Option<TopicCollision> {
+        self.record_message_at(topic, payload, Instant::now())
+    }
+
+    pub fn record_message_at(
+        &self,
+        topic: &ThreePartTopic,
+        payload: &[u8],
+        now: Instant,
+    ) -> Option<TopicCollision> {
+        let (key, mut plugs) = self.record_state(topic, now);
+        let state = plugs.get_mut(&key).expect("state was just recorded");
+        if payload.is_empty() || !state.signatures.insert(payload_signature(payload)) {
+            return None;
+        }
+        (state.signatures.len() > 1).then(|| TopicCollision {
+            plug: key,
+            signatures: state.signatures.iter().cloned().collect(),
+        })
+    }
+
+    fn record_state(
+        &self,
+        topic: &ThreePartTopic,
+        now: Instant,
+    ) -> (PlugKey, MutexGuard<'_, HashMap<PlugKey, PlugState>>) {
+        let key = (
+            String::from(topic.role()),
+            String::from(topic.id()),
+            String::from(topic.plug_name()),
+        );
+        let mut plugs = self.plugs.lock().expect("failed to lock mutex");
+        let state = plugs.entry(key.clone()).or_insert(PlugState {
+            activity: PlugActivity {
+                last_seen: now,
+                message_count: 0,
+            },
+            signatures: BTreeSet::new(),
+        });
+        state.activity.last_seen = now;
+        state.activity.message_count += 1;
+        (key, plugs)
+    }
+
+    pub fn get(&self, role: &str, id: &str, plug_name: &str) -> Option<PlugActivity> {
+        self.plugs
+            .lock()
+            .expect("failed to lock mutex")
+            .get(&(role.into(), id.into(), plug_name.into()))
+            .map(|state| state.activity)
+    }
+
+    /// All topics on which payloads of more than one shape have been seen, sorted
+    pub fn collisions(&self) -> Vec<TopicCollision> {
+        let mut collisions: Vec<TopicCollision> = self
+            .plugs
+            .lock()
+            .expect("failed to lock mutex")
+            .iter()
+            .filter(|(_, state)| state.signatures.len() > 1)
+            .map(|(key, state)| TopicCollision {
+                plug: key.clone(),
+                signatures: state.signatures.iter().cloned().collect(),
+            })
+            .collect();
+        collisions.sort_by(|a, b| a.plug.cmp(&b.plug));
+        collisions
+    }
+
+    /// How many distinct Plugs have been seen so far
+    pub fn len(&self) -> usize {
+        self.plugs.lock().expect("failed to lock mutex").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The known topics which a subscription with this filter (which may include `+`
+    /// and `#` wildcards) would currently receive, sorted; useful to preview a filter
+    pub fn matching(&self, filter: &str) -> Vec<String> {
+        let mut topics: Vec<String> = self
+            .plugs
+            .lock()
+            .expect("failed to lock mutex")
+            .keys()
+            .map(|(role, id, plug_name)| format!("{}/{}/{}", role, id, plug_name))
+            .filter(|topic| topic_filter_matches(filter, topic))
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    /// Plugs which have not sent anything within the given window, sorted by key
+    pub fn stale(&self, window: Duration) -> Vec<(PlugKey, PlugActivity)> {
+        self.stale_at(window, Instant::now())
+    }
+
+    pub fn stale_at(&self, window: Duration, now: Instant) -> Vec<(PlugKey, PlugActivity)> {
+        let mut stale: Vec<(PlugKey, PlugActivity)> = self
+            .plugs
+            .lock()
+            .expect("failed to lock mutex")
+            .iter()
+            .filter(|(_, state)| now.saturating_duration_since(state.activity.last_seen) > window)
+            .map(|(key, state)| (key.clone(), state.activity))
+            .collect();
+        stale.sort_by(|a, b| a.0.cmp(&b.0));
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tether_agent::three_part_topic::ThreePartTopic;
+
+    use serde::Serialize;
+
+    use super::{payload_signature, TopicTracker};
+
+    #[test]
+    fn staleness() {
+        let tracker = TopicTracker::new();
+        let start = Instant::now();
+        let sensor = ThreePartTopic::try_from("sensor/a/temperature").unwrap();
+        let other = ThreePartTopic::try_from("sensor/b/temperature").unwrap();
+
+        tracker.record_at(&sensor, start);
+        tracker.record_at(&other, start);
+        tracker.record_at(&other, start + Duration::from_secs(8));
+
+        let window = Duration::from_secs(5);
+        assert!(tracker
+            .stale_at(window, start + Duration::from_secs(4))
+            .is_empty());
+
+        let stale = tracker.stale_at(window, start + Duration::from_secs(10));
+        assert_eq!(stale.len(), 1);
+        assert_eq!(
+            stale[0].0,
+            ("sensor".into(), "a".into(), "temperature".into())
+        );
+
+        assert_eq!(
+            tracker
+                .stale_at(window, start + Duration::from_secs(20))
+                .len(),
+            2
+        );
+
+        let activity = tracker.get("sensor", "b", "temperature").unwrap();
+        assert_eq!(activity.message_count, 2);
+        assert_eq!(activity.last_seen, start + Duration::from_secs(8));
+    }
+
+    #[test]
+    fn matching_filters() {
+        let tracker = TopicTracker::new();
+        for topic in [
+            "sensor/a/temperature",
+            "sensor/b/temperature",
+            "sensor/b/humidity",
+            "brain/any/temperatureAlert",
+        ] {
+            tracker.record(&ThreePartTopic::try_from(topic).unwrap());
+        }
+
+        assert_eq!(
+            tracker.matching("+/+/temperature"),
+            vec!["sensor/a/temperature", "sensor/b/temperature"]
+        );
+        assert_eq!(
+            tracker.matching("sensor/b/#"),
+            vec!["sensor/b/humidity", "sensor/b/temperature"]
+        );
+        assert_eq!(tracker.matching("#").len(), 4);
+        assert_eq!(
+            tracker.matching("brain/+/temperatureAlert"),
+            vec!["brain/any/temperatureAlert"]
+        );
+        assert!(tracker.matching("+/+/pressure").is_empty());
+        assert!(tracker.matching("sensor/+").is_empty());
+    }
+
+    #[test]
+    fn colliding_publishers() {
+        #[derive(Serialize)]
+        struct Reading {
+            temperature: f32,
+            humidity: f32,
+        }
+
+        let tracker = TopicTracker::new();
+        let topic = ThreePartTopic::try_from("sensor/any/reading").unwrap();
+        let other = ThreePartTopic::try_from("sensor/other/reading").unwrap();
+        let reading = rmp_serde::to_vec_named(&Reading {
+            temperature: 21.5,
+            humidity: 0.4,
+        })
+        .unwrap();
+        assert_eq!(payload_signature(&reading), "map{humidity,temperature}");
+        // Neither array lengths nor the kind of number make a different shape
+        assert_eq!(
+            payload_signature(&rmp_serde::to_vec(&[1.5, 2.0]).unwrap()),
+            payload_signature(&rmp_serde::to_vec(&[1, 2, 3]).unwrap())
+        );
+        assert_eq!(
+            payload_signature(&rmp_serde::to_vec(&(1, "one")).unwrap()),
+            "array[number|string]"
+        );
+
+        // One publisher, always the same shape (and clearing the retained value)
+        for _ in 0..3 {
+            assert!(tracker.record_message(&topic, &reading).is_none());
+        }
+        assert!(tracker.record_message(&topic, &[]).is_none());
+        assert!(tracker
+            .record_message(&other, &rmp_serde::to_vec(&42).unwrap())
+            .is_none());
+        assert!(tracker.collisions().is_empty());
+
+        // A second, misconfigured, publisher on the same topic
+        let collision = tracker
+            .record_message(&topic, &rmp_serde::to_vec(&[1, 2, 3]).unwrap())
+            .expect("should detect collision");
+        assert_eq!(
+            collision.plug,
+            ("sensor".into(), "any".into(), "reading".into())
+        );
+        assert_eq!(
+            collision.signatures,
+            vec!["array[number]", "map{humidity,temperature}"]
+        );
+        // Only reported when first seen
+        assert!(tracker.record_message(&topic, &reading).is_none());
+        assert!(tracker
+            .record_message(&topic, &rmp_serde::to_vec(&[0.5]).unwrap())
+            .is_none());
+        assert_eq!(tracker.collisions(), vec![collision]);
+        assert_eq!(
+            tracker
+                .get("sensor", "any", "reading")
+                .unwrap()
+                .message_count,
+            7
+        );
+    }
+
+    #[test]
+    fn record_from_another_thread() {
+        let tracker = Arc::new(TopicTracker::new());
+        let background = Arc::clone(&tracker);
+        thread::spawn(move || {
+            let topic = ThreePartTopic::try_from("sensor/a/temperature").unwrap();
+            for _ in 0..10 {
+                background.record(&topic);
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tracker.len(), 1);
+        assert_eq!(
+            tracker
+                .get("sensor", "a", "temperature")
+                .unwrap()
+                .message_count,
+            10
+        );
+    }
+}